@@ -0,0 +1,30 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: data/shaders/md3.frag
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      The fragment shader for MD3 models,
+      lit as MD5 models are.
+*/
+
+#version 330
+
+uniform sampler2D tex0;
+
+/* In model space, which MD3 has as Z up. */
+uniform vec3 light_dir = vec3(-0.3f, 0.4f, -0.866f);
+uniform float ambient = 0.3f;
+
+in vec2 trans_coord;
+in vec3 trans_normal;
+out vec4 out_color;
+
+void main()
+{
+  float diffuse = max(dot(normalize(trans_normal), -normalize(light_dir)), 0.0f);
+  vec4 color = texture(tex0, trans_coord);
+  out_color = vec4(color.rgb * (ambient + ((1.0f - ambient) * diffuse)), color.a);
+}
@@ -0,0 +1,33 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: data/shaders/md3.vert
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      The vertex shader for MD3 models,
+      which blends between two frames.
+*/
+
+#version 330
+
+uniform mat4 proj; 
+uniform mat4 world; 
+uniform float blend = 0.0f;
+
+layout (location = 0) in vec3 in_position0;
+layout (location = 1) in vec3 in_normal0;
+layout (location = 2) in vec3 in_position1;
+layout (location = 3) in vec3 in_normal1;
+layout (location = 4) in vec2 in_coord;
+
+out vec2 trans_coord; 
+out vec3 trans_normal;
+
+void main() 
+{ 
+  gl_Position = proj * world * vec4(mix(in_position0, in_position1, blend), 1.0f);
+  trans_coord = in_coord;
+  trans_normal = mix(in_normal0, in_normal1, blend);
+}
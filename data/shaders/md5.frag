@@ -6,18 +6,39 @@
     File: data/shaders/md5.frag
     Author: Jesse 'Jeaye' Wilkerson
     Description:
-      The fragment shader for MD5 models.
+      The fragment shader for MD5 models,
+      with diffuse lighting from a single
+      directional light.
 */
 
 #version 330
 
 uniform sampler2D tex0;
+uniform sampler2D normal_map; /* In tangent space. */
+uniform int normal_mapped = 0;
+
+/* In model space, which MD5 has as Z up. */
+uniform vec3 light_dir = vec3(-0.3f, 0.4f, -0.866f);
+uniform float ambient = 0.3f;
 
 in vec2 trans_coord;
+in vec4 trans_color;
+in vec3 trans_normal;
+in vec4 trans_tangent;
 out vec4 out_color;
 
 void main()
 {
-  out_color = texture(tex0, trans_coord); 
-}
+  vec3 normal = normalize(trans_normal);
+  if(normal_mapped != 0)
+  {
+    vec3 tangent = normalize(trans_tangent.xyz - (normal * dot(normal, trans_tangent.xyz)));
+    vec3 bitangent = cross(normal, tangent) * trans_tangent.w;
+    vec3 mapped = (texture(normal_map, trans_coord).xyz * 2.0f) - 1.0f;
+    normal = normalize(mat3(tangent, bitangent, normal) * mapped);
+  }
 
+  float diffuse = max(dot(normal, -normalize(light_dir)), 0.0f);
+  vec4 color = texture(tex0, trans_coord) * trans_color;
+  out_color = vec4(color.rgb * (ambient + ((1.0f - ambient) * diffuse)), color.a);
+}
@@ -6,7 +6,9 @@
     File: data/shaders/md5.vert
     Author: Jesse 'Jeaye' Wilkerson
     Description:
-      The vertex shader for MD5 models.
+      The vertex shader for MD5 models,
+      which skins each vertex to the
+      current pose.
 */
 
 #version 330
@@ -14,14 +16,49 @@
 uniform mat4 proj; 
 uniform mat4 world; 
 
-layout (location = 0) in vec4 in_position; 
-layout (location = 1) in vec2 in_coord;
+/* Three texels per joint: its position, its orientation, then
+ * the change in its orientation from the bind pose. */
+uniform samplerBuffer joints;
+
+/* Up to four weights, each a joint local position with the bias in w. */
+layout (location = 0) in vec4 in_weights[4];
+layout (location = 4) in vec4 in_joints;
+
+/* In the bind pose. The tangent's w is the bitangent's handedness. */
+layout (location = 5) in vec4 in_normal;
+layout (location = 6) in vec4 in_tangent;
+layout (location = 7) in vec2 in_coord;
+layout (location = 8) in vec4 in_color;
 
 out vec2 trans_coord; 
+out vec4 trans_color;
+out vec3 trans_normal;
+out vec4 trans_tangent;
+
+vec3 rotate(vec4 q, vec3 v)
+{ return v + (2.0f * cross(q.xyz, cross(q.xyz, v) + (q.w * v))); }
 
 void main() 
 { 
-  gl_Position = proj * world * in_position;
+  vec3 position = vec3(0.0f);
+  vec3 normal = vec3(0.0f);
+  vec3 tangent = vec3(0.0f);
+  for(int i = 0; i < 4; ++i)
+  {
+    int joint = int(in_joints[i]) * 3;
+    vec3 joint_position = texelFetch(joints, joint).xyz;
+    vec4 joint_orientation = texelFetch(joints, joint + 1);
+    vec4 joint_delta = texelFetch(joints, joint + 2);
+    float bias = in_weights[i].w;
+
+    position += (joint_position + rotate(joint_orientation, in_weights[i].xyz)) * bias;
+    normal += rotate(joint_delta, in_normal.xyz) * bias;
+    tangent += rotate(joint_delta, in_tangent.xyz) * bias;
+  }
+
+  gl_Position = proj * world * vec4(position, 1.0f);
   trans_coord = in_coord;
+  trans_color = in_color;
+  trans_normal = normal;
+  trans_tangent = vec4(tangent, in_tangent.w);
 }
-
@@ -12,10 +12,11 @@
 #version 330
 
 in vec4 trans_color;
+flat in float trans_occlusion;
 out vec4 out_color;
 
 void main() 
 {
-  out_color = trans_color;
+  out_color = vec4(trans_color.rgb * trans_occlusion, trans_color.a);
 }
 
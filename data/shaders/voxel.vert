@@ -15,6 +15,11 @@ uniform mat4x4 proj;
 uniform mat4x4 world;
 uniform float voxel_size = 1.0f;
 uniform samplerBuffer offsets;
+uniform samplerBuffer occlusion; /* Six faces per voxel. */
+
+/* The cube's strip draws +z, +x, -z, -x, -y, +y, but
+ * occlusion is ordered +x, -x, +y, -y, +z, -z. */
+const int faces[6] = int[6](4, 0, 5, 1, 3, 2);
 
 /* Per vertex. */
 layout (location = 0) in vec4 in_position;
@@ -23,6 +28,7 @@ layout (location = 0) in vec4 in_position;
 layout (location = 1) in int in_index;
 
 out vec4 trans_color;
+flat out float trans_occlusion;
 
 void main()
 {
@@ -33,6 +39,7 @@ void main()
   position.w = 1.0f;
 
   trans_color = tex_color;
+  trans_occlusion = texelFetch(occlusion, (in_index * 6) + faces[gl_VertexID / 4]).r;
   gl_Position = proj * world * position;
 }
 
@@ -246,6 +246,10 @@ impl Camera
 
   pub fn reset(&mut self)
   { self.position = math::Vec3f::zero(); }
+
+  /* The world space frustum for this frame's view. */
+  pub fn get_frustum(&self) -> math::Frustum
+  { math::Frustum::new(&(self.view * self.projection)) }
 }
 
 impl ui::Input_Listener for Camera
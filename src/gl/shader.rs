@@ -162,6 +162,13 @@ impl Shader for Debug_Shader
   { if self.valid { shared::update_uniform_mat(location, mat); } }
 }
  
+#[cfg(debug_shader)]
+impl Drop for Debug_Shader
+{
+  fn drop(&self)
+  { shared::destroy(self.prog, self.vert_obj, self.frag_obj); }
+}
+ 
 #[cfg(release_shader)]
 struct Release_Shader
 {
@@ -217,6 +224,13 @@ impl Shader for Release_Shader
   { shared::update_uniform_mat(location, mat) }
 }
 
+#[cfg(release_shader)]
+impl Drop for Release_Shader
+{
+  fn drop(&self)
+  { shared::destroy(self.prog, self.vert_obj, self.frag_obj); }
+}
+
 mod shared
 {
   use gl2 = opengles::gl2;
@@ -304,6 +318,17 @@ mod shared
     true
   }
 
+  /* Deletes whichever of the objects were created. */
+  pub fn destroy(prog: gl2::GLuint, vert_obj: gl2::GLuint, frag_obj: gl2::GLuint)
+  {
+    if check!(gl2::is_program(prog))
+    { check!(gl2::delete_program(prog)); }
+    if vert_obj > 0 && check!(gl2::is_shader(vert_obj))
+    { check!(gl2::delete_shader(vert_obj)); }
+    if frag_obj > 0 && check!(gl2::is_shader(frag_obj))
+    { check!(gl2::delete_shader(frag_obj)); }
+  }
+
   pub fn bind(shader: &mut super::Shader_Builder)
   { check!(gl2::use_program(shader.prog)); }
 
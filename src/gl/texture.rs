@@ -98,6 +98,16 @@ impl Texture
 
   pub fn unbind(&self)
   { check!(gl2::bind_texture(gl2::TEXTURE_2D, 0)); }
+
+  /* Bytes used on the GPU; textures are stored as RGBA8. */
+  pub fn memory_usage(&self) -> uint
+  { (self.size.x * self.size.y * 4) as uint }
+}
+
+impl Drop for Texture
+{
+  fn drop(&self)
+  { check!(gl2::delete_textures(&[self.obj])); }
 }
  
 
@@ -14,7 +14,7 @@ extern mod opengles;
 extern mod glfw;
 extern mod stb_image;
 
-use std::{ libc, rt };
+use std::{ libc, rt, f32 };
 use gl2 = opengles::gl2;
 use util::Log;
 
@@ -46,6 +46,18 @@ pub mod state;
 #[path = "obj/md5/mod.rs"]
 pub mod md5;
 
+#[path = "obj/md3/mod.rs"]
+pub mod md3;
+
+#[path = "obj/iqm/mod.rs"]
+pub mod iqm;
+
+#[path = "obj/attachment.rs"]
+pub mod attachment;
+
+#[path = "res/mod.rs"]
+pub mod res;
+
 #[path = "util/mod.rs"]
 pub mod util;
 
@@ -86,6 +98,7 @@ fn main(argc: int, argv: **u8, crate_map: *u8) -> int
 
       /* Start the background GL thread. */
       let gl_worker_port = gl::Worker::initialize(worker_window);
+      let resources = res::Manager::initialize();
       let _ui_renderer = ui::Renderer::new(window);
 
       /* Create the console state. */
@@ -118,44 +131,58 @@ fn main(argc: int, argv: **u8, crate_map: *u8) -> int
         key_callback(window, key, action);
       }
 
-      let _model = md5::Model::new(~"data/models/bob/bob.md5mesh");
-      let _model_renderer = md5::Model_Renderer::new(&_model);
-
       /* Console functions. */
       state::Console::get().add_accessor("q3.version", |_|
       { fmt!("%s.%s", env!("VERSION"), env!("COMMIT")) });
       state::Console::get().add_function(~"quit", |_, _| -> (bool, ~str)
       { window.set_should_close(true); (true, ~"")});
+      state::Console::get().add_function(~"assets", |_, _| -> (bool, ~str)
+      { (true, resources.describe()) });
+      /* Size (in world units) of each voxel; takes effect on the next load. */
+      let voxel_size = @mut 0.45f32;
+      state::Console::get().add_accessor("map.voxel_size", |_|
+      { voxel_size.to_str() });
+      state::Console::get().add_mutator("map.voxel_size", |p, x|
+      {
+        match f32::from_str(x)
+        {
+          Some(size) if size > 0.0 => { *voxel_size = size; None }
+          _ => { Some(fmt!("Invalid value for %s (use a positive floating point number)", p)) }
+        }
+      });
       state::Console::get().add_function(~"load_map", |_, map_name| -> (bool, ~str)
       {
-        let mut err = ~"";
-
-        /* Try to load the new map. */
-        let game_state = state::Game::new(map_name);
-        if game_state.is_err()
-        { err = game_state.unwrap_err(); }
-        else
+        /* The map is loaded in the background; the loading
+         * state swaps in the game states once it's done. */
+        let loading_state = state::Loading::new(map_name, *voxel_size);
+        do state::Director::get_mut |director|
         {
-          let game_state = game_state.unwrap();
+          /* Only one map can be loading at a time. */
+          do director.remove_if |state|
+          { state.get_key() == (loading_state as @mut state::State).get_key() }
 
-          let game_renderer_state = state::Game_Renderer::new(game_state);
-          do state::Director::get_mut |director|
-          {
-            /* Remove any existing game states. */
-            do director.remove_if |state|
-            { state.get_key() == (game_state as @mut state::State).get_key() || 
-              state.get_key() == (game_renderer_state as @mut state::State).get_key() }
+          director.push(loading_state as @mut state::State);
+        }
 
-            director.unshift(game_state as @mut state::State);
-            director.unshift(game_renderer_state as @mut state::State);
+        (true, ~"Loading map: \\5" + map_name + "\\1")
+      });
+      state::Console::get().add_function(~"cancel_load", |_, _| -> (bool, ~str)
+      {
+        let mut cancelled = false;
+        do state::Director::get_mut |director|
+        {
+          do director.remove_if |state|
+          {
+            let loading = state.get_key() == "loading";
+            cancelled = cancelled || loading;
+            loading
           }
-          gl::Camera::get_active().reset(); /* Jump back to the origin. */
         }
 
-        if err.len() > 0
-        { (false, ~"\\2Error: \\1" + err) }
+        if cancelled
+        { (true, ~"Cancelled loading") }
         else
-        { (true, ~"Loaded map: \\5" + map_name + "\\1") }
+        { (false, ~"\\2Error: \\1No map is loading") }
       });
       /* Load the default map. */
       let (_loaded, msg) = state::Console::run_function(~"load_map q3ctf1");
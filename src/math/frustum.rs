@@ -0,0 +1,82 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: math/frustum.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A view frustum, described by six
+      inward facing planes, for culling.
+*/
+
+use math;
+
+pub struct Frustum
+{
+  /* Left, right, bottom, top, near, far. Each plane is
+   * (normal, distance); points in front are inside. */
+  planes: [math::Vec4f, ..6],
+}
+
+impl Frustum
+{
+  /* Extracts the planes from a combined clip matrix. Since
+   * `a * b` means b, then a, a camera's clip matrix is
+   * `view * projection`. */
+  pub fn new(clip: &math::Mat4x4) -> Frustum
+  {
+    /* Matrices are column major, so row r is data[c][r]. */
+    let row = |r: uint| -> math::Vec4f
+    { math::Vec4f::new(clip.data[0][r], clip.data[1][r], clip.data[2][r], clip.data[3][r]) };
+    let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+
+    let mut frustum = Frustum
+    {
+      planes: [ r3 + r0, r3 - r0,
+                r3 + r1, r3 - r1,
+                r3 + r2, r3 - r2 ]
+    };
+    for plane in frustum.planes.mut_iter()
+    { *plane = normalize_plane(plane); }
+
+    frustum
+  }
+
+  pub fn contains_point(&self, point: &math::Vec3f) -> bool
+  {
+    for plane in self.planes.iter()
+    {
+      if distance(plane, point) < 0.0
+      { return false; }
+    }
+    true
+  }
+
+  /* Conservative; a box that straddles a corner of the
+   * frustum may be reported as intersecting. */
+  pub fn intersects_box(&self, min: &math::Vec3f, max: &math::Vec3f) -> bool
+  {
+    for plane in self.planes.iter()
+    {
+      /* The corner furthest along the plane's normal. */
+      let furthest = math::Vec3f::new(if plane.x >= 0.0 { max.x } else { min.x },
+                                      if plane.y >= 0.0 { max.y } else { min.y },
+                                      if plane.z >= 0.0 { max.z } else { min.z });
+      if distance(plane, &furthest) < 0.0
+      { return false; }
+    }
+    true
+  }
+}
+
+fn distance(plane: &math::Vec4f, point: &math::Vec3f) -> f32
+{ (plane.x * point.x) + (plane.y * point.y) + (plane.z * point.z) + plane.w }
+
+fn normalize_plane(plane: &math::Vec4f) -> math::Vec4f
+{
+  let len = math::Vec3f::new(plane.x, plane.y, plane.z).length();
+  if len == 0.0
+  { return *plane; }
+  math::Vec4f::new(plane.x / len, plane.y / len, plane.z / len, plane.w / len)
+}
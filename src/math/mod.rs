@@ -15,6 +15,7 @@ pub use self::vec2::*;
 pub use self::vec3::*;
 pub use self::vec4::*;
 pub use self::bb3::*;
+pub use self::frustum::*;
 pub use self::util::*;
 
 mod quaternion;
@@ -23,5 +24,6 @@ mod vec2;
 mod vec3;
 mod vec4;
 mod bb3;
+mod frustum;
 mod util;
 
@@ -72,7 +72,40 @@ impl Quaternion
   }
 
   pub fn get_conjugate(&self) -> Quaternion
-  { Quaternion { x: -self.x, y: -self.y, z: -self.z, w: -self.w } }
+  { Quaternion { x: -self.x, y: -self.y, z: -self.z, w: self.w } }
+
+  pub fn dot(&self, rhs: &Quaternion) -> Component
+  { (self.x * rhs.x) + (self.y * rhs.y) + (self.z * rhs.z) + (self.w * rhs.w) }
+
+  /* Spherical linear interpolation, along the shortest arc. */
+  pub fn slerp(&self, rhs: &Quaternion, t: f32) -> Quaternion
+  {
+    let mut cos_omega = self.dot(rhs);
+    let mut to = *rhs;
+    if cos_omega < 0.0
+    {
+      to.scale(-1.0);
+      cos_omega = -cos_omega;
+    }
+
+    /* Close enough to just lerp, which avoids dividing by zero. */
+    let (k0, k1) = if cos_omega > 0.9999
+    { (1.0 - t, t) }
+    else
+    {
+      let sin_omega = (1.0 - (cos_omega * cos_omega)).sqrt();
+      let omega = sin_omega.atan2(&cos_omega);
+      let one_over = 1.0 / sin_omega;
+      (((1.0 - t) * omega).sin() * one_over, (t * omega).sin() * one_over)
+    };
+
+    let mut q = Quaternion::new((self.x * k0) + (to.x * k1),
+                                (self.y * k0) + (to.y * k1),
+                                (self.z * k0) + (to.z * k1),
+                                (self.w * k0) + (to.w * k1));
+    q.normalize();
+    q
+  }
 
   pub fn normalize(&mut self)
   {
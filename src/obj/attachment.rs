@@ -0,0 +1,72 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/attachment.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Attachment points, such as MD3 tags
+      and MD5 joints, to which other models
+      can be parented (weapons to hands,
+      heads to torsos, etc.). A child takes
+      its transform from the parent's posed
+      attachment point each update.
+*/
+
+use math;
+
+/* Anything with named points that others can be attached to. */
+pub trait Attachable
+{
+  /* World space transform of a named point in the current pose. */
+  fn get_attachment(&self, name: &str) -> Option<math::Mat4x4>;
+}
+
+pub struct Attachment
+{
+  parent: @mut Attachable,
+  point: ~str,
+
+  /* Applied before the attachment point, to position the child. */
+  offset: math::Mat4x4,
+}
+
+impl Attachment
+{
+  pub fn new(parent: @mut Attachable, point: &str) -> Attachment
+  {
+    Attachment
+    {
+      parent: parent,
+      point: point.to_owned(),
+      offset: math::Mat4x4::new(),
+    }
+  }
+
+  /* The child's local to world transform; none if the
+   * parent doesn't have the point. */
+  pub fn get_transform(&self) -> Option<math::Mat4x4>
+  {
+    match self.parent.get_attachment(self.point)
+    {
+      Some(point) => { Some(self.offset * point) },
+      None => { None }
+    }
+  }
+}
+
+/* A transform from an origin and the axes it's rotated into. */
+pub fn new_transform(origin: &math::Vec3f, axes: &[math::Vec3f, ..3]) -> math::Mat4x4
+{
+  math::Mat4x4
+  {
+    data:
+    [
+      [ axes[0].x, axes[0].y, axes[0].z, 0.0 ],
+      [ axes[1].x, axes[1].y, axes[1].z, 0.0 ],
+      [ axes[2].x, axes[2].y, axes[2].z, 0.0 ],
+      [ origin.x, origin.y, origin.z, 1.0 ]
+    ]
+  }
+}
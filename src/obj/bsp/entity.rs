@@ -0,0 +1,119 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/bsp/entity.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Parses the entity lump of Q3 BSP maps,
+      which is text of the form:
+        { "key" "value" ... } { ... }
+*/
+
+use std::hashmap::HashMap;
+use math;
+
+pub struct Entity
+{
+  class_name: ~str,
+  properties: HashMap<~str, ~str>,
+}
+
+impl Entity
+{
+  pub fn get<'a>(&'a self, key: &str) -> Option<&'a str>
+  {
+    match self.properties.find_equiv(&key)
+    {
+      Some(value) => { Some(value.as_slice()) },
+      None => { None }
+    }
+  }
+
+  /* In Q3 units, which are Z up. */
+  pub fn get_origin(&self) -> Option<math::Vec3f>
+  {
+    let value = match self.get("origin")
+    {
+      Some(v) => { v },
+      None => { return None; }
+    };
+    let components: ~[f32] = value.word_iter().filter_map(|w| FromStr::from_str(w)).collect();
+    if components.len() != 3
+    { return None; }
+
+    Some(math::Vec3f::new(components[0], components[1], components[2]))
+  }
+}
+
+pub fn parse(text: &str) -> Result<~[Entity], ~str>
+{
+  let mut entities = ~[];
+  let mut tokens = tokenize(text).move_iter();
+  loop
+  {
+    match tokens.next()
+    {
+      Some(~"{") => { }
+      Some(token) => { return Err(fmt!("Expected { but found %s", token)); }
+      None => { break; }
+    }
+
+    let mut properties = HashMap::new();
+    loop
+    {
+      let key = match tokens.next()
+      {
+        Some(~"}") => { break; }
+        Some(key) => { key },
+        None => { return Err(~"Unterminated entity"); }
+      };
+      match tokens.next()
+      {
+        Some(value) => { properties.insert(key, value); }
+        None => { return Err(fmt!("Missing value for %s", key)); }
+      }
+    }
+
+    let class_name = match properties.find_equiv(&"classname")
+    {
+      Some(name) => { name.clone() },
+      None => { ~"" }
+    };
+    entities.push(Entity { class_name: class_name, properties: properties });
+  }
+
+  Ok(entities)
+}
+
+/* Braces and quoted strings; the quotes are dropped. */
+fn tokenize(text: &str) -> ~[~str]
+{
+  let mut tokens = ~[];
+  let mut chars = text.iter();
+  loop
+  {
+    match chars.next()
+    {
+      Some('"') =>
+      {
+        let mut token = ~"";
+        loop
+        {
+          match chars.next()
+          {
+            Some('"') | None => { break; }
+            Some(ch) => { token.push_char(ch); }
+          }
+        }
+        tokens.push(token);
+      }
+      Some('{') => { tokens.push(~"{"); }
+      Some('}') => { tokens.push(~"}"); }
+      Some(_) => { } /* Whitespace and trailing nulls. */
+      None => { break; }
+    }
+  }
+  tokens
+}
@@ -9,9 +9,9 @@
       Loader and handler of BSP maps.
 */
 
-use std::{ cmp, path, io, sys, cast };
+use std::{ cmp, path, io, sys, cast, str };
 use math;
-use super::lump;
+use super::{ lump, entity };
 use primitive::{ Triangle, Vertex_PC };
 use util::Log;
 
@@ -25,6 +25,7 @@ pub struct Map
 {
   header: lump::Header,
   entity: lump::Entity,
+  entities: ~[entity::Entity],
   tris: ~[Triangle],
   verts: ~[lump::Vertex],
   faces: ~[lump::Face],
@@ -32,16 +33,25 @@ pub struct Map
   position: math::Vec3f,
   bb: math::BB3,
   error: ~str,
+
+  /* Subtracted from everything (after scaling) to center the map. */
+  center: math::Vec3f,
 }
 
 impl Map
 {
   pub fn new(file: &str) -> Result<Map, ~str>
+  { Map::new_with_progress(file, |_| { }) }
+
+  /* The progress callback receives the fraction (0.0 to 1.0) of
+   * the file that has been parsed. */
+  pub fn new_with_progress(file: &str, progress: &fn(f32)) -> Result<Map, ~str>
   {
     let mut map = Map
     {
       header: lump::Header::new(),
       entity: lump::Entity::new(),
+      entities: ~[],
       tris: ~[],
       verts: ~[],
       faces: ~[],
@@ -49,6 +59,8 @@ impl Map
       position: math::Vec3f::zero(),
       bb: math::BB3::zero(),
       error: ~"",
+
+      center: math::Vec3f::zero(),
     };
 
     let fio = io::file_reader(@path::PosixPath(file));
@@ -63,14 +75,20 @@ impl Map
              map.header.magic[2] == 'S' as i8 &&
              map.header.magic[3] == 'P' as i8);
 
+    progress(0.0);
     if !map.read_verts(fio)
     { return Err(map.error); }
+    progress(0.25);
     if !map.read_faces(fio)
     { return Err(map.error); }
+    progress(0.5);
     if !map.read_mesh_verts(fio)
     { return Err(map.error); }
+    progress(0.75);
+    map.read_entities(fio);
 
     map.triangulate();
+    progress(1.0);
     
     Ok(map)
   }
@@ -161,10 +179,36 @@ impl Map
     /* Move the mesh by the center to the origin (easier to voxelize). */
     for v in self.verts.mut_iter()
     { v.position = v.position - center; }
+    self.center = center;
 
     true
   }
 
+  /* Entities only add to the map, so a bad lump leaves it without any. */
+  fn read_entities(&mut self, fio: @io::Reader)
+  {
+    fio.seek(self.header.lumps[lump::Entity_Type as int].offset as int, io::SeekSet);
+    let length = self.header.lumps[lump::Entity_Type as int].length as uint;
+
+    /* The text is null terminated. */
+    let text: ~[u8] = fio.read_bytes(length).move_iter().filter(|b| *b != 0).collect();
+    if !str::is_utf8(text)
+    { log_error!("Entities aren't valid UTF-8; continuing without any"); return; }
+    match entity::parse(str::from_bytes(text))
+    {
+      Ok(entities) =>
+      {
+        log_debug!("Parsed %u entities", entities.len());
+        self.entities = entities;
+      }
+      Err(err) => { log_error!("Invalid entities; continuing without any: %s", err); }
+    }
+  }
+
+  /* Moves a position in Q3 units into the map's space. */
+  pub fn to_local(&self, position: &math::Vec3f) -> math::Vec3f
+  { (math::Vec3f::new(position.x, position.z, -position.y) * (1.0 / 32.0)) - self.center }
+
   fn read_faces(&mut self, fio: @io::Reader) -> bool
   {
     fio.seek(self.header.lumps[lump::Face_Type as int].offset as int, io::SeekSet);
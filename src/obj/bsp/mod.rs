@@ -11,7 +11,9 @@
 
 pub use self::map::Map;
 pub use self::lump::Lump;
+pub use self::entity::Entity;
 
 mod map;
 pub mod lump;
+pub mod entity;
 
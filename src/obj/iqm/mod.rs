@@ -0,0 +1,14 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/iqm/mod.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      An aggregator of IQM items.
+*/
+
+pub use self::model::load;
+
+mod model;
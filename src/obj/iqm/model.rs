@@ -0,0 +1,738 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/iqm/model.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Loads Inter-Quake Models, a binary
+      format exported from Blender, into
+      the same joints, meshes, and
+      animations used for MD5 models. IQM
+      carries normals, tangents, and vertex
+      colors, which are kept. As with MD5,
+      models are Z up. Each animation in
+      the file becomes an animator state
+      of the same name.
+*/
+
+use std::{ io, path, os, str, cast };
+use std::num::{ CheckedAdd, CheckedMul };
+use math;
+use md5;
+use util::Log;
+
+#[macro_escape]
+#[path = "../../util/log_macros.rs"]
+mod log_macros;
+
+static Magic: &'static str = "INTERQUAKEMODEL\0";
+static Version: u32 = 2;
+
+/* Vertex array types. */
+static Position: u32 = 0;
+static Tex_Coord: u32 = 1;
+static Normal: u32 = 2;
+static Tangent: u32 = 3;
+static Blend_Indices: u32 = 4;
+static Blend_Weights: u32 = 5;
+static Color: u32 = 6;
+
+/* Vertex array formats; half and double aren't supported. */
+static Format_Byte: u32 = 0;
+static Format_Ubyte: u32 = 1;
+static Format_Short: u32 = 2;
+static Format_Ushort: u32 = 3;
+static Format_Int: u32 = 4;
+static Format_Uint: u32 = 5;
+static Format_Float: u32 = 7;
+
+static Anim_Loop: u32 = 1;
+
+/* IQM allows animations without one; this is Blender's default. */
+static Default_Frame_Rate: f32 = 24.0;
+
+/* Sizes, in bytes, of each structure in the file. */
+static Header_Size: uint = 124;
+static Mesh_Size: uint = 24;
+static Vertex_Array_Size: uint = 20;
+static Triangle_Size: uint = 12;
+static Joint_Size: uint = 48;
+static Pose_Size: uint = 88;
+static Anim_Size: uint = 20;
+static Bounds_Size: uint = 32;
+
+/* Translation, rotation (a quaternion), then scale. */
+static Pose_Channels: uint = 10;
+
+/* Every field is a u32, past the magic. */
+struct Header
+{
+  version: u32,
+  file_size: u32,
+  flags: u32,
+  num_text: u32, ofs_text: u32,
+  num_meshes: u32, ofs_meshes: u32,
+  num_vertex_arrays: u32, num_vertices: u32, ofs_vertex_arrays: u32,
+  num_triangles: u32, ofs_triangles: u32, ofs_adjacency: u32,
+  num_joints: u32, ofs_joints: u32,
+  num_poses: u32, ofs_poses: u32,
+  num_anims: u32, ofs_anims: u32,
+  num_frames: u32, num_frame_channels: u32, ofs_frames: u32, ofs_bounds: u32,
+  num_comment: u32, ofs_comment: u32,
+  num_extensions: u32, ofs_extensions: u32,
+}
+
+struct Vertex_Array
+{
+  kind: u32,
+  format: u32,
+  size: uint, /* Components per vertex. */
+  offset: uint,
+}
+
+pub fn load(file: &str) -> Result<md5::Model, ~str>
+{
+  let data = match io::read_whole_file(&path::Path(file))
+  {
+    Ok(data) => { data },
+    Err(err) => { return Err(err); }
+  };
+  match load_from_bytes(file, data)
+  {
+    Ok(model) => { Ok(model) },
+    Err(err) => { Err(fmt!("%s: %s", file, err)) }
+  }
+}
+
+/* The file is used to find textures. */
+fn load_from_bytes(file: &str, data: &[u8]) -> Result<md5::Model, ~str>
+{
+  if data.len() < Header_Size || data.slice(0, Magic.len()) != Magic.as_bytes()
+  { return Err(~"Not an IQM file"); }
+  let header = read_header(data);
+  if header.version != Version
+  { return Err(fmt!("Unsupported version %u", header.version as uint)); }
+  if header.file_size as uint > data.len()
+  { return Err(~"Truncated file"); }
+
+  /* Every section needs to be in the file before anything is read. Sizes
+   * are computed as uints, since a file's counts can overflow a u32. */
+  let frame_values = match (header.num_frames as uint).checked_mul(&(header.num_frame_channels as uint))
+  {
+    Some(count) => { count },
+    None => { return Err(~"Invalid frames section"); }
+  };
+  let sections = [ ("text", header.ofs_text as uint, header.num_text as uint, 1),
+                   ("meshes", header.ofs_meshes as uint, header.num_meshes as uint, Mesh_Size),
+                   ("vertex arrays", header.ofs_vertex_arrays as uint, header.num_vertex_arrays as uint, Vertex_Array_Size),
+                   ("triangles", header.ofs_triangles as uint, header.num_triangles as uint, Triangle_Size),
+                   ("joints", header.ofs_joints as uint, header.num_joints as uint, Joint_Size),
+                   ("poses", header.ofs_poses as uint, header.num_poses as uint, Pose_Size),
+                   ("animations", header.ofs_anims as uint, header.num_anims as uint, Anim_Size),
+                   ("frames", header.ofs_frames as uint, frame_values, 2) ];
+  for &(name, offset, count, size) in sections.iter()
+  {
+    if !in_range(data, offset, count, size)
+    { return Err(fmt!("Invalid %s section", name)); }
+  }
+  let text = data.slice(header.ofs_text as uint, header.ofs_text as uint + header.num_text as uint);
+  log_debug!("Loading IQM %s: %u meshes, %u vertices, %u joints, %u animations", file,
+             header.num_meshes as uint, header.num_vertices as uint,
+             header.num_joints as uint, header.num_anims as uint);
+  log_push!();
+
+  let res = read_model(file, data, text, &header);
+  log_pop!();
+  let mut model = match res
+  {
+    Ok(model) => { model },
+    Err(err) => { return Err(err); }
+  };
+
+  match read_animations(data, text, &header, &mut model)
+  {
+    Ok(()) => { Ok(model) },
+    Err(err) => { Err(err) }
+  }
+}
+
+fn read_model(file: &str, data: &[u8], text: &[u8], header: &Header) -> Result<md5::Model, ~str>
+{
+  let joints = match read_joints(data, text, header)
+  {
+    Ok(joints) => { joints },
+    Err(err) => { return Err(err); }
+  };
+
+  /* Each vertex array, as floats, keyed by type. */
+  let mut arrays: ~[(u32, uint, ~[f32])] = ~[];
+  for i in range(0, header.num_vertex_arrays as uint)
+  {
+    let offset = header.ofs_vertex_arrays as uint + (i * Vertex_Array_Size);
+    let array = Vertex_Array
+    {
+      kind: read_u32(data, offset),
+      format: read_u32(data, offset + 8),
+      size: read_u32(data, offset + 12) as uint,
+      offset: read_u32(data, offset + 16) as uint,
+    };
+    if array.kind > Color
+    { loop; } /* Custom arrays. */
+
+    /* Colors and weights are normalized; indices are not. */
+    let normalized = array.kind == Color || array.kind == Blend_Weights;
+    match read_vertex_array(data, &array, header.num_vertices as uint, normalized)
+    {
+      Ok(values) => { arrays.push((array.kind, array.size, values)); }
+      Err(err) => { return Err(err); }
+    }
+  }
+  /* Arrays of another size than the usual are ignored. */
+  let find = |kind: u32, size: uint| -> Option<&~[f32]>
+  {
+    arrays.iter().find(|&&(k, s, _)| k == kind && s == size).map(|&(_, _, ref values)| values)
+  };
+  let positions = match find(Position, 3)
+  {
+    Some(values) => { values },
+    None => { return Err(~"No vertex positions"); }
+  };
+  let tex_coords = find(Tex_Coord, 2);
+  let normals = find(Normal, 3);
+  let tangents = find(Tangent, 4);
+  let blend_indices = find(Blend_Indices, 4);
+  let blend_weights = find(Blend_Weights, 4);
+  let colors = find(Color, 4);
+
+  /* Pulls the nth vertex's components out of an array. */
+  let component = |values: &~[f32], vertex: uint, c: uint, size: uint| -> f32
+  { values[(vertex * size) + c] };
+
+  let mut meshes = ~[];
+  for i in range(0, header.num_meshes as uint)
+  {
+    let offset = header.ofs_meshes as uint + (i * Mesh_Size);
+    let name = match read_string(text, read_u32(data, offset))
+    {
+      Ok(s) => { s },
+      Err(err) => { return Err(err); }
+    };
+    let material = match read_string(text, read_u32(data, offset + 4))
+    {
+      Ok(s) => { s },
+      Err(err) => { return Err(err); }
+    };
+    let first_vertex = read_u32(data, offset + 8) as uint;
+    let num_vertices = read_u32(data, offset + 12) as uint;
+    let first_triangle = read_u32(data, offset + 16) as uint;
+    let num_triangles = read_u32(data, offset + 20) as uint;
+    if !within(first_vertex, num_vertices, header.num_vertices as uint) ||
+       !within(first_triangle, num_triangles, header.num_triangles as uint)
+    { return Err(fmt!("Mesh %s is out of range", name)); }
+
+    let mut mesh = md5::Mesh::new();
+    mesh.texture = find_texture(file, material);
+    log_debug!("Mesh %s: %u verts, %u tris, texture %s", name, num_vertices, num_triangles, mesh.texture);
+
+    for v in range(first_vertex, first_vertex + num_vertices)
+    {
+      let mut vert = md5::Vertex::new();
+      let position = math::Vec3f::new(component(positions, v, 0, 3),
+                                       component(positions, v, 1, 3),
+                                       component(positions, v, 2, 3));
+      vert.position = position;
+      match tex_coords
+      {
+        Some(values) => { vert.tex_coord = math::Vec2f::new(component(values, v, 0, 2), component(values, v, 1, 2)); },
+        None => { }
+      }
+      match normals
+      {
+        Some(values) =>
+        { vert.normal = math::Vec3f::new(component(values, v, 0, 3), component(values, v, 1, 3), component(values, v, 2, 3)); },
+        None => { }
+      }
+      match tangents
+      {
+        Some(values) =>
+        {
+          vert.tangent = math::Vec4f::new(component(values, v, 0, 4), component(values, v, 1, 4),
+                                          component(values, v, 2, 4), component(values, v, 3, 4));
+        },
+        None => { }
+      }
+      match colors
+      {
+        Some(values) =>
+        {
+          mesh.colors.push(math::Vec4f::new(component(values, v, 0, 4), component(values, v, 1, 4),
+                                            component(values, v, 2, 4), component(values, v, 3, 4)));
+        },
+        None => { }
+      }
+
+      /* Weights are kept relative to their joint, as in MD5. */
+      vert.start_weight = mesh.weights.len() as i32;
+      match (blend_indices, blend_weights)
+      {
+        (Some(indices), Some(weights)) =>
+        {
+          for w in range(0, 4)
+          {
+            let bias = component(weights, v, w, 4);
+            if bias <= 0.0
+            { loop; }
+
+            let joint_id = component(indices, v, w, 4) as uint;
+            if joint_id >= joints.len()
+            { return Err(fmt!("Vertex %u references joint %u of %u", v, joint_id, joints.len())); }
+            mesh.weights.push(new_weight(&joints[joint_id], joint_id, bias, &position));
+          }
+        }
+        _ => { }
+      }
+
+      /* Unweighted vertices stay with the root. */
+      if mesh.weights.len() as i32 == vert.start_weight
+      { mesh.weights.push(new_weight(&joints[0], 0, 1.0, &position)); }
+      vert.weight_count = mesh.weights.len() as i32 - vert.start_weight;
+      mesh.verts.push(vert);
+    }
+
+    /* Triangle indices are of the whole file; meshes index their own. */
+    for t in range(first_triangle, first_triangle + num_triangles)
+    {
+      let mut tri = md5::Triangle::new();
+      for j in range(0, 3)
+      {
+        let index = read_u32(data, header.ofs_triangles as uint + (t * Triangle_Size) + (j * 4)) as uint;
+        if index < first_vertex || index >= first_vertex + num_vertices
+        { return Err(fmt!("Triangle %u of mesh %s is out of range", t, name)); }
+        tri.indices[j] = (index - first_vertex) as i32;
+        mesh.indices.push((index - first_vertex) as u32);
+      }
+      mesh.triangles.push(tri);
+    }
+
+    meshes.push(mesh);
+  }
+
+  /* Without both, they're computed as they are for MD5. */
+  let has_tangent_frames = normals.is_some() && tangents.is_some();
+  Ok(md5::Model::new_from_parts(joints, meshes, has_tangent_frames))
+}
+
+/* Joints are stored relative to their parents; MD5 keeps them in
+ * model space. Models without joints get a root at the origin. */
+fn read_joints(data: &[u8], text: &[u8], header: &Header) -> Result<~[md5::Joint], ~str>
+{
+  if header.num_joints == 0
+  {
+    let mut root = md5::Joint::new();
+    root.name = ~"root";
+    root.parent = -1;
+    root.orientation = math::Quaternion::new(0.0, 0.0, 0.0, 1.0);
+    return Ok(~[root]);
+  }
+
+  let mut joints: ~[md5::Joint] = ~[];
+  for i in range(0, header.num_joints as uint)
+  {
+    let offset = header.ofs_joints as uint + (i * Joint_Size);
+    let mut joint = md5::Joint::new();
+    joint.name = match read_string(text, read_u32(data, offset))
+    {
+      Ok(s) => { s },
+      Err(err) => { return Err(err); }
+    };
+    joint.parent = read_u32(data, offset + 4) as i32;
+    if joint.parent < -1 || joint.parent >= i as i32
+    { return Err(fmt!("Joint %s has an invalid parent %d", joint.name, joint.parent as int)); }
+
+    /* Scale isn't supported, since MD5 joints don't have it. */
+    joint.position = read_vec3(data, offset + 8);
+    joint.orientation = read_quaternion(data, offset + 20);
+    to_model_space(&mut joint, joints);
+    joints.push(joint);
+  }
+  Ok(joints)
+}
+
+/* Each animation is a range of the file's frames. */
+fn read_animations(data: &[u8], text: &[u8], header: &Header, model: &mut md5::Model) -> Result<(), ~str>
+{
+  if header.num_anims == 0
+  { return Ok(()); }
+  if header.num_poses as uint != model.joints.len()
+  { return Err(fmt!("There are %u poses for %u joints", header.num_poses as uint, model.joints.len())); }
+  if header.ofs_bounds != 0 && !in_range(data, header.ofs_bounds as uint, header.num_frames as uint, Bounds_Size)
+  { return Err(~"Invalid bounds section"); }
+
+  /* Frames only store the channels that change. */
+  let mut masks = ~[];
+  let mut channel_offsets = ~[];
+  let mut channel_scales = ~[];
+  let mut channels = 0;
+  for i in range(0, header.num_poses as uint)
+  {
+    let offset = header.ofs_poses as uint + (i * Pose_Size);
+    let mask = read_u32(data, offset + 4);
+    masks.push(mask);
+    for c in range(0, Pose_Channels)
+    {
+      channel_offsets.push(read_f32(data, offset + 8 + (c * 4)));
+      channel_scales.push(read_f32(data, offset + 48 + (c * 4)));
+      if (mask & (1 << c)) != 0
+      { channels += 1; }
+    }
+  }
+  if channels != header.num_frame_channels as uint
+  { return Err(fmt!("Poses have %u channels, but frames have %u", channels, header.num_frame_channels as uint)); }
+
+  let mut first_name = ~"";
+  for i in range(0, header.num_anims as uint)
+  {
+    let offset = header.ofs_anims as uint + (i * Anim_Size);
+    let name = match read_string(text, read_u32(data, offset))
+    {
+      Ok(s) => { s },
+      Err(err) => { return Err(err); }
+    };
+    let first_frame = read_u32(data, offset + 4) as uint;
+    let num_frames = read_u32(data, offset + 8) as uint;
+    let frame_rate = read_f32(data, offset + 12);
+    let flags = read_u32(data, offset + 16);
+    if num_frames == 0 || !within(first_frame, num_frames, header.num_frames as uint)
+    { return Err(fmt!("Animation %s is out of range", name)); }
+
+    let mut skeletons = ~[];
+    let mut bounds = ~[];
+    for frame in range(first_frame, first_frame + num_frames)
+    {
+      let mut channel = header.ofs_frames as uint + (frame * header.num_frame_channels as uint * 2);
+      let mut skeleton: ~[md5::Joint] = ~[];
+      for (j, bind) in model.joints.iter().enumerate()
+      {
+        let mut values = [ 0.0f32, ..10 ];
+        for c in range(0, Pose_Channels)
+        {
+          let index = (j * Pose_Channels) + c;
+          values[c] = channel_offsets[index];
+          if (masks[j] & (1 << c)) != 0
+          {
+            values[c] += read_u16(data, channel) as f32 * channel_scales[index];
+            channel += 2;
+          }
+        }
+
+        let mut joint = md5::Joint::new();
+        joint.name = bind.name.clone();
+        joint.parent = bind.parent;
+        joint.position = math::Vec3f::new(values[0], values[1], values[2]);
+        joint.orientation = math::Quaternion::new(values[3], values[4], values[5], values[6]);
+        joint.orientation.normalize();
+        to_model_space(&mut joint, skeleton);
+        skeleton.push(joint);
+      }
+      skeletons.push(skeleton);
+
+      if header.ofs_bounds != 0
+      {
+        let offset = header.ofs_bounds as uint + (frame * Bounds_Size);
+        bounds.push((read_vec3(data, offset), read_vec3(data, offset + 12)));
+      }
+    }
+
+    let frame_rate = if frame_rate.is_finite() && frame_rate > 0.0 { frame_rate } else { Default_Frame_Rate };
+    let anim = md5::Animation::new_from_skeletons(skeletons, bounds, frame_rate);
+    log_debug!("Animation %s: %u frames at %f fps", name, num_frames, frame_rate as float);
+
+    /* One-shots go back to the first animation once they finish. */
+    let next = if (flags & Anim_Loop) != 0 || i == 0
+    { None }
+    else
+    { Some(first_name.clone()) };
+    match model.add_animation(name, anim, next)
+    {
+      Ok(()) => { },
+      Err(err) => { return Err(err); }
+    }
+    if i == 0
+    { first_name = name; }
+  }
+  Ok(())
+}
+
+/* Moves a joint from its parent's space, which needs to be in
+ * the skeleton already, into model space. */
+fn to_model_space(joint: &mut md5::Joint, skeleton: &[md5::Joint])
+{
+  if joint.parent < 0
+  { return; }
+
+  let parent = &skeleton[joint.parent];
+  joint.position = parent.position + parent.orientation.rotate_vec(&joint.position);
+  joint.orientation = parent.orientation * joint.orientation;
+  joint.orientation.normalize();
+}
+
+/* A weight of a bind pose position, relative to its joint. */
+fn new_weight(joint: &md5::Joint, joint_id: uint, bias: f32, position: &math::Vec3f) -> md5::Weight
+{
+  let mut weight = md5::Weight::new();
+  weight.joint_id = joint_id as i32;
+  weight.bias = bias;
+  weight.position = joint.orientation.get_conjugate().rotate_vec(&(*position - joint.position));
+  weight
+}
+
+/* Converts every component to a float. */
+fn read_vertex_array(data: &[u8], array: &Vertex_Array, num_vertices: uint, normalized: bool) -> Result<~[f32], ~str>
+{
+  let component_size = match array.format
+  {
+    Format_Byte | Format_Ubyte => { 1 },
+    Format_Short | Format_Ushort => { 2 },
+    Format_Int | Format_Uint | Format_Float => { 4 },
+    _ => { return Err(fmt!("Unsupported vertex array format %u", array.format as uint)); }
+  };
+  let count = match num_vertices.checked_mul(&array.size)
+  {
+    Some(count) if in_range(data, array.offset, count, component_size) => { count },
+    _ => { return Err(~"Invalid vertex array"); }
+  };
+
+  let mut values = ~[];
+  for i in range(0, count)
+  {
+    let offset = array.offset + (i * component_size);
+    let value = match array.format
+    {
+      Format_Byte => { data[offset] as i8 as f32 / if normalized { 127.0 } else { 1.0 } },
+      Format_Ubyte => { data[offset] as f32 / if normalized { 255.0 } else { 1.0 } },
+      Format_Short => { read_u16(data, offset) as i16 as f32 / if normalized { 32767.0 } else { 1.0 } },
+      Format_Ushort => { read_u16(data, offset) as f32 / if normalized { 65535.0 } else { 1.0 } },
+      Format_Int => { read_u32(data, offset) as i32 as f32 },
+      Format_Uint => { read_u32(data, offset) as f32 },
+      _ => { read_f32(data, offset) }
+    };
+    values.push(value);
+  }
+  Ok(values)
+}
+
+fn read_header(data: &[u8]) -> Header
+{
+  let field = |i: uint| -> u32 { read_u32(data, Magic.len() + (i * 4)) };
+  Header
+  {
+    version: field(0),
+    file_size: field(1),
+    flags: field(2),
+    num_text: field(3), ofs_text: field(4),
+    num_meshes: field(5), ofs_meshes: field(6),
+    num_vertex_arrays: field(7), num_vertices: field(8), ofs_vertex_arrays: field(9),
+    num_triangles: field(10), ofs_triangles: field(11), ofs_adjacency: field(12),
+    num_joints: field(13), ofs_joints: field(14),
+    num_poses: field(15), ofs_poses: field(16),
+    num_anims: field(17), ofs_anims: field(18),
+    num_frames: field(19), num_frame_channels: field(20), ofs_frames: field(21), ofs_bounds: field(22),
+    num_comment: field(23), ofs_comment: field(24),
+    num_extensions: field(25), ofs_extensions: field(26),
+  }
+}
+
+/* Materials name a texture relative to the model, though its
+ * extension may be missing or wrong. */
+fn find_texture(file: &str, material: &str) -> ~str
+{
+  if material.len() == 0
+  { return ~""; }
+
+  let texture = path::Path(path::Path(file).dirname() + "/" + material);
+  let mut candidates = ~[texture.clone()];
+  for ext in ["png", "tga", "jpg"].iter()
+  { candidates.push(texture.with_filetype(*ext)); }
+
+  match candidates.iter().find(|c| os::path_exists(*c))
+  {
+    Some(found) => { found.to_str() },
+    None => { log_error!("Missing texture %s", material); ~"" }
+  }
+}
+
+/* Whether count items of the given size, from the offset, are in the data. */
+fn in_range(data: &[u8], offset: uint, count: uint, size: uint) -> bool
+{
+  match count.checked_mul(&size)
+  {
+    Some(bytes) => { offset <= data.len() && bytes <= data.len() - offset },
+    None => { false }
+  }
+}
+
+/* Whether count items from first are within the total. */
+fn within(first: uint, count: uint, total: uint) -> bool
+{
+  match first.checked_add(&count)
+  {
+    Some(end) => { end <= total },
+    None => { false }
+  }
+}
+
+/* Null terminated, at an offset into the text section. */
+fn read_string(text: &[u8], offset: u32) -> Result<~str, ~str>
+{
+  let start = offset as uint;
+  if start >= text.len()
+  { return Ok(~""); }
+  let end = match text.slice_from(start).iter().position(|b| *b == 0)
+  {
+    Some(len) => { start + len },
+    None => { text.len() }
+  };
+  if !str::is_utf8(text.slice(start, end))
+  { return Err(fmt!("Invalid string at %u", start)); }
+  Ok(str::from_bytes(text.slice(start, end)))
+}
+
+fn read_u16(data: &[u8], offset: uint) -> u16
+{ data[offset] as u16 | (data[offset + 1] as u16 << 8) }
+
+fn read_u32(data: &[u8], offset: uint) -> u32
+{
+  data[offset] as u32 | (data[offset + 1] as u32 << 8) |
+  (data[offset + 2] as u32 << 16) | (data[offset + 3] as u32 << 24)
+}
+
+fn read_f32(data: &[u8], offset: uint) -> f32
+{ unsafe { cast::transmute(read_u32(data, offset)) } }
+
+fn read_vec3(data: &[u8], offset: uint) -> math::Vec3f
+{ math::Vec3f::new(read_f32(data, offset), read_f32(data, offset + 4), read_f32(data, offset + 8)) }
+
+fn read_quaternion(data: &[u8], offset: uint) -> math::Quaternion
+{
+  let mut q = math::Quaternion::new(read_f32(data, offset), read_f32(data, offset + 4),
+                                    read_f32(data, offset + 8), read_f32(data, offset + 12));
+  q.normalize();
+  q
+}
+
+#[cfg(test)]
+mod test
+{
+  use math;
+  use md5;
+  use super::{ load_from_bytes, read_u32 };
+
+  /* A triangle with an arm joint, raised by its one animation. */
+  static Triangle: &'static [u8] = include_bin!("../../../data/models/test/triangle.iqm");
+
+  fn close(a: math::Vec3f, b: math::Vec3f) -> bool
+  { (a - b).length() < 0.0001 }
+
+  /* The fixture, with u32s replaced at the given offsets. */
+  fn patched(values: &[(uint, u32)]) -> ~[u8]
+  {
+    let mut data = Triangle.to_owned();
+    for &(offset, value) in values.iter()
+    {
+      for i in range(0u, 4)
+      { data[offset + i] = (value >> (i * 8)) as u8; }
+    }
+    data
+  }
+
+  fn load_error(data: &[u8]) -> ~str
+  {
+    match load_from_bytes("test.iqm", data)
+    {
+      Ok(_) => { fail!("Loaded a malformed model"); },
+      Err(err) => { err }
+    }
+  }
+
+  /* Offset of a header field. */
+  fn field(i: uint) -> uint
+  { 16 + (i * 4) }
+
+  #[test]
+  fn valid()
+  {
+    let model: md5::Model = match load_from_bytes("test.iqm", Triangle)
+    {
+      Ok(model) => { model },
+      Err(err) => { fail!(err); }
+    };
+    assert_eq!(model.joints.len(), 2);
+    assert!(close(model.joints[1].position, math::Vec3f::new(0.0, 0.0, 1.0)));
+
+    assert_eq!(model.meshes.len(), 1);
+    let mesh = &model.meshes[0];
+    assert_eq!(mesh.verts.len(), 3);
+    assert_eq!(mesh.triangles.len(), 1);
+    assert_eq!(mesh.indices, ~[0, 1, 2]);
+    assert!(close(mesh.positions[2], math::Vec3f::new(0.0, 0.0, 2.0)));
+
+    /* The top vertex follows the arm, relative to it. */
+    let weight = &mesh.weights[mesh.verts[2].start_weight];
+    assert_eq!(weight.joint_id, 1);
+    assert!(close(weight.position, math::Vec3f::new(0.0, 0.0, 1.0)));
+
+    assert_eq!(model.animator.states.len(), 1);
+    let state = &model.animator.states[0];
+    assert_eq!(state.name, ~"raise");
+    assert_eq!(state.animation.skeletons.len(), 2);
+    assert_eq!(state.animation.bounds.len(), 2);
+    assert!(close(state.animation.skeletons[1][1].position, math::Vec3f::new(0.0, 0.0, 2.0)));
+  }
+
+  #[test]
+  fn truncated()
+  {
+    assert_eq!(load_error(Triangle.slice(0, 100)), ~"Not an IQM file");
+    assert_eq!(load_error(Triangle.slice(0, Triangle.len() - 1)), ~"Truncated file");
+  }
+
+  /* Counts whose product wraps as a u32 are still rejected. */
+  #[test]
+  fn frames_overflow()
+  {
+    let data = patched([(field(19), 0x10000), (field(20), 0x10000)]);
+    assert_eq!(load_error(data), ~"Invalid frames section");
+  }
+
+  /* Infinite rates are as unusable as missing ones. */
+  #[test]
+  fn infinite_frame_rate()
+  {
+    let offset = read_u32(Triangle, field(18)) as uint + 12;
+    match load_from_bytes("test.iqm", patched([(offset, 0x7F800000)]))
+    {
+      Ok(model) => { assert_eq!(model.animator.states[0].animation.frame_rate, 24); },
+      Err(err) => { fail!(err); }
+    }
+  }
+
+  #[test]
+  fn invalid_string()
+  {
+    /* The first byte of the first joint's name, within the text. */
+    let name = read_u32(Triangle, read_u32(Triangle, field(14)) as uint) as uint;
+    let mut data = Triangle.to_owned();
+    data[read_u32(Triangle, field(4)) as uint + name] = 0xFF;
+    assert_eq!(load_error(data), fmt!("Invalid string at %u", name));
+  }
+
+  #[test]
+  fn vertex_array_overflow()
+  {
+    /* The first array's components per vertex. */
+    let offset = read_u32(Triangle, field(9)) as uint + 12;
+    assert_eq!(load_error(patched([(offset, 0x40000000)])), ~"Invalid vertex array");
+  }
+}
@@ -0,0 +1,86 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md3/instance.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A placed, animating MD3 model, which
+      can be attached to other models and
+      have others attached to its tags.
+*/
+
+use math;
+use attachment::{ Attachable, Attachment };
+use super::{ Model, Model_Renderer };
+
+struct Instance
+{
+  model: @Model,
+
+  frame: f32, /* Fractional, to blend between frames. */
+  frame_rate: f32, /* Frames per second. */
+
+  local_to_world: math::Mat4x4,
+  attachment: Option<Attachment>,
+}
+
+impl Instance
+{
+  pub fn new(model: @Model) -> Instance
+  {
+    Instance
+    {
+      model: model,
+
+      frame: 0.0,
+      frame_rate: 0.0,
+
+      local_to_world: math::Mat4x4::new(),
+      attachment: None,
+    }
+  }
+
+  /* Advances the frame and follows any attachment. Attached
+   * instances should be updated after their parents. */
+  pub fn update(&mut self, delta: f32) /* dt is in terms of seconds. */
+  {
+    self.frame += delta * self.frame_rate;
+
+    let transform = match self.attachment
+    {
+      Some(ref attachment) => { attachment.get_transform() },
+      None => { None }
+    };
+    match transform
+    {
+      Some(t) => { self.local_to_world = t; }
+      None => { }
+    }
+  }
+
+  /* Parents the instance to a point (tag, joint, etc.) of another. */
+  pub fn attach_to(&mut self, parent: @mut Attachable, point: &str)
+  { self.attachment = Some(Attachment::new(parent, point)); }
+
+  /* The instance stays where it was last attached. */
+  pub fn detach(&mut self)
+  { self.attachment = None; }
+
+  /* The renderer needs to be of the same model. */
+  pub fn render(&self, renderer: &Model_Renderer)
+  { renderer.render(&self.local_to_world, self.frame); }
+}
+
+impl Attachable for Instance
+{
+  fn get_attachment(&self, name: &str) -> Option<math::Mat4x4>
+  {
+    match self.model.get_tag(name, self.frame)
+    {
+      Some(tag) => { Some(tag * self.local_to_world) },
+      None => { None }
+    }
+  }
+}
@@ -0,0 +1,25 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md3/mod.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      An aggregator of MD3 items.
+*/
+
+pub use self::model::{ Model, Frame };
+pub use self::model_renderer::Model_Renderer;
+pub use self::instance::Instance;
+pub use self::surface::{ Surface, Vertex };
+pub use self::surface_renderer::Surface_Renderer;
+pub use self::tag::Tag;
+
+mod model;
+mod model_renderer;
+mod instance;
+mod surface;
+mod surface_renderer;
+mod tag;
+
@@ -0,0 +1,457 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md3/model.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Loads Quake 3 MD3 models, which are
+      animated by storing every vertex of
+      every frame. Models are kept in Q3
+      units, which are Z up. Texture paths
+      are relative to the data directory,
+      as they are to the Q3 base directory.
+*/
+
+use std::{ io, path, str, os, f32, sys, cast };
+use std::num::{ CheckedAdd, CheckedMul };
+use math;
+use attachment::new_transform;
+use super::{ Surface, Vertex, Tag };
+use util::Log;
+
+#[macro_escape]
+#[path = "../../util/log_macros.rs"]
+mod log_macros;
+
+static Magic: &'static str = "IDP3";
+static Version: i32 = 15;
+static Data_Directory: &'static str = "data/";
+
+/* Vertex positions are fixed point. */
+static Position_Scale: f32 = 1.0 / 64.0;
+
+/* Name lengths, including the null terminator. */
+static Max_Name: uint = 64;
+static Max_Frame_Name: uint = 16;
+
+/* Sizes, in bytes, of each structure in the file. */
+static Header_Size: uint = 108;
+static Frame_Size: uint = 56;
+static Tag_Size: uint = 112;
+static Surface_Size: uint = 108;
+static Triangle_Size: uint = 12;
+static Tex_Coord_Size: uint = 8;
+static Vertex_Size: uint = 8;
+
+struct Frame
+{
+  name: ~str,
+  min: math::Vec3f,
+  max: math::Vec3f,
+  origin: math::Vec3f,
+  radius: f32,
+}
+
+struct Model
+{
+  name: ~str,
+  frames: ~[Frame],
+
+  /* Every frame's tags, one frame after another. */
+  num_tags: uint,
+  tags: ~[Tag],
+
+  surfaces: ~[Surface],
+}
+
+impl Model
+{
+  pub fn new(file: &str) -> Result<Model, ~str>
+  {
+    let data = match io::read_whole_file(&path::Path(file))
+    {
+      Ok(data) => { data },
+      Err(err) => { return Err(err); }
+    };
+    match Model::load_from_bytes(data)
+    {
+      Ok(model) => { Ok(model) },
+      Err(err) => { Err(fmt!("%s: %s", file, err)) }
+    }
+  }
+
+  /* Every section is checked against the data before it's read. */
+  fn load_from_bytes(data: &[u8]) -> Result<Model, ~str>
+  {
+    if data.len() < Header_Size || data.slice(0, Magic.len()) != Magic.as_bytes()
+    { return Err(~"Not an MD3 file"); }
+    let version = read_i32(data, 4);
+    if version != Version
+    { return Err(fmt!("Unsupported version %d", version as int)); }
+
+    let mut model = Model
+    {
+      name: match read_name(data, 8, Max_Name)
+      {
+        Ok(name) => { name },
+        Err(err) => { return Err(err); }
+      },
+      frames: ~[],
+      num_tags: 0,
+      tags: ~[],
+      surfaces: ~[],
+    };
+    /* Flags and skins are skipped. */
+    let num_frames = read_i32(data, 76);
+    let num_tags = read_i32(data, 80);
+    let num_surfaces = read_i32(data, 84);
+    if num_frames <= 0 || num_tags < 0 || num_surfaces < 0
+    { return Err(~"Invalid counts"); }
+    let (num_frames, num_tags) = (num_frames as uint, num_tags as uint);
+    let num_frame_tags = match num_frames.checked_mul(&num_tags)
+    {
+      Some(count) => { count },
+      None => { return Err(~"Invalid tags section"); }
+    };
+    let frames_offset = match section(data, 0, read_u32(data, 92), num_frames, Frame_Size)
+    {
+      Some(offset) => { offset },
+      None => { return Err(~"Invalid frames section"); }
+    };
+    let tags_offset = match section(data, 0, read_u32(data, 96), num_frame_tags, Tag_Size)
+    {
+      Some(offset) => { offset },
+      None => { return Err(~"Invalid tags section"); }
+    };
+    log_debug!("Loading MD3 %s: %u frames, %u tags, %d surfaces", model.name,
+               num_frames, num_tags, num_surfaces as int);
+    log_push!();
+    let res = model.read_sections(data, num_frames, frames_offset, num_frame_tags, tags_offset,
+                                  num_surfaces as uint, read_u32(data, 100));
+    log_pop!();
+    model.num_tags = num_tags;
+
+    match res
+    {
+      Ok(()) => { Ok(model) },
+      Err(err) => { Err(err) }
+    }
+  }
+
+  fn read_sections(&mut self, data: &[u8], num_frames: uint, frames_offset: uint,
+                   num_frame_tags: uint, tags_offset: uint,
+                   num_surfaces: uint, surfaces_offset: u32) -> Result<(), ~str>
+  {
+    for i in range(0, num_frames)
+    {
+      let offset = frames_offset + (i * Frame_Size);
+      let name = match read_name(data, offset + 40, Max_Frame_Name)
+      {
+        Ok(name) => { name },
+        Err(err) => { return Err(err); }
+      };
+      self.frames.push(Frame { name: name, min: read_vec3(data, offset), max: read_vec3(data, offset + 12),
+                               origin: read_vec3(data, offset + 24), radius: read_f32(data, offset + 36) });
+    }
+
+    for i in range(0, num_frame_tags)
+    {
+      let offset = tags_offset + (i * Tag_Size);
+      let mut tag = Tag::new();
+      tag.name = match read_name(data, offset, Max_Name)
+      {
+        Ok(name) => { name },
+        Err(err) => { return Err(err); }
+      };
+      tag.origin = read_vec3(data, offset + 64);
+      for a in range(0, 3)
+      { tag.axes[a] = read_vec3(data, offset + 76 + (a * 12)); }
+      self.tags.push(tag);
+    }
+
+    /* Each surface says where the next one starts. */
+    let mut surface_start = surfaces_offset as uint;
+    for _ in range(0, num_surfaces)
+    {
+      let (surface, size) = match read_surface(data, surface_start, num_frames)
+      {
+        Ok(s) => { s },
+        Err(err) => { return Err(err); }
+      };
+      log_debug!("Surface %s: %u verts, %u triangles, texture %s", surface.name,
+                 surface.num_verts, surface.indices.len() / 3, surface.texture);
+      self.surfaces.push(surface);
+      surface_start = match surface_start.checked_add(&size)
+      {
+        Some(start) => { start },
+        None => { return Err(~"Invalid surface size"); }
+      };
+    }
+
+    Ok(())
+  }
+
+  /* The two frames around a fractional frame, which wraps, and
+   * how far between them it is. */
+  pub fn get_frames(&self, frame: f32) -> (uint, uint, f32)
+  {
+    let count = self.frames.len();
+    let floor = frame.floor();
+    let frame0 = ((floor as int % count as int) + count as int) as uint % count;
+    (frame0, (frame0 + 1) % count, frame - floor)
+  }
+
+  /* Bytes used by the frames and surfaces, including what's
+   * uploaded. Textures are counted separately. */
+  pub fn memory_usage(&self) -> uint
+  {
+    let mut total = sys::size_of::<Model>() + (self.frames.len() * sys::size_of::<Frame>()) +
+                    (self.tags.len() * sys::size_of::<Tag>());
+    for surface in self.surfaces.iter()
+    {
+      total += ((surface.verts.len() * sys::size_of::<Vertex>()) +
+                (surface.tex_coords.len() * sys::size_of::<math::Vec2f>()) +
+                (surface.indices.len() * sys::size_of::<u32>())) * 2;
+    }
+    total
+  }
+
+  /* Model space transform of a tag at a fractional frame. */
+  pub fn get_tag(&self, name: &str, frame: f32) -> Option<math::Mat4x4>
+  {
+    let index = match self.tags.slice(0, self.num_tags).iter().position(|t| t.name.as_slice() == name)
+    {
+      Some(i) => { i },
+      None => { return None; }
+    };
+    let (frame0, frame1, blend) = self.get_frames(frame);
+    let tag0 = &self.tags[(frame0 * self.num_tags) + index];
+    let tag1 = &self.tags[(frame1 * self.num_tags) + index];
+
+    let origin = tag0.origin + ((tag1.origin - tag0.origin) * blend);
+    let mut axes = [ math::Vec3f::zero(), ..3 ];
+    for i in range(0, 3)
+    {
+      axes[i] = tag0.axes[i] + ((tag1.axes[i] - tag0.axes[i]) * blend);
+      axes[i].normalize();
+    }
+    Some(new_transform(&origin, &axes))
+  }
+}
+
+/* Reads a surface, returning it and its size in bytes. Its
+ * sections are relative to its start. */
+fn read_surface(data: &[u8], start: uint, num_frames: uint) -> Result<(Surface, uint), ~str>
+{
+  if section(data, start, 0, 1, Surface_Size).is_none() ||
+     data.slice(start, start + Magic.len()) != Magic.as_bytes()
+  { return Err(~"Invalid surface"); }
+
+  let size = read_u32(data, start + 104) as uint;
+  if size < Surface_Size
+  { return Err(~"Invalid surface size"); }
+
+  let mut surface = Surface::new();
+  surface.name = match read_name(data, start + 4, Max_Name)
+  {
+    Ok(name) => { name },
+    Err(err) => { return Err(err); }
+  };
+  /* Flags are skipped. */
+  let surface_frames = read_i32(data, start + 72);
+  let num_shaders = read_i32(data, start + 76);
+  let num_verts = read_i32(data, start + 80);
+  let num_triangles = read_i32(data, start + 84);
+  if surface_frames as uint != num_frames || num_verts < 0 || num_triangles < 0 || num_shaders < 0
+  { return Err(fmt!("Surface %s has invalid counts", surface.name)); }
+  let (num_verts, num_triangles) = (num_verts as uint, num_triangles as uint);
+  surface.num_verts = num_verts;
+
+  let num_frame_verts = match num_frames.checked_mul(&num_verts)
+  {
+    Some(count) => { count },
+    None => { return Err(fmt!("Surface %s has invalid counts", surface.name)); }
+  };
+  let sections = ( section(data, start, read_u32(data, start + 88), num_triangles, Triangle_Size),
+                   section(data, start, read_u32(data, start + 92), if num_shaders > 0 { 1 } else { 0 }, Max_Name),
+                   section(data, start, read_u32(data, start + 96), num_verts, Tex_Coord_Size),
+                   section(data, start, read_u32(data, start + 100), num_frame_verts, Vertex_Size) );
+  let (triangles_offset, shaders_offset, coords_offset, verts_offset) = match sections
+  {
+    (Some(t), Some(s), Some(c), Some(v)) => { (t, s, c, v) },
+    _ => { return Err(fmt!("Surface %s has an invalid section", surface.name)); }
+  };
+
+  /* Only the first shader is used. */
+  if num_shaders > 0
+  {
+    surface.texture = match read_name(data, shaders_offset, Max_Name)
+    {
+      Ok(name) => { find_texture(name) },
+      Err(err) => { return Err(err); }
+    };
+  }
+
+  for i in range(0, num_triangles * 3)
+  {
+    let index = read_i32(data, triangles_offset + (i * 4));
+    if index < 0 || index as uint >= num_verts
+    { return Err(fmt!("Surface %s has an invalid triangle", surface.name)); }
+    surface.indices.push(index as u32);
+  }
+
+  for i in range(0, num_verts)
+  {
+    let offset = coords_offset + (i * Tex_Coord_Size);
+    surface.tex_coords.push(math::Vec2f::new(read_f32(data, offset), read_f32(data, offset + 4)));
+  }
+
+  for i in range(0, num_frame_verts)
+  {
+    let offset = verts_offset + (i * Vertex_Size);
+    let x = read_u16(data, offset) as i16 as f32 * Position_Scale;
+    let y = read_u16(data, offset + 2) as i16 as f32 * Position_Scale;
+    let z = read_u16(data, offset + 4) as i16 as f32 * Position_Scale;
+    let normal = read_u16(data, offset + 6);
+    surface.verts.push(Vertex { position: math::Vec3f::new(x, y, z), normal: decode_normal(normal) });
+  }
+
+  Ok((surface, size))
+}
+
+/* Normals are packed as latitude and longitude, a byte each. */
+fn decode_normal(packed: u16) -> math::Vec3f
+{
+  let lat = ((packed >> 8) & 0xFF) as f32 * (2.0 * f32::consts::pi / 255.0);
+  let lng = (packed & 0xFF) as f32 * (2.0 * f32::consts::pi / 255.0);
+  math::Vec3f::new(lat.cos() * lng.sin(), lat.sin() * lng.sin(), lng.cos())
+}
+
+/* Shaders name a texture, though its extension may be wrong;
+ * Q3 falls back to .jpg when a .tga is missing. */
+fn find_texture(name: ~str) -> ~str
+{
+  if name.len() == 0
+  { return ~""; }
+
+  let file = path::Path(Data_Directory + name);
+  let mut candidates = ~[file.clone()];
+  for ext in ["tga", "jpg", "png"].iter()
+  { candidates.push(file.with_filetype(*ext)); }
+
+  match candidates.iter().find(|c| os::path_exists(*c))
+  {
+    Some(found) => { found.to_str() },
+    None => { log_error!("Missing texture %s", name); ~"" }
+  }
+}
+
+/* Whether count items of the given size, from the offset, are in the data. */
+fn in_range(data: &[u8], offset: uint, count: uint, size: uint) -> bool
+{
+  match count.checked_mul(&size)
+  {
+    Some(bytes) => { offset <= data.len() && bytes <= data.len() - offset },
+    None => { false }
+  }
+}
+
+/* The absolute offset of a section, which is relative to start,
+ * if all of its items are in the data. */
+fn section(data: &[u8], start: uint, offset: u32, count: uint, size: uint) -> Option<uint>
+{
+  match start.checked_add(&(offset as uint))
+  {
+    Some(begin) if in_range(data, begin, count, size) => { Some(begin) },
+    _ => { None }
+  }
+}
+
+/* Fixed length, null padded. */
+fn read_name(data: &[u8], offset: uint, len: uint) -> Result<~str, ~str>
+{
+  let bytes = data.slice(offset, offset + len);
+  let end = bytes.iter().position(|b| *b == 0).unwrap_or(len);
+  if !str::is_utf8(bytes.slice(0, end))
+  { return Err(~"Invalid name"); }
+  Ok(str::from_bytes(bytes.slice(0, end)))
+}
+
+fn read_u16(data: &[u8], offset: uint) -> u16
+{ data[offset] as u16 | (data[offset + 1] as u16 << 8) }
+
+fn read_u32(data: &[u8], offset: uint) -> u32
+{
+  data[offset] as u32 | (data[offset + 1] as u32 << 8) |
+  (data[offset + 2] as u32 << 16) | (data[offset + 3] as u32 << 24)
+}
+
+fn read_i32(data: &[u8], offset: uint) -> i32
+{ read_u32(data, offset) as i32 }
+
+fn read_f32(data: &[u8], offset: uint) -> f32
+{ unsafe { cast::transmute(read_u32(data, offset)) } }
+
+fn read_vec3(data: &[u8], offset: uint) -> math::Vec3f
+{ math::Vec3f::new(read_f32(data, offset), read_f32(data, offset + 4), read_f32(data, offset + 8)) }
+
+#[cfg(test)]
+mod test
+{
+  use super::Model;
+
+  /* A two frame triangle, with a tag, raised in its second frame. */
+  static Triangle: &'static [u8] = include_bin!("../../../data/models/test/triangle.md3");
+
+  /* Where the surface's first triangle index is. */
+  static Triangle_Offset: uint = 620;
+
+  #[test]
+  fn valid()
+  {
+    let model = match Model::load_from_bytes(Triangle)
+    {
+      Ok(model) => { model },
+      Err(err) => { fail!(err); }
+    };
+    assert_eq!(model.frames.len(), 2);
+    assert_eq!(model.num_tags, 1);
+    assert_eq!(model.tags.len(), 2);
+    assert_eq!(model.tags[0].name, ~"tag_weapon");
+    assert_eq!(model.tags[1].origin.z, 1.0);
+
+    assert_eq!(model.surfaces.len(), 1);
+    let surface = &model.surfaces[0];
+    assert_eq!(surface.name, ~"triangle");
+    assert_eq!(surface.num_verts, 3);
+    assert_eq!(surface.verts.len(), 6);
+    assert_eq!(surface.tex_coords.len(), 3);
+    assert_eq!(surface.indices, ~[0, 1, 2]);
+    assert_eq!(surface.verts[1].position.x, 1.0);
+    assert_eq!(surface.verts[3].position.z, 1.0);
+  }
+
+  #[test]
+  fn truncated()
+  {
+    for len in range(0, Triangle.len())
+    {
+      if Model::load_from_bytes(Triangle.slice(0, len)).is_ok()
+      { fail!(fmt!("Loaded a model truncated to %u bytes", len)); }
+    }
+  }
+
+  #[test]
+  fn invalid_triangle()
+  {
+    let mut data = Triangle.to_owned();
+    data[Triangle_Offset] = 3;
+    match Model::load_from_bytes(data)
+    {
+      Ok(_) => { fail!("Loaded a triangle past the vertices"); },
+      Err(err) => { assert_eq!(err, ~"Surface triangle has an invalid triangle"); }
+    }
+  }
+}
@@ -0,0 +1,91 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md3/model_renderer.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Renders an MD3 model (which contains
+      multiple surfaces) at a fractional
+      frame, blending the frames around it.
+*/
+
+use gl2 = opengles::gl2;
+use gl;
+use math;
+use res;
+use super::{ Model, Surface_Renderer };
+
+#[path = "../../gl/check.rs"]
+mod check;
+
+static Vert_Shader: &'static str = "data/shaders/md3.vert";
+static Frag_Shader: &'static str = "data/shaders/md3.frag";
+
+struct Model_Renderer
+{
+  model: @Model,
+  surface_renderers: ~[Surface_Renderer],
+
+  shader: @mut gl::Shader,
+  proj_loc: gl2::GLint,
+  world_loc: gl2::GLint,
+  blend_loc: gl2::GLint,
+}
+
+impl Model_Renderer
+{
+  pub fn new(m: @Model) -> Model_Renderer
+  {
+    let mut mr = Model_Renderer
+    {
+      model: m,
+      surface_renderers: ~[],
+
+      shader: res::Manager::get().get_shader(Vert_Shader, Frag_Shader),
+      proj_loc: 0,
+      world_loc: 0,
+      blend_loc: 0,
+    };
+
+    mr.shader.bind();
+    mr.proj_loc = mr.shader.get_uniform_location("proj");
+    mr.world_loc = mr.shader.get_uniform_location("world");
+    mr.blend_loc = mr.shader.get_uniform_location("blend");
+    let tex0_loc = mr.shader.get_uniform_location("tex0");
+    mr.shader.update_uniform_i32(tex0_loc, 0);
+
+    for x in mr.model.surfaces.iter()
+    { mr.surface_renderers.push(Surface_Renderer::new(x)); }
+
+    mr
+  }
+
+  /* Gives the shader and textures back to the resource manager. */
+  pub fn release(&mut self)
+  {
+    res::Manager::get().release_shader(Vert_Shader, Frag_Shader);
+    for x in self.surface_renderers.mut_iter()
+    { x.release(); }
+  }
+
+  /* The world transform takes the model out of its (Z up) Q3 units. */
+  pub fn render(&self, world: &math::Mat4x4, frame: f32)
+  {
+    check!(gl2::front_face(gl2::CW));
+
+    let camera = gl::Camera::get_active();
+    let (frame0, frame1, blend) = self.model.get_frames(frame);
+    self.shader.bind();
+    self.shader.update_uniform_mat(self.proj_loc, &camera.projection);
+    self.shader.update_uniform_mat(self.world_loc, &(*world * camera.view));
+    self.shader.update_uniform_f32(self.blend_loc, blend);
+
+    for x in self.surface_renderers.iter()
+    { x.render(frame0, frame1); }
+
+    check!(gl2::front_face(gl2::CCW));
+  }
+}
+
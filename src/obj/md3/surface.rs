@@ -0,0 +1,54 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md3/surface.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A textured group of triangles, with
+      its vertices stored for every frame.
+*/
+
+use math;
+
+struct Vertex
+{
+  position: math::Vec3f,
+  normal: math::Vec3f,
+}
+
+struct Surface
+{
+  name: ~str,
+  texture: ~str, /* Empty if it couldn't be found. */
+
+  num_verts: uint,
+
+  /* Every frame's vertices, one frame after another. */
+  verts: ~[Vertex],
+  tex_coords: ~[math::Vec2f],
+  indices: ~[u32],
+}
+
+impl Surface
+{
+  pub fn new() -> Surface
+  {
+    Surface
+    {
+      name: ~"",
+      texture: ~"",
+
+      num_verts: 0,
+
+      verts: ~[],
+      tex_coords: ~[],
+      indices: ~[],
+    }
+  }
+
+  pub fn get_frame<'a>(&'a self, frame: uint) -> &'a [Vertex]
+  { self.verts.slice(frame * self.num_verts, (frame + 1) * self.num_verts) }
+}
+
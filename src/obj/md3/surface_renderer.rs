@@ -0,0 +1,132 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md3/surface_renderer.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Renders an MD3 surface. Every frame
+      is uploaded once; the two frames
+      being blended are bound as separate
+      attributes and mixed in md3.vert.
+*/
+
+use std::sys;
+use gl2 = opengles::gl2;
+use gl;
+use math;
+use res;
+use super::{ Surface, Vertex };
+
+#[path = "../../gl/check.rs"]
+mod check;
+
+struct Surface_Renderer
+{
+  vao: gl2::GLuint,
+  vert_vbo: gl2::GLuint,
+  tex_vbo: gl2::GLuint,
+  ibo: gl2::GLuint,
+  num_verts: uint,
+  index_count: i32,
+
+  texture: Option<@gl::Texture>,
+}
+
+impl Surface_Renderer
+{
+  pub fn new(surface: &Surface) -> Surface_Renderer
+  {
+    let mut sr = Surface_Renderer
+    {
+      vao: 0,
+      vert_vbo: 0,
+      tex_vbo: 0,
+      ibo: 0,
+      num_verts: surface.num_verts,
+      index_count: surface.indices.len() as i32,
+
+      texture: None,
+    };
+
+    if surface.texture.len() > 0
+    { sr.texture = Some(res::Manager::get().get_texture(surface.texture)); }
+
+    let name = check!(gl2::gen_vertex_arrays(1));
+    assert!(name.len() == 1);
+    sr.vao = name[0];
+
+    let name = check!(gl2::gen_buffers(3));
+    assert!(name.len() == 3);
+    sr.vert_vbo = name[0];
+    sr.tex_vbo = name[1];
+    sr.ibo = name[2];
+
+    check!(gl2::bind_vertex_array(sr.vao));
+
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, sr.vert_vbo));
+    check!(gl2::buffer_data(gl2::ARRAY_BUFFER, surface.verts, gl2::STATIC_DRAW));
+
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, sr.tex_vbo));
+    check!(gl2::buffer_data(gl2::ARRAY_BUFFER, surface.tex_coords, gl2::STATIC_DRAW));
+
+    check!(gl2::bind_buffer(gl2::ELEMENT_ARRAY_BUFFER, sr.ibo));
+    check!(gl2::buffer_data(gl2::ELEMENT_ARRAY_BUFFER, surface.indices, gl2::STATIC_DRAW));
+
+    check!(gl2::bind_vertex_array(0));
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, 0));
+
+    sr
+  }
+
+  /* Gives the texture back to the resource manager. */
+  pub fn release(&mut self)
+  {
+    match self.texture
+    {
+      Some(tex) => { res::Manager::get().release_texture(tex.filename); },
+      None => { }
+    }
+    self.texture = None;
+  }
+
+  pub fn render(&self, frame0: uint, frame1: uint)
+  {
+    match self.texture
+    {
+      Some(tex) => { tex.bind(gl2::TEXTURE_2D); },
+      None => { }
+    }
+
+    check!(gl2::bind_vertex_array(self.vao));
+
+    /* Position and normal of each frame. */
+    let stride = sys::size_of::<Vertex>();
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.vert_vbo));
+    for (i, frame) in [frame0, frame1].iter().enumerate()
+    {
+      let offset = *frame * self.num_verts * stride;
+      let loc = (i * 2) as u32;
+      check!(gl2::enable_vertex_attrib_array(loc));
+      check!(gl2::vertex_attrib_pointer_f32(loc, 3, false, stride as i32, offset as u32));
+      check!(gl2::enable_vertex_attrib_array(loc + 1));
+      check!(gl2::vertex_attrib_pointer_f32(loc + 1, 3, false, stride as i32,
+                                             (offset + sys::size_of::<math::Vec3f>()) as u32));
+    }
+
+    check!(gl2::enable_vertex_attrib_array(4));
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.tex_vbo));
+    check!(gl2::vertex_attrib_pointer_f32(4, 2, false, sys::size_of::<math::Vec2f>() as i32, 0));
+
+    check!(gl2::bind_buffer(gl2::ELEMENT_ARRAY_BUFFER, self.ibo));
+    check!(gl2::draw_elements(gl2::TRIANGLES, self.index_count, gl2::UNSIGNED_INT, None));
+
+    for i in range(0, 5u32)
+    { check!(gl2::disable_vertex_attrib_array(i)); }
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, 0));
+    check!(gl2::bind_buffer(gl2::ELEMENT_ARRAY_BUFFER, 0));
+    check!(gl2::bind_vertex_array(0));
+  }
+}
+
@@ -0,0 +1,38 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md3/tag.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A named point, with an orientation,
+      at which other models are attached
+      (such as a weapon to a hand).
+*/
+
+use math;
+
+#[deriving(Clone)]
+struct Tag
+{
+  name: ~str,
+  origin: math::Vec3f,
+  axes: [math::Vec3f, ..3], /* Forward, left, up. */
+}
+
+impl Tag
+{
+  pub fn new() -> Tag
+  {
+    Tag
+    {
+      name: ~"",
+      origin: math::Vec3f::zero(),
+      axes: [ math::Vec3f::new(1.0, 0.0, 0.0),
+              math::Vec3f::new(0.0, 1.0, 0.0),
+              math::Vec3f::new(0.0, 0.0, 1.0) ],
+    }
+  }
+}
+
@@ -0,0 +1,506 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md5/animation.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Loads, parses, and samples MD5
+      skeletal animations. Each frame only
+      stores the joint components which
+      change from the base frame; these
+      are built into full skeletons up
+      front, and blended between as the
+      animation plays. Animations are
+      shared, so playback time is kept
+      by whoever plays them.
+*/
+
+use std::{ vec, cmp };
+use super::Joint;
+use super::parser::{ Parser, Parse_Error };
+use math;
+use util::Log;
+
+#[macro_escape]
+#[path = "../../util/log_macros.rs"]
+mod log_macros;
+
+/* Which components of a joint are animated. */
+static Position_X: i32 = 1;
+static Position_Y: i32 = 2;
+static Position_Z: i32 = 4;
+static Orientation_X: i32 = 8;
+static Orientation_Y: i32 = 16;
+static Orientation_Z: i32 = 32;
+static Components: [i32, ..6] = [ Position_X, Position_Y, Position_Z, Orientation_X, Orientation_Y, Orientation_Z ];
+static All_Components: i32 = 63;
+
+static Version: i32 = 10;
+
+struct Joint_Info
+{
+  name: ~str,
+  parent: i32,
+  flags: i32,
+  start_index: i32, /* Of the joint's first animated component in each frame. */
+}
+
+struct Bound
+{
+  min: math::Vec3f,
+  max: math::Vec3f,
+}
+
+struct Base_Frame
+{
+  position: math::Vec3f,
+  orientation: math::Quaternion,
+}
+
+struct Animation
+{
+  version: i32,
+  num_frames: i32,
+  num_joints: i32,
+  frame_rate: i32,
+  num_animated_components: i32,
+
+  hierarchy: ~[Joint_Info],
+  bounds: ~[Bound],
+  base_frames: ~[Base_Frame],
+  frames: ~[~[f32]],
+
+  /* One full, model space skeleton per frame. */
+  skeletons: ~[~[Joint]],
+
+  frame_duration: f32, /* In seconds. */
+  duration: f32,
+}
+
+impl Animation
+{
+  pub fn new(file: &str) -> Result<Animation, Parse_Error>
+  {
+    let mut anim = Animation::new_empty();
+    let mut parser = read!(Parser::new(file));
+    log_debug!("Parsing animation %s", file);
+    log_push!();
+    let res = anim.load(&mut parser);
+    log_pop!();
+    read!(res);
+
+    let skeletons = do vec::from_fn(anim.frames.len()) |i|
+    { anim.build_skeleton(i) };
+    anim.skeletons = skeletons;
+    anim.frame_duration = 1.0 / anim.frame_rate as f32;
+    anim.duration = anim.frame_duration * anim.num_frames as f32;
+    log_debug!("Animation %s is %f seconds long", file, anim.duration as float);
+
+    Ok(anim)
+  }
+
+  fn new_empty() -> Animation
+  {
+    Animation
+    {
+      version: 0,
+      num_frames: 0,
+      num_joints: 0,
+      frame_rate: 0,
+      num_animated_components: 0,
+
+      hierarchy: ~[],
+      bounds: ~[],
+      base_frames: ~[],
+      frames: ~[],
+
+      skeletons: ~[],
+
+      frame_duration: 0.0,
+      duration: 0.0,
+    }
+  }
+
+  /* From model space skeletons that are already built, such as
+   * those of IQM files. Bounds are optional, but if given, they're
+   * one per frame. */
+  pub fn new_from_skeletons(skeletons: ~[~[Joint]], bounds: ~[(math::Vec3f, math::Vec3f)],
+                            frame_rate: f32) -> Animation
+  {
+    let hierarchy = do skeletons[0].map |joint|
+    { Joint_Info { name: joint.name.clone(), parent: joint.parent, flags: 0, start_index: 0 } };
+    let frame_duration = 1.0 / frame_rate;
+
+    Animation
+    {
+      version: 0,
+      num_frames: skeletons.len() as i32,
+      num_joints: hierarchy.len() as i32,
+      frame_rate: frame_rate.round() as i32,
+      num_animated_components: 0,
+
+      hierarchy: hierarchy,
+      bounds: bounds.map(|&(min, max)| Bound { min: min, max: max }),
+      base_frames: ~[],
+      frames: ~[],
+
+      duration: frame_duration * skeletons.len() as f32,
+      frame_duration: frame_duration,
+      skeletons: skeletons,
+    }
+  }
+
+  /* The counts come first, since the sections are sized by them. */
+  fn load(&mut self, parser: &mut Parser) -> Result<(), Parse_Error>
+  {
+    read!(parser.expect("MD5Version"));
+    self.version = read!(parser.read_i32("version"));
+    if self.version != Version
+    { return Err(parser.error(fmt!("Unsupported version %d (expected %d)", self.version as int, Version as int))); }
+    log_debug!("Animation version: %d", self.version as int);
+
+    while !parser.is_done()
+    {
+      let token = read!(parser.next("a section"));
+      let is_count = match token.text
+      {
+        ~"numFrames" | ~"numJoints" | ~"frameRate" | ~"numAnimatedComponents" => { true },
+        _ => { false }
+      };
+      if is_count && (self.hierarchy.len() > 0 || self.frames.len() > 0)
+      { return Err(parser.error_at(&token, fmt!("%s needs to come before the joints and frames", token.text))); }
+
+      match token.text
+      {
+        ~"commandline" =>
+        { read!(parser.read_string("command line")); }
+        ~"numFrames" =>
+        {
+          self.num_frames = read!(read_count(parser, "frame count", 1));
+          log_debug!("Animation frames: %d", self.num_frames as int);
+        }
+        ~"numJoints" =>
+        {
+          self.num_joints = read!(read_count(parser, "joint count", 1));
+          log_debug!("Animation joints: %d", self.num_joints as int);
+        }
+        ~"frameRate" =>
+        { self.frame_rate = read!(read_count(parser, "frame rate", 1)); }
+        ~"numAnimatedComponents" =>
+        { self.num_animated_components = read!(read_count(parser, "animated component count", 0)); }
+        ~"hierarchy" =>
+        {
+          if self.num_joints == 0 || self.hierarchy.len() > 0
+          { return Err(parser.error_at(&token, ~"The hierarchy needs to come once, after numJoints")); }
+          read!(self.read_hierarchy(parser));
+        }
+        ~"bounds" =>
+        {
+          if self.num_frames == 0 || self.bounds.len() > 0
+          { return Err(parser.error_at(&token, ~"Bounds need to come once, after numFrames")); }
+          read!(parser.expect("{"));
+          for _ in range(0, self.num_frames)
+          {
+            let min = read!(parser.read_vec3("bounds minimum"));
+            let max = read!(parser.read_vec3("bounds maximum"));
+            self.bounds.push(Bound { min: min, max: max });
+          }
+          read!(parser.expect("}"));
+        }
+        ~"baseframe" =>
+        {
+          if self.num_joints == 0 || self.base_frames.len() > 0
+          { return Err(parser.error_at(&token, ~"The base frame needs to come once, after numJoints")); }
+          read!(parser.expect("{"));
+          for _ in range(0, self.num_joints)
+          {
+            let position = read!(parser.read_vec3("base frame position"));
+            let orientation = read!(parser.read_vec3("base frame orientation"));
+            self.base_frames.push(Base_Frame
+            {
+              position: position,
+              orientation: math::Quaternion::new(orientation.x, orientation.y, orientation.z, 0.0),
+            });
+          }
+          read!(parser.expect("}"));
+        }
+        ~"frame" =>
+        {
+          if self.num_frames == 0
+          { return Err(parser.error_at(&token, ~"Frames need to come after numFrames")); }
+
+          /* Frames are expected in order. */
+          let index = read!(parser.read_i32("frame index"));
+          if index < 0 || index as uint != self.frames.len() || index >= self.num_frames
+          { return Err(parser.error(fmt!("Expected frame %u but found %d", self.frames.len(), index as int))); }
+
+          read!(parser.expect("{"));
+          let mut frame = vec::with_capacity(self.num_animated_components as uint);
+          for _ in range(0, self.num_animated_components)
+          { frame.push(read!(parser.read_f32("frame component"))); }
+          read!(parser.expect("}"));
+          self.frames.push(frame);
+        }
+        _ =>
+        { return Err(parser.error_at(&token, fmt!("Unexpected '%s'", token.text))); }
+      }
+    }
+
+    if self.num_frames == 0 || self.frame_rate == 0 || self.hierarchy.len() == 0 || self.base_frames.len() == 0
+    { return Err(parser.error(~"Missing counts, hierarchy, or base frame")); }
+    if self.frames.len() != self.num_frames as uint
+    { return Err(parser.error(fmt!("Found %u of the %d frames declared", self.frames.len(), self.num_frames as int))); }
+
+    Ok(())
+  }
+
+  /* { "name" parent flags start_index ... } */
+  fn read_hierarchy(&mut self, parser: &mut Parser) -> Result<(), Parse_Error>
+  {
+    read!(parser.expect("{"));
+    for i in range(0, self.num_joints)
+    {
+      let name = read!(parser.read_string("joint name"));
+
+      /* Skeletons are built in order, so parents come first. */
+      let parent = read!(parser.read_i32("joint parent"));
+      if parent < -1 || parent >= i
+      { return Err(parser.error(fmt!("Joint %s has an invalid parent %d", name, parent as int))); }
+
+      /* Joints can't read outside of their frame's components. */
+      let flags = read!(parser.read_i32("joint flags"));
+      let start_index = read!(parser.read_i32("joint start index"));
+      let count = Components.iter().count(|flag| (flags & *flag) != 0) as i32;
+      if flags < 0 || flags > All_Components || start_index < 0 ||
+         start_index + count > self.num_animated_components
+      { return Err(parser.error(fmt!("Joint %s animates components outside of the %d", name,
+                                     self.num_animated_components as int))); }
+
+      self.hierarchy.push(Joint_Info { name: name, parent: parent, flags: flags, start_index: start_index });
+    }
+    read!(parser.expect("}"));
+
+    Ok(())
+  }
+
+  /* Applies a frame to the base frame and moves every joint
+   * into model space. Parents always precede their children. */
+  fn build_skeleton(&self, frame_index: uint) -> ~[Joint]
+  {
+    let frame = &self.frames[frame_index];
+    let mut skeleton: ~[Joint] = vec::with_capacity(self.hierarchy.len());
+    for (i, info) in self.hierarchy.iter().enumerate()
+    {
+      let base = &self.base_frames[i];
+      let mut values = [ base.position.x, base.position.y, base.position.z,
+                         base.orientation.x, base.orientation.y, base.orientation.z ];
+
+      /* Animated components are packed in order. */
+      let mut component = info.start_index as uint;
+      for (k, flag) in Components.iter().enumerate()
+      {
+        if (info.flags & *flag) != 0
+        {
+          values[k] = frame[component];
+          component += 1;
+        }
+      }
+
+      let mut position = math::Vec3f::new(values[0], values[1], values[2]);
+      let mut orientation = math::Quaternion::new(values[3], values[4], values[5], 0.0);
+      orientation.compute_w();
+
+      if info.parent >= 0
+      {
+        let parent = &skeleton[info.parent];
+        position = parent.position + parent.orientation.rotate_vec(&position);
+        orientation = parent.orientation * orientation;
+        orientation.normalize();
+      }
+
+      skeleton.push(Joint
+      {
+        name: info.name.clone(),
+        parent: info.parent,
+        position: position,
+        orientation: orientation,
+      });
+    }
+    skeleton
+  }
+
+  /* Whether the animation's skeleton lines up with the joints. */
+  pub fn matches(&self, joints: &[Joint]) -> bool
+  {
+    joints.len() == self.hierarchy.len() &&
+    joints.iter().zip(self.hierarchy.iter()).all(|(joint, info)|
+    { joint.name == info.name && joint.parent == info.parent })
+  }
+
+  /* Brings a playback time into the animation. Looping animations
+   * wrap; others hold their last frame once finished. */
+  pub fn wrap_time(&self, time: f32, looping: bool) -> f32
+  {
+    let mut time = time;
+    if looping
+    {
+      while time >= self.duration
+      { time -= self.duration; }
+      while time < 0.0
+      { time += self.duration; }
+      time
+    }
+    else
+    {
+      /* The last frame isn't blended back into the first. */
+      let end = self.frame_duration * (self.num_frames - 1) as f32;
+      cmp::min(cmp::max(time, 0.0), end)
+    }
+  }
+
+  /* Blends the two frames nearest to the (wrapped) time. */
+  pub fn sample(&self, time: f32, looping: bool) -> ~[Joint]
+  {
+    let frame_time = time / self.frame_duration;
+    let frame0 = (frame_time.floor() as uint) % self.skeletons.len();
+    let frame1 = if looping
+    { (frame0 + 1) % self.skeletons.len() }
+    else
+    { cmp::min(frame0 + 1, self.skeletons.len() - 1) };
+    let blend = frame_time - frame_time.floor();
+
+    interpolate(self.skeletons[frame0], self.skeletons[frame1], blend)
+  }
+
+  /* Whether a non-looping animation has reached its last frame. */
+  pub fn is_finished(&self, time: f32, looping: bool) -> bool
+  { !looping && time >= self.frame_duration * (self.num_frames - 1) as f32 }
+
+  /* Bounds of the model at the (wrapped) time. */
+  pub fn get_bounds(&self, time: f32) -> (math::Vec3f, math::Vec3f)
+  {
+    if self.bounds.len() == 0
+    { return (math::Vec3f::zero(), math::Vec3f::zero()); }
+
+    let frame = ((time / self.frame_duration) as uint) % self.bounds.len();
+    (self.bounds[frame].min, self.bounds[frame].max)
+  }
+}
+
+/* A count in the header, which can't be less than the minimum. */
+fn read_count(parser: &mut Parser, what: &str, min: i32) -> Result<i32, Parse_Error>
+{
+  let count = read!(parser.read_i32(what));
+  if count < min
+  { return Err(parser.error(fmt!("The %s needs to be at least %d", what, min as int))); }
+  Ok(count)
+}
+
+/* Blends any number of weighted skeletons of the same hierarchy.
+ * Each skeleton is slerped in by its share of the weight so far,
+ * which weighs every skeleton fairly regardless of order. */
+pub fn blend(skeletons: &[(&[Joint], f32)]) -> ~[Joint]
+{
+  let (first, first_weight) = skeletons[0];
+  let mut result = first.to_owned();
+  let mut total = first_weight;
+  for &(skeleton, weight) in skeletons.slice_from(1).iter()
+  {
+    if weight <= 0.0
+    { loop; }
+
+    total += weight;
+    result = interpolate(result, skeleton, weight / total);
+  }
+  result
+}
+
+/* Blends between two skeletons of the same hierarchy. */
+pub fn interpolate(skel0: &[Joint], skel1: &[Joint], blend: f32) -> ~[Joint]
+{
+  do vec::from_fn(skel0.len()) |i|
+  {
+    let (j0, j1) = (&skel0[i], &skel1[i]);
+    Joint
+    {
+      name: j0.name.clone(),
+      parent: j0.parent,
+      position: j0.position + ((j1.position - j0.position) * blend),
+      orientation: j0.orientation.slerp(&j1.orientation, blend),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test
+{
+  use super::Animation;
+  use super::super::parser::{ Parse_Error, test_support };
+  use super::super::parser::test_support::error_position;
+
+  /* Two joints, with the child's X position animated; the
+   * child's hierarchy line is given. */
+  fn fixture(child: &str) -> ~str
+  {
+    ~"MD5Version 10\n" +
+    "numFrames 2\n" +
+    "numJoints 2\n" +
+    "frameRate 24\n" +
+    "numAnimatedComponents 1\n" +
+    "hierarchy {\n" +
+    "  \"root\" -1 0 0\n" +
+    "  " + child + "\n" +
+    "}\n" +
+    "baseframe {\n" +
+    "  ( 0 0 0 ) ( 0 0 0 )\n" +
+    "  ( 0 0 1 ) ( 0 0 0 )\n" +
+    "}\n" +
+    "frame 0 { 0.0 }\n" +
+    "frame 1 { 2.0 }\n"
+  }
+
+  fn load(text: &str) -> Result<Animation, Parse_Error>
+  { test_support::load("test.md5anim", text, Animation::new_empty(), |item, parser| item.load(parser)) }
+
+  #[test]
+  fn valid()
+  {
+    match load(fixture("\"child\" 0 1 0"))
+    {
+      Ok(anim) =>
+      {
+        assert_eq!(anim.hierarchy.len(), 2);
+        assert_eq!(anim.build_skeleton(1)[1].position.x, 2.0);
+      },
+      Err(err) => { fail!(err.to_str()); }
+    }
+  }
+
+  /* Skeletons are built in order, so a parent after its child can't be used. */
+  #[test]
+  fn parent_after_child()
+  {
+    assert_eq!(error_position(load(fixture("\"child\" 1 1 0"))), (8, 11));
+    assert_eq!(error_position(load(fixture("\"child\" 2 1 0"))), (8, 11));
+  }
+
+  #[test]
+  fn components_out_of_range()
+  { assert_eq!(error_position(load(fixture("\"child\" 0 3 0"))), (8, 15)); }
+
+  #[test]
+  fn frame_out_of_order()
+  {
+    let text = fixture("\"child\" 0 1 0").replace("frame 1 {", "frame 2 {");
+    assert_eq!(error_position(load(text)), (15, 7));
+  }
+
+  #[test]
+  fn truncated()
+  {
+    let text = fixture("\"child\" 0 1 0").replace("frame 1 { 2.0 }\n", "");
+    assert_eq!(error_position(load(text)), (14, 15));
+  }
+}
@@ -0,0 +1,358 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md5/animator.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A small animation state machine for
+      MD5 models. Each state plays one
+      animation; switching states crossfades
+      from every active animation into the
+      new one, so states can change again
+      mid-fade without popping. Animations
+      are shared between animators; each
+      keeps its own playback times.
+*/
+
+use super::{ Joint, Animation };
+use super::animation;
+use util::Log;
+
+#[macro_escape]
+#[path = "../../util/log_macros.rs"]
+mod log_macros;
+
+/* Crossfade duration (in seconds) when no transition is specified. */
+static Default_Fade: f32 = 0.2;
+
+#[deriving(Clone)]
+struct Animation_State
+{
+  name: ~str,
+  animation: @Animation,
+
+  /* Non-looping states move here once they finish. */
+  next: Option<~str>,
+}
+
+#[deriving(Clone)]
+struct Transition
+{
+  from: ~str, /* Empty for any state. */
+  to: ~str,
+  duration: f32,
+}
+
+/* A state that currently contributes to the pose. */
+#[deriving(Clone)]
+struct Active
+{
+  state: uint,
+  weight: f32,
+  time: f32, /* Wrapped into the state's animation. */
+}
+
+#[deriving(Clone)]
+pub struct Animator
+{
+  states: ~[Animation_State],
+  transitions: ~[Transition],
+
+  /* The last is the current state; the rest are fading out. */
+  active: ~[Active],
+  fade_duration: f32,
+
+  skeleton: ~[Joint],
+}
+
+impl Animator
+{
+  pub fn new() -> Animator
+  {
+    Animator
+    {
+      states: ~[],
+      transitions: ~[],
+
+      active: ~[],
+      fade_duration: 0.0,
+
+      skeleton: ~[],
+    }
+  }
+
+  /* The first state added becomes the current state. A state
+   * with a next state plays once, then crossfades into it. */
+  pub fn add_state(&mut self, name: &str, anim: @Animation, next: Option<~str>)
+  {
+    match self.find_state(name)
+    {
+      Some(i) => { self.states[i] = Animation_State { name: name.to_owned(), animation: anim, next: next }; }
+      None =>
+      {
+        self.states.push(Animation_State { name: name.to_owned(), animation: anim, next: next });
+        if self.active.len() == 0
+        {
+          self.active.push(Active { state: 0, weight: 1.0, time: 0.0 });
+          self.skeleton = self.states[0].animation.skeletons[0].clone();
+        }
+      }
+    }
+  }
+
+  /* How long to crossfade between two states. An empty from
+   * applies to changes from any state. */
+  pub fn add_transition(&mut self, from: &str, to: &str, duration: f32)
+  {
+    self.transitions.retain(|t| !(t.from.as_slice() == from && t.to.as_slice() == to));
+    self.transitions.push(Transition { from: from.to_owned(), to: to.to_owned(), duration: duration });
+  }
+
+  pub fn get_state<'a>(&'a self) -> Option<&'a str>
+  {
+    match self.active.last_opt()
+    {
+      Some(active) => { Some(self.states[active.state].name.as_slice()) },
+      None => { None }
+    }
+  }
+
+  /* Crossfades into the state. Changing to the current state
+   * does nothing. */
+  pub fn set_state(&mut self, name: &str) -> Result<(), ~str>
+  {
+    let to = match self.find_state(name)
+    {
+      Some(i) => { i },
+      None => { return Err(fmt!("Unknown animation state %s", name)); }
+    };
+    let from = match self.active.last_opt()
+    {
+      Some(active) => { active.state },
+      None => { return Err(~"There are no animation states"); }
+    };
+    if from == to
+    { return Ok(()); }
+
+    /* Specific transitions win over ones from any state. */
+    let from_name = self.states[from].name.clone();
+    let duration = self.transitions.iter().find(|t| t.from == from_name && t.to.as_slice() == name)
+               .or(self.transitions.iter().find(|t| t.from.len() == 0 && t.to.as_slice() == name))
+               .map_default(Default_Fade, |t| t.duration);
+    log_debug!("Animation %s -> %s over %f seconds", from_name, name, duration as float);
+
+    /* A state that's fading out fades back in from where it is. */
+    let active = match self.active.iter().position(|a| a.state == to)
+    {
+      Some(i) => { self.active.remove(i) },
+      None => { Active { state: to, weight: 0.0, time: 0.0 } }
+    };
+    self.active.push(active);
+    self.fade_duration = duration;
+    if duration <= 0.0
+    { self.finish_fade(); }
+
+    Ok(())
+  }
+
+  /* Advances every active animation and blends them into the skeleton. */
+  pub fn update(&mut self, delta: f32) /* dt is in terms of seconds. */
+  {
+    if self.active.len() == 0
+    { return; }
+
+    /* The current state gains weight; the others lose it proportionally. */
+    if self.active.len() > 1
+    {
+      let last = self.active.len() - 1;
+      let old = self.active[last].weight;
+      let new = if self.fade_duration > 0.0
+      { old + (delta / self.fade_duration) } else { 1.0 };
+      if new >= 1.0
+      { self.finish_fade(); }
+      else
+      {
+        let scale = (1.0 - new) / (1.0 - old);
+        for active in self.active.mut_iter()
+        { active.weight *= scale; }
+        self.active[last].weight = new;
+      }
+    }
+
+    /* States without a next state loop. */
+    for active in self.active.mut_iter()
+    {
+      let state = &self.states[active.state];
+      active.time = state.animation.wrap_time(active.time + delta, state.next.is_none());
+    }
+
+    let poses = do self.active.map |active|
+    {
+      let state = &self.states[active.state];
+      state.animation.sample(active.time, state.next.is_none())
+    };
+    let skeleton = animation::blend(do poses.iter().zip(self.active.iter()).map |(pose, active)|
+    { (pose.as_slice(), active.weight) }.collect::<~[(&[Joint], f32)]>());
+    self.skeleton = skeleton;
+
+    /* Finished one-shot states hand over to their next state. */
+    let next =
+    {
+      let active = self.active.last();
+      let current = &self.states[active.state];
+      if current.animation.is_finished(active.time, current.next.is_none())
+      { current.next.clone() }
+      else
+      { None }
+    };
+    match next
+    {
+      Some(next) =>
+      {
+        match self.set_state(next)
+        {
+          Ok(()) => { },
+          Err(err) => { log_error!("%s", err); }
+        }
+      }
+      None => { }
+    }
+  }
+
+  /* Skeletons of every state's animation need to match the joints. */
+  pub fn matches(&self, joints: &[Joint]) -> bool
+  { self.states.iter().all(|state| state.animation.matches(joints)) }
+
+  fn finish_fade(&mut self)
+  {
+    let mut current = self.active.pop();
+    current.weight = 1.0;
+    self.active = ~[current];
+  }
+
+  fn find_state(&self, name: &str) -> Option<uint>
+  { self.states.iter().position(|state| state.name.as_slice() == name) }
+}
+
+#[cfg(test)]
+mod test
+{
+  use std::vec;
+  use math;
+  use super::Animator;
+  use super::super::{ Joint, Animation };
+
+  /* A single joint held at x for the given number of frames, at ten a second. */
+  fn animation(x: f32, frames: uint) -> @Animation
+  {
+    let mut joint = Joint::new();
+    joint.parent = -1;
+    joint.position = math::Vec3f::new(x, 0.0, 0.0);
+    joint.orientation = math::Quaternion::new(0.0, 0.0, 0.0, 1.0);
+    @Animation::new_from_skeletons(vec::from_elem(frames, ~[joint]), ~[], 10.0)
+  }
+
+  /* Looping states a, b, and c, with the joint at 0, 1, and 2. */
+  fn fixture() -> Animator
+  {
+    let mut animator = Animator::new();
+    animator.add_state("a", animation(0.0, 2), None);
+    animator.add_state("b", animation(1.0, 2), None);
+    animator.add_state("c", animation(2.0, 2), None);
+    animator
+  }
+
+  fn close(a: f32, b: f32) -> bool
+  { (a - b).abs() < 0.0001 }
+
+  fn total_weight(animator: &Animator) -> f32
+  { animator.active.iter().fold(0.0f32, |total, active| total + active.weight) }
+
+  #[test]
+  fn fade_weights()
+  {
+    let mut animator = fixture();
+    animator.add_transition("", "b", 1.0);
+    assert_eq!(animator.set_state("b"), Ok(()));
+
+    animator.update(0.25);
+    assert!(close(animator.active[1].weight, 0.25));
+    assert!(close(total_weight(&animator), 1.0));
+    assert!(close(animator.skeleton[0].position.x, 0.25));
+
+    /* Changing again mid-fade keeps the total. */
+    assert_eq!(animator.set_state("c"), Ok(()));
+    animator.update(0.1);
+    assert_eq!(animator.active.len(), 3);
+    assert!(close(animator.active[2].weight, 0.5));
+    assert!(close(total_weight(&animator), 1.0));
+  }
+
+  #[test]
+  fn finish_fade()
+  {
+    let mut animator = fixture();
+    animator.add_transition("", "b", 0.5);
+    assert_eq!(animator.set_state("b"), Ok(()));
+    animator.update(0.3);
+    animator.update(0.3);
+
+    assert_eq!(animator.active.len(), 1);
+    assert_eq!(animator.active[0].weight, 1.0);
+    assert_eq!(animator.get_state(), Some("b"));
+  }
+
+  #[test]
+  fn specific_transition()
+  {
+    let mut animator = fixture();
+    animator.add_transition("", "b", 1.0);
+    animator.add_transition("a", "b", 0.5);
+    assert_eq!(animator.set_state("b"), Ok(()));
+    assert_eq!(animator.fade_duration, 0.5);
+
+    /* Other states still use the one from any state. */
+    assert_eq!(animator.set_state("c"), Ok(()));
+    assert_eq!(animator.set_state("b"), Ok(()));
+    assert_eq!(animator.fade_duration, 1.0);
+  }
+
+  #[test]
+  fn fade_back_in()
+  {
+    let mut animator = fixture();
+    animator.add_transition("", "b", 1.0);
+    animator.add_transition("", "a", 1.0);
+    assert_eq!(animator.set_state("b"), Ok(()));
+    animator.update(0.25);
+
+    /* a was fading out at 0.75, and continues from there. */
+    assert_eq!(animator.set_state("a"), Ok(()));
+    assert_eq!(animator.active.len(), 2);
+    assert_eq!(animator.active[1].state, 0);
+    assert!(close(animator.active[1].weight, 0.75));
+
+    animator.update(0.25);
+    assert_eq!(animator.active.len(), 1);
+    assert_eq!(animator.get_state(), Some("a"));
+  }
+
+  #[test]
+  fn one_shot()
+  {
+    let mut animator = fixture();
+    animator.add_state("wave", animation(3.0, 3), Some(~"a"));
+    animator.add_transition("", "wave", 0.0);
+    assert_eq!(animator.set_state("wave"), Ok(()));
+    assert_eq!(animator.active.len(), 1);
+
+    /* The last of its three frames is at 0.2 seconds. */
+    animator.update(0.1);
+    assert_eq!(animator.get_state(), Some("wave"));
+    animator.update(0.15);
+    assert_eq!(animator.get_state(), Some("a"));
+    assert_eq!(animator.active.len(), 2);
+  }
+}
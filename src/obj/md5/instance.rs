@@ -0,0 +1,109 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md5/instance.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A placed, animating MD5 model. Many
+      instances can share one model (and
+      its renderer), each with its own
+      transform and animation state.
+*/
+
+use math;
+use attachment::{ Attachable, Attachment, new_transform };
+use super::{ Model, Animator, Joint };
+
+struct Instance
+{
+  model: @Model,
+  animator: Animator,
+
+  position: math::Vec3f,
+  orientation: math::Quaternion,
+  scale: f32,
+
+  /* Computed on update, from the above or the attachment. */
+  local_to_world: math::Mat4x4,
+  attachment: Option<Attachment>,
+}
+
+impl Instance
+{
+  pub fn new(model: @Model) -> Instance
+  {
+    Instance
+    {
+      model: model,
+      animator: model.animator.clone(),
+
+      position: math::Vec3f::zero(),
+      orientation: math::Quaternion::new(0.0, 0.0, 0.0, 1.0),
+      scale: 1.0,
+
+      local_to_world: math::Mat4x4::new(),
+      attachment: None,
+    }
+  }
+
+  /* Advances the animation and computes the transform. Attached
+   * instances should be updated after their parents. */
+  pub fn update(&mut self, delta: f32) /* dt is in terms of seconds. */
+  {
+    if self.model.is_animated
+    { self.animator.update(delta); }
+
+    let transform = match self.attachment
+    {
+      Some(ref attachment) => { attachment.get_transform() },
+      None => { None }
+    };
+    self.local_to_world = match transform
+    {
+      Some(t) => { t },
+      None =>
+      {
+        let axes = [ self.orientation.rotate_vec(&math::Vec3f::new(self.scale, 0.0, 0.0)),
+                     self.orientation.rotate_vec(&math::Vec3f::new(0.0, self.scale, 0.0)),
+                     self.orientation.rotate_vec(&math::Vec3f::new(0.0, 0.0, self.scale)) ];
+        new_transform(&self.position, &axes)
+      }
+    };
+  }
+
+  /* Crossfades into another animation state. */
+  pub fn set_animation(&mut self, state: &str) -> Result<(), ~str>
+  { self.animator.set_state(state) }
+
+  /* Parents the instance to a point (joint, tag, etc.) of another.
+   * Its own position, orientation, and scale are ignored until
+   * it's detached. */
+  pub fn attach_to(&mut self, parent: @mut Attachable, point: &str)
+  { self.attachment = Some(Attachment::new(parent, point)); }
+
+  pub fn detach(&mut self)
+  { self.attachment = None; }
+
+  /* Joints of the current pose, in model space. */
+  pub fn get_pose<'a>(&'a self) -> &'a [Joint]
+  {
+    if self.model.is_animated
+    { self.animator.skeleton.as_slice() }
+    else
+    { self.model.joints.as_slice() }
+  }
+}
+
+impl Attachable for Instance
+{
+  fn get_attachment(&self, name: &str) -> Option<math::Mat4x4>
+  {
+    match self.model.get_joint_transform(self.get_pose(), name)
+    {
+      Some(joint) => { Some(joint * self.local_to_world) },
+      None => { None }
+    }
+  }
+}
@@ -10,13 +10,30 @@
       joints that make up one renderable entity.
 */
 
+use extra::sort;
 use gl2 = opengles::gl2;
 use super::{ Vertex, Triangle, Weight };
 use math;
 
+/* Weights per vertex when skinning on the GPU. */
+pub static Max_Skin_Weights: uint = 4;
+
+/* A vertex's heaviest weights, laid out for the GPU. */
+struct Skin_Vertex
+{
+  /* Joint local positions, with the bias in w. */
+  weights: [math::Vec4f, ..4],
+  joints: math::Vec4f,
+
+  /* In the bind pose. */
+  normal: math::Vec4f,
+  tangent: math::Vec4f,
+}
+
 struct Mesh
 {
   texture: ~str,
+  normal_map: ~str, /* Empty if there is none. */
   verts: ~[Vertex],
   triangles: ~[Triangle],
   weights: ~[Weight],
@@ -25,8 +42,11 @@ struct Mesh
 
   positions: ~[math::Vec3f],
   normals: ~[math::Vec3f],
+  tangents: ~[math::Vec4f],
   tex_coords: ~[math::Vec2f],
+  colors: ~[math::Vec4f], /* RGBA, from 0.0 to 1.0. */
   indices: ~[u32],
+  skin: ~[Skin_Vertex],
 }
 
 impl Mesh
@@ -36,6 +56,7 @@ impl Mesh
     Mesh
     {
       texture: ~"",
+      normal_map: ~"",
       verts: ~[],
       triangles: ~[],
       weights: ~[],
@@ -44,9 +65,52 @@ impl Mesh
 
       positions: ~[],
       normals: ~[],
+      tangents: ~[],
       tex_coords: ~[],
+      colors: ~[],
       indices: ~[],
+      skin: ~[],
+    }
+  }
+
+  /* Packs the weights of each vertex for GPU skinning. Vertices
+   * with more than Max_Skin_Weights keep their heaviest, which
+   * are rebalanced to sum to one. Returns the number of vertices
+   * which lost weights. */
+  pub fn prepare_skin(&mut self) -> uint
+  {
+    let mut truncated = 0;
+    self.skin.clear();
+    for vert in self.verts.iter()
+    {
+      let start = vert.start_weight as uint;
+      let mut weights = self.weights.slice(start, start + vert.weight_count as uint).map(|w| *w);
+      if weights.len() > Max_Skin_Weights
+      {
+        truncated += 1;
+        sort::quick_sort(weights, |a, b| a.bias >= b.bias);
+        weights.truncate(Max_Skin_Weights);
+      }
+      let total = weights.iter().fold(0.0f32, |total, w| total + w.bias);
+
+      let mut skin = Skin_Vertex
+      {
+        weights: [math::Vec4f::zero(), ..4],
+        joints: math::Vec4f::zero(),
+        normal: math::Vec4f::new(vert.normal.x, vert.normal.y, vert.normal.z, 0.0),
+        tangent: vert.tangent,
+      };
+      let mut joints = [0.0f32, ..4];
+      for (i, weight) in weights.iter().enumerate()
+      {
+        let bias = if total > 0.0 { weight.bias / total } else { 0.0 };
+        skin.weights[i] = math::Vec4f::new(weight.position.x, weight.position.y, weight.position.z, bias);
+        joints[i] = weight.joint_id as f32;
+      }
+      skin.joints = math::Vec4f::new(joints[0], joints[1], joints[2], joints[3]);
+      self.skin.push(skin);
     }
+    truncated
   }
 }
 
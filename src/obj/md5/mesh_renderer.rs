@@ -6,84 +6,123 @@
     File: obj/md5/mesh_renderer.rs
     Author: Jesse 'Jeaye' Wilkerson
     Description:
-      Renders an MD5 mesh.
+      Renders an MD5 mesh. Vertices carry
+      their weights, and are skinned on the
+      GPU against the model's joints. Meshes
+      with normal maps are lit with them.
 */
 
 use std::sys;
 use gl2 = opengles::gl2;
 use gl;
 use math;
+use res;
 use super::{ Mesh };
+use super::mesh::{ Skin_Vertex, Max_Skin_Weights };
 
 #[path = "../../gl/check.rs"]
 mod check;
 
-struct Mesh_Renderer<'self>
+struct Mesh_Renderer
 {
-  mesh: &'self Mesh,
-
   vao: gl2::GLuint,
-  position_vbo: gl2::GLuint, 
-  tex_vbo: gl2::GLuint, 
-  ibo: gl2::GLuint, 
+  skin_vbo: gl2::GLuint,
+  tex_vbo: gl2::GLuint,
+  color_vbo: gl2::GLuint,
+  ibo: gl2::GLuint,
+  index_count: i32,
   tex0_loc: gl2::GLint,
+  normal_map_loc: gl2::GLint,
+  normal_mapped_loc: gl2::GLint,
 
-  shader: @mut gl::Shader, 
-  texture: Option<gl::Texture>,
+  shader: @mut gl::Shader,
+  texture: Option<@gl::Texture>,
+  normal_map: Option<@gl::Texture>,
 }
 
-impl<'self> Mesh_Renderer<'self>
+impl Mesh_Renderer
 {
-  pub fn new(m: &'self Mesh, sh: @mut gl::Shader) -> Mesh_Renderer<'self>
+  pub fn new(m: &Mesh, sh: @mut gl::Shader) -> Mesh_Renderer
   {
     let mut mr = Mesh_Renderer
     {
-      mesh: m,
-
       vao: 0,
-      position_vbo: 0,
+      skin_vbo: 0,
       tex_vbo: 0,
+      color_vbo: 0,
       ibo: 0,
+      index_count: 0,
       tex0_loc: 0,
+      normal_map_loc: 0,
+      normal_mapped_loc: 0,
 
       shader: sh,
       texture: None,
+      normal_map: None,
     };
 
     if m.texture.len() > 0
-    { mr.texture = Some(gl::Texture::new(gl2::TEXTURE_2D, m.texture)); }
+    { mr.texture = Some(res::Manager::get().get_texture(m.texture)); }
+    if m.normal_map.len() > 0
+    { mr.normal_map = Some(res::Manager::get().get_texture(m.normal_map)); }
 
     mr.shader.bind();
     mr.tex0_loc = mr.shader.get_uniform_location("tex0");
     mr.shader.update_uniform_i32(mr.tex0_loc, 0);
+    mr.normal_map_loc = mr.shader.get_uniform_location("normal_map");
+    mr.shader.update_uniform_i32(mr.normal_map_loc, 2); /* The joints are in 1. */
+    mr.normal_mapped_loc = mr.shader.get_uniform_location("normal_mapped");
 
-    mr.upload();
+    mr.upload(m);
 
     mr
   }
 
-  fn upload(&mut self)
+  fn upload(&mut self, mesh: &Mesh)
   {
     let name = check!(gl2::gen_vertex_arrays(1));
     assert!(name.len() == 1);
     self.vao = name[0];
 
-    let name = check!(gl2::gen_buffers(3));
-    assert!(name.len() == 3);
-    self.position_vbo = name[0];
+    let name = check!(gl2::gen_buffers(4));
+    assert!(name.len() == 4);
+    self.skin_vbo = name[0];
     self.tex_vbo = name[1];
-    self.ibo = name[2];
+    self.color_vbo = name[2];
+    self.ibo = name[3];
 
     check!(gl2::bind_vertex_array(self.vao));
 
-    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.position_vbo));
-    check!(gl2::buffer_data(gl2::ARRAY_BUFFER, self.mesh.positions, gl2::STATIC_DRAW));
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.skin_vbo));
+    check!(gl2::buffer_data(gl2::ARRAY_BUFFER, mesh.skin, gl2::STATIC_DRAW));
 
     check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.tex_vbo));
-    check!(gl2::buffer_data(gl2::ARRAY_BUFFER, self.mesh.tex_coords, gl2::STATIC_DRAW));
+    check!(gl2::buffer_data(gl2::ARRAY_BUFFER, mesh.tex_coords, gl2::STATIC_DRAW));
+
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.color_vbo));
+    check!(gl2::buffer_data(gl2::ARRAY_BUFFER, mesh.colors, gl2::STATIC_DRAW));
 
     check!(gl2::bind_buffer(gl2::ELEMENT_ARRAY_BUFFER, self.ibo));
-    check!(gl2::buffer_data(gl2::ELEMENT_ARRAY_BUFFER, self.mesh.indices, gl2::STATIC_DRAW));
+    check!(gl2::buffer_data(gl2::ELEMENT_ARRAY_BUFFER, mesh.indices, gl2::STATIC_DRAW));
+    self.index_count = mesh.indices.len() as i32;
+
+    check!(gl2::bind_vertex_array(0));
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, 0));
+  }
+
+  /* Gives the textures back to the resource manager. */
+  pub fn release(&mut self)
+  {
+    for tex in [self.texture, self.normal_map].iter()
+    {
+      match *tex
+      {
+        Some(tex) => { res::Manager::get().release_texture(tex.filename); },
+        None => { }
+      }
+    }
+    self.texture = None;
+    self.normal_map = None;
   }
 
   pub fn render(&self)
@@ -93,22 +132,45 @@ impl<'self> Mesh_Renderer<'self>
       Some(tex) => { tex.bind(gl2::TEXTURE_2D); },
       None => { }
     }
+    match self.normal_map
+    {
+      Some(tex) =>
+      {
+        check!(gl2::active_texture(gl2::TEXTURE0 + 2));
+        tex.bind(gl2::TEXTURE_2D);
+        check!(gl2::active_texture(gl2::TEXTURE0));
+        self.shader.update_uniform_i32(self.normal_mapped_loc, 1);
+      },
+      None => { self.shader.update_uniform_i32(self.normal_mapped_loc, 0); }
+    }
 
     check!(gl2::bind_vertex_array(self.vao));
 
-    check!(gl2::enable_vertex_attrib_array(0));
-    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.position_vbo));
-    check!(gl2::vertex_attrib_pointer_f32(0, 3, false, sys::size_of::<math::Vec3f>() as i32, 0));
+    /* Each weight, the joint indices, then the normal and tangent. */
+    let stride = sys::size_of::<Skin_Vertex>() as i32;
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.skin_vbo));
+    for i in range(0, Max_Skin_Weights + 3)
+    {
+      check!(gl2::enable_vertex_attrib_array(i as u32));
+      check!(gl2::vertex_attrib_pointer_f32(i as u32, 4, false, stride,
+                                             (i * sys::size_of::<math::Vec4f>()) as u32));
+    }
 
-    check!(gl2::enable_vertex_attrib_array(1));
+    let coord_loc = (Max_Skin_Weights + 3) as u32;
+    check!(gl2::enable_vertex_attrib_array(coord_loc));
     check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.tex_vbo));
-    check!(gl2::vertex_attrib_pointer_f32(1, 2, false, sys::size_of::<math::Vec2f>() as i32, 0));
+    check!(gl2::vertex_attrib_pointer_f32(coord_loc, 2, false, sys::size_of::<math::Vec2f>() as i32, 0));
+
+    let color_loc = coord_loc + 1;
+    check!(gl2::enable_vertex_attrib_array(color_loc));
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.color_vbo));
+    check!(gl2::vertex_attrib_pointer_f32(color_loc, 4, false, sys::size_of::<math::Vec4f>() as i32, 0));
 
     check!(gl2::bind_buffer(gl2::ELEMENT_ARRAY_BUFFER, self.ibo));
-    check!(gl2::draw_elements(gl2::TRIANGLES, self.mesh.indices.len() as i32, gl2::UNSIGNED_INT, None));
+    check!(gl2::draw_elements(gl2::TRIANGLES, self.index_count, gl2::UNSIGNED_INT, None));
 
-    check!(gl2::disable_vertex_attrib_array(0));
-    check!(gl2::disable_vertex_attrib_array(1));
+    for i in range(0, color_loc + 1)
+    { check!(gl2::disable_vertex_attrib_array(i)); }
     check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, 0));
     check!(gl2::bind_buffer(gl2::ELEMENT_ARRAY_BUFFER, 0));
     check!(gl2::bind_vertex_array(0));
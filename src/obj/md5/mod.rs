@@ -10,6 +10,10 @@
 */
 
 pub use self::model::Model;
+pub use self::parser::Parse_Error;
+pub use self::animation::Animation;
+pub use self::animator::Animator;
+pub use self::instance::Instance;
 pub use self::model_renderer::Model_Renderer;
 pub use self::joint::Joint;
 pub use self::weight::Weight;
@@ -18,7 +22,12 @@ pub use self::mesh_renderer::Mesh_Renderer;
 pub use self::triangle::Triangle;
 pub use self::vertex::Vertex;
 
+#[macro_escape]
+mod parser;
 mod model;
+mod animation;
+mod animator;
+mod instance;
 mod model_renderer;
 mod joint;
 mod weight;
@@ -8,18 +8,28 @@
     Description:
       Loads, parses, and represents
       the model (as in MVC) side of
-      MD5 animated models.
+      MD5 animated models. Models are
+      shared by instances, which are
+      what get placed and animated.
 */
 
-use std::{ io, path, vec, str };
-use super::{ Joint, Vertex, Triangle, Weight, Mesh };
+use std::{ path, vec, cmp, os, sys };
+use stb_image;
+use super::{ Joint, Vertex, Triangle, Weight, Mesh, Animation, Animator };
+use super::parser::{ Parser, Parse_Error, Token };
+use super::mesh::{ Skin_Vertex, Max_Skin_Weights };
+use Prim_Triangle = primitive::Triangle;
+use primitive::Vertex_PC;
 use math;
+use attachment::new_transform;
 use util::Log;
 
 #[macro_escape]
 #[path = "../../util/log_macros.rs"]
 mod log_macros;
 
+static Version: i32 = 10;
+
 struct Model
 {
   file_directory: ~str,
@@ -32,28 +42,37 @@ struct Model
   joints: ~[Joint],
   meshes: ~[Mesh],
 
-  //animation: Option<Animation>,
-  
-  local_to_world: math::Mat4x4,
+  /* Each instance starts with a copy of this. */
+  animator: Animator,
 }
 
 impl Model
 {
-  pub fn new(mesh_file: ~str) -> Model
+  pub fn new(mesh_file: &str) -> Result<Model, Parse_Error>
   {
     /* TODO: Custom Path type to handle this. */
     let dir;
-    let mut posix = true;
-    for x in range(0, mesh_file.len() as i32)
-    { if mesh_file[x] == '\\' as u8 { posix = false; } }
-    if posix
-    { dir = path::PosixPath(mesh_file.clone()).normalize().dirname(); }
+    if is_posix(mesh_file)
+    { dir = path::PosixPath(mesh_file).normalize().dirname(); }
     else
-    { dir = path::WindowsPath(mesh_file.clone()).normalize().dirname(); }
+    { dir = path::WindowsPath(mesh_file).normalize().dirname(); }
+
+    let mut model = Model::new_empty(dir);
+    let mut parser = read!(Parser::new(mesh_file));
+    log_debug!("Parsing model %s", mesh_file);
+    log_push!();
+    let res = model.load(&mut parser);
+    log_pop!();
+    read!(res);
+
+    Ok(model)
+  }
 
-    let mut model = Model
+  fn new_empty(file_directory: ~str) -> Model
+  {
+    Model
     {
-      file_directory: dir,
+      file_directory: file_directory,
       version: 0,
       num_joints: 0,
       num_meshes: 0,
@@ -62,294 +81,664 @@ impl Model
       joints: ~[],
       meshes: ~[],
 
-      //animation: None,
-
-      local_to_world: math::Mat4x4::new(),
-    };
-
-    model.load(mesh_file);
-
-    model
+      animator: Animator::new(),
+    }
   }
 
-  fn load(&mut self, file: ~str) -> bool
+  fn load(&mut self, parser: &mut Parser) -> Result<(), Parse_Error>
   {
-    let fior = io::file_reader(&path::Path(file));
-    if fior.is_err()
-    { log_error!("Failed to open model file %s", file); return false; }
-
-    /* Clear existing data. */
-    self.joints.clear();
-    self.meshes.clear();
-
-    let fio = fior.unwrap();
-    let mut param;
-    macro_rules! read_param
-    (
-      () =>
-      ({
-        param = ~""; /* TODO: clear? */
-        let mut ch = fio.read_char();
-        while ch.is_whitespace() && !fio.eof() /* Find the next word. */
-        { ch = fio.read_char(); }
-
-        if !fio.eof()
-        { 
-          param.push_char(ch);
-          ch = fio.read_char();
-          while !ch.is_whitespace() && !fio.eof() /* Read the next word. */
-          { param.push_char(ch); ch = fio.read_char(); }
-        }
-      });
-    )
-    macro_rules! read_junk
-    (() => ({ read_param!(); });)
-    macro_rules! ignore_line
-    (() => ({ fio.read_line(); });)
-    macro_rules! read_type
-    (
-      ($var:expr) =>
-      ({
-        let name = param.clone();
-        read_param!();
-        let num = FromStr::from_str(param);
-        if num.is_some()
-        { $var = num.unwrap(); }
-        else
-        { log_error!("Invalid %s in %s", name, file); }
-      });
-    )
-
-    /* Read the first param and jump into the parsing. */
-    log_debug!("Parsing model");
-    log_push!();
-    read_param!();
-    while !fio.eof()
+    read!(parser.expect("MD5Version"));
+    self.version = read!(parser.read_i32("version"));
+    if self.version != Version
+    { return Err(parser.error(fmt!("Unsupported version %d (expected %d)", self.version as int, Version as int))); }
+    log_debug!("Model version: %d", self.version as int);
+
+    while !parser.is_done()
     {
-      match param
+      let token = read!(parser.next("a section"));
+      match token.text
       {
-        ~"MD5Version" =>
-        {
-          /* Read version. */
-          read_type!(self.version);
-          log_debug!("Model version: %d", self.version as int);
-        }
         ~"commandline" =>
-        { ignore_line!(); }
+        { read!(parser.read_string("command line")); }
         ~"numJoints" =>
         {
-          read_type!(self.num_joints);
+          if self.num_joints != 0
+          { return Err(parser.error_at(&token, ~"Duplicate numJoints")); }
+          self.num_joints = read!(parser.read_i32("joint count"));
+          if self.num_joints <= 0
+          { return Err(parser.error(~"There needs to be at least one joint")); }
           self.joints = vec::with_capacity(self.num_joints as uint);
           log_debug!("Model joints: %d", self.num_joints as int);
         }
         ~"numMeshes" =>
         {
-          read_type!(self.num_meshes);
+          self.num_meshes = read!(parser.read_i32("mesh count"));
+          if self.num_meshes < 0
+          { return Err(parser.error(~"The mesh count can't be negative")); }
           self.meshes = vec::with_capacity(self.num_meshes as uint);
           log_debug!("Model meshes: %d", self.num_meshes as int);
         }
         ~"joints" =>
         {
-          let mut joint = Joint::new();
-          read_junk!();
+          if self.num_joints == 0 || self.joints.len() > 0
+          { return Err(parser.error_at(&token, ~"Joints need to come once, after numJoints")); }
           log_debug!("Reading model joints");
-          
-          for _ in range(0, self.num_joints)
-          {
-            read_param!();
-            joint.name = param.clone();
-
-            read_type!(joint.parent);
-
-            read_junk!();
-            read_type!(joint.position.x);
-            read_type!(joint.position.y);
-            read_type!(joint.position.z);
-            read_junk!();
-            read_junk!();
-            read_type!(joint.orientation.x);
-            read_type!(joint.orientation.y);
-            read_type!(joint.orientation.z);
-            read_junk!();
-
-            joint.orientation.compute_w();
-            self.joints.push(joint.clone());
-
-            /* Ignore the rest of the line. */
-            ignore_line!();
-          }
-
-          read_junk!();
+          read!(self.read_joints(parser));
         }
         ~"mesh" =>
         {
-          let mut mesh = Mesh::new();
-          let mut vert = Vertex::new();
-          let mut tri = Triangle::new();
-          let mut weight = Weight::new();
-          let mut num_verts = 0;
-          let mut num_tris = 0;
-          let mut num_weights = 0;
+          if self.joints.len() == 0
+          { return Err(parser.error_at(&token, ~"Meshes need to come after the joints")); }
+          if self.meshes.len() >= self.num_meshes as uint
+          { return Err(parser.error_at(&token, fmt!("More than the %d meshes declared", self.num_meshes as int))); }
 
           log_debug!("Parsing mesh");
           log_push!();
+          let res = self.read_mesh(parser);
+          log_pop!();
+          let mut mesh = read!(res);
 
-          read_junk!();
-          read_param!();
-          while param != ~"}"
+          self.prepare_mesh(&mut mesh, false);
+          self.meshes.push(mesh);
+        }
+        _ =>
+        { return Err(parser.error_at(&token, fmt!("Unexpected '%s'", token.text))); }
+      }
+    }
+
+    if self.joints.len() == 0
+    { return Err(parser.error(~"Missing joints")); }
+    if self.meshes.len() != self.num_meshes as uint
+    { return Err(parser.error(fmt!("Found %u of the %d meshes declared", self.meshes.len(), self.num_meshes as int))); }
+
+    Ok(())
+  }
+
+  /* { "name" parent ( position ) ( orientation ) ... } */
+  fn read_joints(&mut self, parser: &mut Parser) -> Result<(), Parse_Error>
+  {
+    read!(parser.expect("{"));
+    for i in range(0, self.num_joints)
+    {
+      let mut joint = Joint::new();
+      joint.name = read!(parser.read_string("joint name"));
+      joint.parent = read!(parser.read_i32("joint parent"));
+      if joint.parent < -1 || joint.parent >= self.num_joints || joint.parent == i
+      { return Err(parser.error(fmt!("Joint %s has an invalid parent %d", joint.name, joint.parent as int))); }
+
+      joint.position = read!(parser.read_vec3("joint position"));
+      let orientation = read!(parser.read_vec3("joint orientation"));
+      joint.orientation.x = orientation.x;
+      joint.orientation.y = orientation.y;
+      joint.orientation.z = orientation.z;
+      joint.orientation.compute_w();
+      self.joints.push(joint);
+    }
+    read!(parser.expect("}"));
+
+    Ok(())
+  }
+
+  /* References between the vertices, triangles, weights, and joints
+   * are checked once the whole mesh is read, since the sections can
+   * come in any order. */
+  fn read_mesh(&self, parser: &mut Parser) -> Result<Mesh, Parse_Error>
+  {
+    let mut mesh = Mesh::new();
+    read!(parser.expect("{"));
+    loop
+    {
+      let token = read!(parser.next("'}'"));
+      match token.text
+      {
+        ~"}" => { break; }
+        ~"shader" => /* shader == texture path */
+        {
+          let shader = read!(parser.read_string("shader"));
+          mesh.texture = self.find_texture(shader);
+          log_debug!("Mesh shader/texture: %s", mesh.texture);
+        }
+        ~"numverts" =>
+        {
+          let count = read!(read_count(parser, &token, mesh.verts.len(), "vertex"));
+          log_debug!("Mesh verts: %u", count);
+          for i in range(0, count)
           {
-            match param
-            {
-              ~"shader" => /* shader == texture path */
-              {
-                read_param!();
-
-                /* Rust needs to know if it's a POSIX or Windows
-                 * path so it can parse it. Just loop through and
-                 * check for \ which indicates it's for Windows. */
-                let mut posix = true;
-                for x in range(0, param.len() as i32)
-                { if param[x] == '\\' as u8 { posix = false; } }
-                if posix
-                {
-                  mesh.texture =
-                    match path::PosixPath(param.clone()).normalize().filename()
-                    {
-                      Some(file) => { file },
-                      None => { ~"" }
-                    };
-                }
-                else
-                {
-                  mesh.texture =
-                    match path::WindowsPath(param.clone()).normalize().filename()
-                    {
-                      Some(file) => { file },
-                      None => { ~"" }
-                    };
-                }
-                /* Remove quotes. */
-                mesh.texture = self.file_directory + "/" + str::replace(mesh.texture, "\"", "");
-                log_debug!("Mesh shader/texture: %s", mesh.texture);
-
-                ignore_line!();
-              }
-              ~"numverts" =>
-              {
-                read_type!(num_verts);
-                ignore_line!();
-
-                log_debug!("Mesh verts: %d", num_verts);
-
-                for _ in range(0, num_verts)
-                {
-                  read_junk!();
-                  read_junk!();
-                  read_junk!();
-                  read_type!(vert.tex_coord.x);
-                  read_type!(vert.tex_coord.y);
-                  read_junk!();
-                  read_type!(vert.start_weight);
-                  read_type!(vert.weight_count);
-
-                  ignore_line!();
-
-                  mesh.verts.push(vert);
-                  mesh.tex_coords.push(vert.tex_coord);
-                }
-              }
-              ~"numtris" =>
-              {
-                read_type!(num_tris);
-                ignore_line!();
-                log_debug!("Mesh tris: %d", num_tris);
-
-                for _ in range(0, num_tris)
-                {
-                  read_junk!();
-                  read_junk!();
-                  read_type!(tri.indices[0]);
-                  read_type!(tri.indices[1]);
-                  read_type!(tri.indices[2]);
-
-                  ignore_line!();
-
-                  mesh.triangles.push(tri);
-                  mesh.indices.push(tri.indices[0] as u32);
-                  mesh.indices.push(tri.indices[1] as u32);
-                  mesh.indices.push(tri.indices[2] as u32);
-                }
-              }
-              ~"numweights" =>
-              {
-                read_type!(num_weights);
-                ignore_line!();
-                log_debug!("Mesh weights: %d", num_weights);
-
-                for _ in range(0, num_weights)
-                {
-                  read_junk!();
-                  read_junk!();
-                  read_type!(weight.joint_id);
-                  read_type!(weight.bias);
-                  read_junk!();
-                  read_type!(weight.position.x);
-                  read_type!(weight.position.y);
-                  read_type!(weight.position.z);
-                  read_junk!();
-
-                  ignore_line!();
-                  mesh.weights.push(weight);
-                }
-              }
-              _ =>
-              { ignore_line!(); }
-            }
-
-            read_param!();
+            read!(parser.expect("vert"));
+            read!(read_index(parser, i, "vertex"));
+            let mut vert = Vertex::new();
+            vert.tex_coord = read!(parser.read_vec2("texture coordinate"));
+            vert.start_weight = read!(parser.read_i32("start weight"));
+            vert.weight_count = read!(parser.read_i32("weight count"));
+            mesh.verts.push(vert);
+            mesh.tex_coords.push(vert.tex_coord);
           }
-
-          self.prepare_mesh(&mut mesh);
-          self.meshes.push(mesh);
-          log_pop!();
         }
-        _ => { } 
+        ~"numtris" =>
+        {
+          let count = read!(read_count(parser, &token, mesh.triangles.len(), "triangle"));
+          log_debug!("Mesh tris: %u", count);
+          for i in range(0, count)
+          {
+            read!(parser.expect("tri"));
+            read!(read_index(parser, i, "triangle"));
+            let mut tri = Triangle::new();
+            for j in range(0, 3)
+            { tri.indices[j] = read!(parser.read_i32("triangle index")); }
+            mesh.triangles.push(tri);
+            for j in range(0, 3)
+            { mesh.indices.push(tri.indices[j] as u32); }
+          }
+        }
+        ~"numweights" =>
+        {
+          let count = read!(read_count(parser, &token, mesh.weights.len(), "weight"));
+          log_debug!("Mesh weights: %u", count);
+          for i in range(0, count)
+          {
+            read!(parser.expect("weight"));
+            read!(read_index(parser, i, "weight"));
+            let mut weight = Weight::new();
+            weight.joint_id = read!(parser.read_i32("weight joint"));
+            if weight.joint_id < 0 || weight.joint_id as uint >= self.joints.len()
+            { return Err(parser.error(fmt!("Weight %u references joint %d of %u", i, weight.joint_id as int,
+                                           self.joints.len()))); }
+            weight.bias = read!(parser.read_f32("weight bias"));
+            weight.position = read!(parser.read_vec3("weight position"));
+            mesh.weights.push(weight);
+          }
+        }
+        _ =>
+        { return Err(parser.error_at(&token, fmt!("Unexpected '%s' in mesh", token.text))); }
       }
+    }
 
-      /* Move to the next param. */
-      read_param!();
+    for (i, tri) in mesh.triangles.iter().enumerate()
+    {
+      if tri.indices.iter().any(|index| *index < 0 || *index as uint >= mesh.verts.len())
+      { return Err(parser.error(fmt!("Triangle %u references a vertex outside of the %u", i, mesh.verts.len()))); }
+    }
+    for (i, vert) in mesh.verts.iter().enumerate()
+    {
+      if vert.start_weight < 0 || vert.weight_count <= 0 ||
+         (vert.start_weight + vert.weight_count) as uint > mesh.weights.len()
+      { return Err(parser.error(fmt!("Vertex %u references weights outside of the %u", i, mesh.weights.len()))); }
+    }
+
+    Ok(mesh)
+  }
+
+  /* Shaders name the texture, relative to the model. */
+  fn find_texture(&self, shader: &str) -> ~str
+  {
+    let file = if is_posix(shader)
+    { path::PosixPath(shader).normalize().filename() }
+    else
+    { path::WindowsPath(shader).normalize().filename() };
+
+    match file
+    {
+      Some(file) => { self.file_directory + "/" + file },
+      None => { ~"" }
+    }
+  }
+
+  /* Builds a model from joints and meshes that are already loaded,
+   * such as from IQM files, as MD5 models would have them. Meshes
+   * with their own normals and tangents keep them. */
+  pub fn new_from_parts(joints: ~[Joint], meshes: ~[Mesh], has_tangent_frames: bool) -> Model
+  {
+    let mut model = Model::new_empty(~"");
+    model.num_joints = joints.len() as i32;
+    model.num_meshes = meshes.len() as i32;
+    model.joints = joints;
+
+    for mesh in meshes.move_iter()
+    {
+      let mut mesh = mesh;
+      model.prepare_mesh(&mut mesh, has_tangent_frames);
+      model.meshes.push(mesh);
     }
-    log_pop!();
 
-    true
+    model
   }
 
-  fn prepare_mesh(&mut self, mesh: &mut Mesh)
+  fn prepare_mesh(&mut self, mesh: &mut Mesh, has_tangent_frames: bool)
   {
-    mesh.positions.clear();
     mesh.tex_coords.clear();
+    for vert in mesh.verts.iter()
+    { mesh.tex_coords.push(vert.tex_coord); }
 
-    for x in range(0, mesh.verts.len() as i32)
+    /* MD5 has no vertex colors. */
+    if mesh.colors.len() != mesh.verts.len()
+    { mesh.colors = vec::from_elem(mesh.verts.len(), math::Vec4f::new(1.0, 1.0, 1.0, 1.0)); }
+
+    skin_mesh(mesh, self.joints, self.joints);
+    if !has_tangent_frames
+    { compute_tangent_frames(mesh); }
+
+    /* Doom 3 keeps tangent space normal maps beside the diffuse. */
+    let diffuse = path::Path(mesh.texture);
+    let normal_map = match diffuse.filestem()
+    {
+      Some(stem) => { diffuse.with_filestem(stem + "_local") },
+      None => { diffuse.clone() }
+    };
+    if normal_map != diffuse && os::path_exists(&normal_map)
     {
-      let vert = &mut mesh.verts[x];
-      vert.position = math::Vec3f::zero();
-      vert.normal = math::Vec3f::zero();
+      mesh.normal_map = normal_map.to_str();
+      log_debug!("Mesh normal map: %s", mesh.normal_map);
+    }
+
+    let truncated = mesh.prepare_skin();
+    if truncated > 0
+    { log_info!("%u vertices have more than %u weights; keeping the heaviest", truncated, Max_Skin_Weights); }
+  }
 
-      /* Sum the position of all the weights. */
-      for w in range(0, vert.weight_count)
+  /* Adds (or replaces) a state of the model's animator, which
+   * instances created after will have. The animation's skeleton
+   * needs to match the model's. One-shot states, such as
+   * jumping, name the state to move to after. */
+  pub fn load_animation(&mut self, state: &str, file: &str, next: Option<~str>) -> Result<(), ~str>
+  {
+    match Animation::new(file)
+    {
+      Ok(anim) => { self.add_animation(state, anim, next) },
+      Err(err) => { Err(err.to_str()) }
+    }
+  }
+
+  /* As with load_animation, for animations that are already loaded. */
+  pub fn add_animation(&mut self, state: &str, anim: Animation, next: Option<~str>) -> Result<(), ~str>
+  {
+    if !anim.matches(self.joints)
+    { return Err(fmt!("Animation %s doesn't match the model's skeleton", state)); }
+
+    self.animator.add_state(state, @anim, next);
+    self.is_animated = true;
+    Ok(())
+  }
+
+  /* Model space transform of a joint in the given pose. */
+  pub fn get_joint_transform(&self, pose: &[Joint], name: &str) -> Option<math::Mat4x4>
+  {
+    let joint = match pose.iter().find(|j| j.name.as_slice() == name)
+    {
+      Some(j) => { j },
+      None => { return None; }
+    };
+    let axes = [ joint.orientation.rotate_vec(&math::Vec3f::new(1.0, 0.0, 0.0)),
+                 joint.orientation.rotate_vec(&math::Vec3f::new(0.0, 1.0, 0.0)),
+                 joint.orientation.rotate_vec(&math::Vec3f::new(0.0, 0.0, 1.0)) ];
+    Some(new_transform(&joint.position, &axes))
+  }
+
+  /* Skins the meshes to a pose (such as an instance's) on the
+   * CPU. This is the reference the GPU path is tested against,
+   * and is needed wherever the posed positions are used outside
+   * of rendering. */
+  pub fn skin(&mut self, pose: &[Joint])
+  {
+    for mesh in self.meshes.mut_iter()
+    { skin_mesh(mesh, pose, self.joints); }
+  }
+
+  /* Bytes used by the meshes, including what's uploaded. Textures
+   * are counted separately. */
+  pub fn memory_usage(&self) -> uint
+  {
+    let mut total = sys::size_of::<Model>() + (self.joints.len() * sys::size_of::<Joint>());
+    for mesh in self.meshes.iter()
+    {
+      total += (mesh.verts.len() * sys::size_of::<Vertex>()) +
+               (mesh.triangles.len() * sys::size_of::<Triangle>()) +
+               (mesh.weights.len() * sys::size_of::<Weight>()) +
+               (mesh.positions.len() * sys::size_of::<math::Vec3f>() * 2) + /* And normals. */
+               (mesh.tangents.len() * sys::size_of::<math::Vec4f>()) +
+               (mesh.colors.len() * sys::size_of::<math::Vec4f>() * 2) +
+               (mesh.indices.len() * sys::size_of::<u32>() * 2) +
+               (mesh.tex_coords.len() * sys::size_of::<math::Vec2f>() * 2) +
+               (mesh.skin.len() * sys::size_of::<Skin_Vertex>() * 2);
+    }
+    total
+  }
+
+  /* Flattens every mesh, as last skinned on the CPU, into standalone
+   * triangles. Vertex colors are sampled from each mesh's
+   * texture and are in the range [0, 255], as with BSP maps. */
+  pub fn get_triangles(&self) -> ~[Prim_Triangle]
+  {
+    let mut tris = ~[];
+    for mesh in self.meshes.iter()
+    {
+      let image = match stb_image::image::load(mesh.texture.clone())
+      {
+        stb_image::image::ImageU8(image) => { Some(image) },
+        _ => { log_error!("Failed to load texture %s; using white", mesh.texture); None }
+      };
+      let colors = do mesh.tex_coords.map |coord|
       {
-        let weight = &mut mesh.weights[vert.start_weight + w];
-        let joint = &mut self.joints[weight.joint_id];
+        match image
+        {
+          Some(ref image) => { sample_texel(image.data, image.width, image.height, image.depth, coord) },
+          None => { math::Vec3f::new(255.0, 255.0, 255.0) }
+        }
+      };
 
-        /* Convert the weight position from joint local to object space. */
-        let rot_pos = joint.orientation.rotate_vec(&weight.position);
-        
-        vert.position = vert.position + ((joint.position + rot_pos) * weight.bias);
+      for tri in mesh.triangles.iter()
+      {
+        let vert = |i: uint| -> Vertex_PC
+        {
+          let index = tri.indices[i] as uint;
+          let (texel, tint) = (colors[index], mesh.colors[index]);
+          Vertex_PC::new(mesh.positions[index],
+                         math::Vec3f::new(texel.x * tint.x, texel.y * tint.y, texel.z * tint.z))
+        };
+        tris.push(Prim_Triangle::new(vert(0), vert(1), vert(2)));
       }
+    }
+    log_debug!("Model has %u triangles", tris.len());
+
+    tris
+  }
+}
+
+/* Rust needs to know if a path is POSIX or Windows so it can parse it;
+ * a \ indicates it's for Windows. */
+fn is_posix(file: &str) -> bool
+{ !file.iter().any(|ch| ch == '\\') }
+
+/* A section's count, which comes once and can't be negative. */
+fn read_count(parser: &mut Parser, token: &Token, existing: uint, what: &str) -> Result<uint, Parse_Error>
+{
+  if existing > 0
+  { return Err(parser.error_at(token, fmt!("Duplicate %s section", what))); }
 
-      mesh.positions.push(vert.position);
-      mesh.tex_coords.push(vert.tex_coord);
+  let count = read!(parser.read_i32(fmt!("%s count", what)));
+  if count < 0
+  { return Err(parser.error(fmt!("The %s count can't be negative", what))); }
+  Ok(count as uint)
+}
+
+/* Entries are numbered, and need to be in order. */
+fn read_index(parser: &mut Parser, expected: uint, what: &str) -> Result<(), Parse_Error>
+{
+  let index = read!(parser.read_i32(fmt!("%s index", what)));
+  if index < 0 || index as uint != expected
+  { return Err(parser.error(fmt!("Expected %s %u but found %d", what, expected, index as int))); }
+  Ok(())
+}
+
+/* Positions each vertex of the mesh by the weighted joints of the
+ * pose. Normals and tangents are rotated by each joint's change
+ * from the bind pose, with the same weights. */
+fn skin_mesh(mesh: &mut Mesh, pose: &[Joint], bind: &[Joint])
+{
+  mesh.positions.clear();
+  mesh.normals.clear();
+  mesh.tangents.clear();
+
+  for x in range(0, mesh.verts.len())
+  {
+    let vert = &mut mesh.verts[x];
+    let bind_tangent = math::Vec3f::new(vert.tangent.x, vert.tangent.y, vert.tangent.z);
+    let mut normal = math::Vec3f::zero();
+    let mut tangent = math::Vec3f::zero();
+    vert.position = math::Vec3f::zero();
+
+    /* Sum the position of all the weights. */
+    for w in range(0, vert.weight_count)
+    {
+      let weight = &mesh.weights[vert.start_weight + w];
+      let joint = &pose[weight.joint_id];
+
+      /* Convert the weight position from joint local to object space. */
+      let rot_pos = joint.orientation.rotate_vec(&weight.position);
+
+      vert.position = vert.position + ((joint.position + rot_pos) * weight.bias);
+
+      let delta = joint.orientation * bind[weight.joint_id].orientation.get_conjugate();
+      normal = normal + (delta.rotate_vec(&vert.normal) * weight.bias);
+      tangent = tangent + (delta.rotate_vec(&bind_tangent) * weight.bias);
     }
+    normal.normalize();
+    tangent.normalize();
+
+    mesh.positions.push(vert.position);
+    mesh.normals.push(normal);
+    mesh.tangents.push(math::Vec4f::new(tangent.x, tangent.y, tangent.z, vert.tangent.w));
+  }
+}
+
+/* Smooth normals and tangents, from the skinned positions, which are
+ * stored as each vertex's bind pose frame. MD5 triangles wind clockwise. */
+fn compute_tangent_frames(mesh: &mut Mesh)
+{
+  let mut normals = vec::from_elem(mesh.verts.len(), math::Vec3f::zero());
+  let mut tangents = vec::from_elem(mesh.verts.len(), math::Vec3f::zero());
+  let mut bitangents = vec::from_elem(mesh.verts.len(), math::Vec3f::zero());
+  for tri in mesh.triangles.iter()
+  {
+    let (i0, i1, i2) = (tri.indices[0] as uint, tri.indices[1] as uint, tri.indices[2] as uint);
+    let (p0, p1, p2) = (mesh.positions[i0], mesh.positions[i1], mesh.positions[i2]);
+    let (t0, t1, t2) = (mesh.tex_coords[i0], mesh.tex_coords[i1], mesh.tex_coords[i2]);
+    let (e1, e2) = (p1 - p0, p2 - p0);
+
+    /* Unnormalized, so larger triangles count for more. */
+    let normal = e2.cross(&e1);
+
+    let (du1, dv1) = (t1.x - t0.x, t1.y - t0.y);
+    let (du2, dv2) = (t2.x - t0.x, t2.y - t0.y);
+    let det = (du1 * dv2) - (du2 * dv1);
+    let r = if det.abs() > 0.000001 { 1.0 / det } else { 0.0 };
+    let tangent = ((e1 * dv2) - (e2 * dv1)) * r;
+    let bitangent = ((e2 * du1) - (e1 * du2)) * r;
+
+    for &i in [i0, i1, i2].iter()
+    {
+      normals[i] = normals[i] + normal;
+      tangents[i] = tangents[i] + tangent;
+      bitangents[i] = bitangents[i] + bitangent;
+    }
+  }
+
+  mesh.normals.clear();
+  mesh.tangents.clear();
+  for (i, vert) in mesh.verts.mut_iter().enumerate()
+  {
+    let mut normal = normals[i];
+    normal.normalize();
+
+    /* Gram-Schmidt, so the tangent is perpendicular to the normal. */
+    let mut tangent = tangents[i] - (normal * normal.dot(&tangents[i]));
+    tangent.normalize();
+    let handedness = if normal.cross(&tangent).dot(&bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+
+    vert.normal = normal;
+    vert.tangent = math::Vec4f::new(tangent.x, tangent.y, tangent.z, handedness);
+    mesh.normals.push(normal);
+    mesh.tangents.push(vert.tangent);
   }
 }
 
+/* Nearest texel at the texture coordinate, which wraps. One and
+ * two channel (grey and grey with alpha) textures are grey; empty
+ * textures are white. */
+fn sample_texel(data: &[u8], width: uint, height: uint, depth: uint, coord: &math::Vec2f) -> math::Vec3f
+{
+  if width == 0 || height == 0 || depth == 0 || data.len() < width * height * depth
+  { return math::Vec3f::new(255.0, 255.0, 255.0); }
+
+  let u = coord.x - coord.x.floor();
+  let v = coord.y - coord.y.floor();
+  let x = cmp::min((u * width as f32) as uint, width - 1);
+  let y = cmp::min((v * height as f32) as uint, height - 1);
+  let i = ((y * width) + x) * depth;
+  match depth
+  {
+    1 | 2 => { math::Vec3f::new(data[i] as f32, data[i] as f32, data[i] as f32) },
+    _ => { math::Vec3f::new(data[i] as f32, data[i + 1] as f32, data[i + 2] as f32) }
+  }
+}
+
+#[cfg(test)]
+mod test
+{
+  use math;
+  use super::{ Model, sample_texel };
+  use super::super::parser::{ Parse_Error, test_support };
+  use super::super::parser::test_support::error_position;
+
+  /* One joint, with a mesh of one vertex; the weight's line is given. */
+  fn fixture(weight: &str) -> ~str
+  {
+    ~"MD5Version 10\n" +
+    "numJoints 1\n" +
+    "numMeshes 1\n" +
+    "joints {\n" +
+    "  \"root\" -1 ( 0 0 0 ) ( 0 0 0 )\n" +
+    "}\n" +
+    "mesh {\n" +
+    "  numverts 1\n" +
+    "  vert 0 ( 0 0 ) 0 1\n" +
+    "  numtris 0\n" +
+    "  numweights 1\n" +
+    "  " + weight + "\n" +
+    "}\n"
+  }
+
+  fn load(text: &str) -> Result<Model, Parse_Error>
+  { test_support::load("test.md5mesh", text, Model::new_empty(~"."), |item, parser| item.load(parser)) }
+
+  #[test]
+  fn valid()
+  {
+    match load(fixture("weight 0 0 1.0 ( 1 2 3 )"))
+    {
+      Ok(model) =>
+      {
+        assert_eq!(model.joints.len(), 1);
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].positions[0].x, 1.0);
+      },
+      Err(err) => { fail!(err.to_str()); }
+    }
+  }
+
+  #[test]
+  fn bad_version()
+  { assert_eq!(error_position(load("MD5Version 11\n")), (1, 12)); }
+
+  #[test]
+  fn truncated()
+  {
+    let text = "MD5Version 10\nnumJoints 1\njoints {\n  \"root\" -1 ( 0 0 0 )";
+    assert_eq!(error_position(load(text)), (4, 21));
+  }
+
+  #[test]
+  fn unterminated_string()
+  {
+    let text = "MD5Version 10\nnumJoints 1\njoints {\n  \"root -1 ( 0 0 0 ) ( 0 0 0 )\n}";
+    assert_eq!(error_position(load(text)), (4, 3));
+  }
+
+  #[test]
+  fn weight_joint_out_of_range()
+  { assert_eq!(error_position(load(fixture("weight 0 1 1.0 ( 0 0 0 )"))), (12, 12)); }
+
+  #[test]
+  fn triangle_out_of_range()
+  {
+    let text = fixture("weight 0 0 1.0 ( 0 0 0 )").replace("numtris 0", "numtris 1\n  tri 0 0 0 1");
+    assert_eq!(error_position(load(text)), (14, 1));
+  }
+
+  /* A second count can't raise the joints that weights may use. */
+  #[test]
+  fn duplicate_joint_count()
+  {
+    let text = fixture("weight 0 1 1.0 ( 0 0 0 )").replace("}\nmesh", "}\nnumJoints 2\nmesh");
+    assert_eq!(error_position(load(text)), (7, 1));
+  }
+
+  /* The packed weights, skinned as md5.vert does, land where the CPU puts them. */
+  #[test]
+  fn skin_matches_packed()
+  {
+    let text = ~"MD5Version 10\n" +
+               "numJoints 2\n" +
+               "numMeshes 1\n" +
+               "joints {\n" +
+               "  \"root\" -1 ( 0 0 0 ) ( 0 0 0 )\n" +
+               "  \"arm\" 0 ( 0 0 2 ) ( 0.3 0 0 )\n" +
+               "}\n" +
+               "mesh {\n" +
+               "  numverts 3\n" +
+               "  vert 0 ( 0 0 ) 0 1\n" +
+               "  vert 1 ( 1 0 ) 1 1\n" +
+               "  vert 2 ( 0 1 ) 2 2\n" +
+               "  numtris 1\n" +
+               "  tri 0 0 1 2\n" +
+               "  numweights 4\n" +
+               "  weight 0 0 1.0 ( 1 0 0 )\n" +
+               "  weight 1 1 1.0 ( 0 1 0 )\n" +
+               "  weight 2 0 0.25 ( 0 1 1 )\n" +
+               "  weight 3 1 0.75 ( 1 1 0 )\n" +
+               "}\n";
+    let mut model = match load(text)
+    {
+      Ok(model) => { model },
+      Err(err) => { fail!(err.to_str()); }
+    };
+
+    let mut pose = model.joints.clone();
+    pose[0].position = math::Vec3f::new(0.5, -1.0, 0.0);
+    pose[1].position = math::Vec3f::new(1.0, 0.0, 2.5);
+    pose[1].orientation = math::Quaternion::new_from_axis(&math::Vec3f::new(0.0, 1.0, 1.0), 0.6) *
+                          pose[1].orientation;
+    model.skin(pose);
+
+    let close = |a: math::Vec3f, b: math::Vec3f| -> bool { (a - b).length() < 0.0001 };
+    let mesh = &model.meshes[0];
+    assert_eq!(mesh.skin.len(), 3);
+    for (i, skin) in mesh.skin.iter().enumerate()
+    {
+      let mut position = math::Vec3f::zero();
+      let mut normal = math::Vec3f::zero();
+      let bind_normal = math::Vec3f::new(skin.normal.x, skin.normal.y, skin.normal.z);
+      let joints = [skin.joints.x, skin.joints.y, skin.joints.z, skin.joints.w];
+      for w in range(0u, 4)
+      {
+        let joint = joints[w] as uint;
+        let weight = skin.weights[w];
+        let local = math::Vec3f::new(weight.x, weight.y, weight.z);
+        let delta = pose[joint].orientation * model.joints[joint].orientation.get_conjugate();
+
+        position = position + ((pose[joint].position + pose[joint].orientation.rotate_vec(&local)) * weight.w);
+        normal = normal + (delta.rotate_vec(&bind_normal) * weight.w);
+      }
+      normal.normalize();
+
+      assert!(close(position, mesh.positions[i]));
+      assert!(close(normal, mesh.normals[i]));
+    }
+  }
+
+  #[test]
+  fn texel_depths()
+  {
+    /* Two texels wide, one tall; the second is sampled. */
+    let coord = math::Vec2f::new(0.75, 0.5);
+    assert_eq!(sample_texel([10, 20], 2, 1, 1, &coord), math::Vec3f::new(20.0, 20.0, 20.0));
+    assert_eq!(sample_texel([10, 255, 20, 128], 2, 1, 2, &coord), math::Vec3f::new(20.0, 20.0, 20.0));
+    assert_eq!(sample_texel([1, 2, 3, 4, 5, 6], 2, 1, 3, &coord), math::Vec3f::new(4.0, 5.0, 6.0));
+    assert_eq!(sample_texel([1, 2, 3, 4, 5, 6, 7, 8], 2, 1, 4, &coord), math::Vec3f::new(5.0, 6.0, 7.0));
+
+    /* Coordinates wrap. */
+    assert_eq!(sample_texel([10, 20], 2, 1, 1, &math::Vec2f::new(-1.25, 2.0)), math::Vec3f::new(20.0, 20.0, 20.0));
+
+    /* Nothing to sample. */
+    assert_eq!(sample_texel([], 0, 0, 3, &coord), math::Vec3f::new(255.0, 255.0, 255.0));
+  }
+}
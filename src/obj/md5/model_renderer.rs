@@ -6,64 +6,133 @@
     File: obj/md5/model_renderer.rs
     Author: Jesse 'Jeaye' Wilkerson
     Description:
-      Renders an MD5 model (which contains
-      multiple meshes).
+      Renders instances of an MD5 model
+      (which contains multiple meshes). The
+      meshes are shared; each instance's pose
+      is uploaded to a texture buffer, which
+      the meshes are skinned against.
 */
 
 use gl2 = opengles::gl2;
 use gl;
-use super::{ Model, Mesh_Renderer };
+use math;
+use res;
+use super::{ Model, Instance, Joint, Mesh_Renderer };
 
 #[path = "../../gl/check.rs"]
 mod check;
 
-struct Model_Renderer<'self>
+static Vert_Shader: &'static str = "data/shaders/md5.vert";
+static Frag_Shader: &'static str = "data/shaders/md5.frag";
+
+struct Model_Renderer
 {
-  model: &'self Model,
-  mesh_renderers: ~[Mesh_Renderer<'self>],
+  model: @Model,
+  mesh_renderers: ~[Mesh_Renderer],
+
+  joint_vbo: gl2::GLuint,
+  joint_tex: gl2::GLuint,
 
-  shader: @mut gl::Shader, /* TODO: shared */
+  shader: @mut gl::Shader,
   proj_loc: gl2::GLint,
   world_loc: gl2::GLint,
+  joints_loc: gl2::GLint,
 }
 
-impl<'self> Model_Renderer<'self>
+impl Model_Renderer
 {
-  pub fn new(m: &'self Model) -> Model_Renderer<'self>
+  pub fn new(m: @Model) -> Model_Renderer
   {
     let mut mr = Model_Renderer
     {
       model: m,
       mesh_renderers: ~[],
 
-      shader: gl::Shader_Builder::new_with_files("data/shaders/md5.vert", "data/shaders/md5.frag"),
+      joint_vbo: 0,
+      joint_tex: 0,
+
+      shader: res::Manager::get().get_shader(Vert_Shader, Frag_Shader),
       proj_loc: 0,
       world_loc: 0,
+      joints_loc: 0,
     };
 
     mr.shader.bind();
     mr.proj_loc = mr.shader.get_uniform_location("proj");
     mr.world_loc = mr.shader.get_uniform_location("world");
+    mr.joints_loc = mr.shader.get_uniform_location("joints");
+    mr.shader.update_uniform_i32(mr.joints_loc, 1);
 
     for x in mr.model.meshes.iter()
     { mr.mesh_renderers.push(Mesh_Renderer::new(x, mr.shader)); }
 
+    let name = check!(gl2::gen_buffers(1));
+    assert!(name.len() == 1);
+    mr.joint_vbo = name[0];
+    check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, mr.joint_vbo));
+    check!(gl2::buffer_data(gl2::TEXTURE_BUFFER, get_pose(mr.model, mr.model.joints), gl2::DYNAMIC_DRAW));
+    check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, 0));
+
+    let name = check!(gl2::gen_textures(1));
+    assert!(name.len() == 1);
+    mr.joint_tex = name[0];
+    check!(gl2::bind_texture(gl2::TEXTURE_BUFFER, mr.joint_tex));
+    check!(gl2::tex_buffer(gl2::TEXTURE_BUFFER, 0x8814 /* RGBA32F */, mr.joint_vbo));
+    check!(gl2::bind_texture(gl2::TEXTURE_BUFFER, 0));
+
     mr
   }
 
-  pub fn render(&mut self)
+  /* Gives the shader and textures back to the resource manager. */
+  pub fn release(&mut self)
+  {
+    res::Manager::get().release_shader(Vert_Shader, Frag_Shader);
+    for x in self.mesh_renderers.mut_iter()
+    { x.release(); }
+  }
+
+  /* The instance needs to be of the same model. */
+  pub fn render(&self, instance: &Instance)
   {
+    check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, self.joint_vbo));
+    check!(gl2::buffer_sub_data(gl2::TEXTURE_BUFFER, 0, get_pose(self.model, instance.get_pose())));
+    check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, 0));
+
     check!(gl2::front_face(gl2::CW));
 
     let camera = gl::Camera::get_active();
     self.shader.bind();
     self.shader.update_uniform_mat(self.proj_loc, &camera.projection);
-    self.shader.update_uniform_mat(self.world_loc, &camera.view);
+    self.shader.update_uniform_mat(self.world_loc, &(instance.local_to_world * camera.view));
+
+    check!(gl2::active_texture(gl2::TEXTURE0 + 1));
+    check!(gl2::bind_texture(gl2::TEXTURE_BUFFER, self.joint_tex));
+    check!(gl2::active_texture(gl2::TEXTURE0));
 
     for x in self.mesh_renderers.iter()
     { x.render(); }
 
+    check!(gl2::active_texture(gl2::TEXTURE0 + 1));
+    check!(gl2::bind_texture(gl2::TEXTURE_BUFFER, 0));
+    check!(gl2::active_texture(gl2::TEXTURE0));
+
     check!(gl2::front_face(gl2::CCW));
   }
 }
 
+/* Three texels per joint, as md5.vert expects: the position, the
+ * orientation, and the change in orientation from the bind pose. */
+fn get_pose(model: &Model, joints: &[Joint]) -> ~[math::Vec4f]
+{
+  let mut pose = ~[];
+  for (joint, bind) in joints.iter().zip(model.joints.iter())
+  {
+    let delta = joint.orientation * bind.orientation.get_conjugate();
+    pose.push(math::Vec4f::new(joint.position.x, joint.position.y, joint.position.z, 0.0));
+    pose.push(math::Vec4f::new(joint.orientation.x, joint.orientation.y,
+                               joint.orientation.z, joint.orientation.w));
+    pose.push(math::Vec4f::new(delta.x, delta.y, delta.z, delta.w));
+  }
+  pose
+}
+
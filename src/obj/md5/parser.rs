@@ -0,0 +1,377 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/md5/parser.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Splits MD5 text files into tokens,
+      which are words, quoted strings, and
+      brackets, each with the line and
+      column it started at, so errors can
+      point to where a file went wrong.
+*/
+
+use std::{ io, path, str };
+use math;
+
+/* Unwraps a parse result, or returns its error. */
+macro_rules! read
+(
+  ($res:expr) =>
+  (
+    match $res
+    {
+      Ok(x) => { x },
+      Err(err) => { return Err(err); }
+    }
+  );
+)
+
+pub struct Parse_Error
+{
+  file: ~str,
+  line: uint, /* 1 based; 0 if the file couldn't be read. */
+  column: uint,
+  message: ~str,
+}
+
+impl ToStr for Parse_Error
+{
+  fn to_str(&self) -> ~str
+  {
+    if self.line == 0
+    { fmt!("%s: %s", self.file, self.message) }
+    else
+    { fmt!("%s:%u:%u: %s", self.file, self.line, self.column, self.message) }
+  }
+}
+
+#[deriving(Clone)]
+struct Token
+{
+  text: ~str,
+  quoted: bool, /* The quotes aren't kept in the text. */
+  line: uint,
+  column: uint,
+}
+
+pub struct Parser
+{
+  file: ~str,
+  tokens: ~[Token],
+  position: uint, /* Of the next token. */
+}
+
+impl Parser
+{
+  pub fn new(file: &str) -> Result<Parser, Parse_Error>
+  {
+    match io::read_whole_file_str(&path::Path(file))
+    {
+      Ok(text) => { Parser::new_from_str(file, text) },
+      Err(err) => { Err(Parse_Error { file: file.to_owned(), line: 0, column: 0, message: err }) }
+    }
+  }
+
+  /* The file is only used for errors. */
+  pub fn new_from_str(file: &str, text: &str) -> Result<Parser, Parse_Error>
+  {
+    let mut parser = Parser
+    {
+      file: file.to_owned(),
+      tokens: ~[],
+      position: 0,
+    };
+    match tokenize(text)
+    {
+      Ok(tokens) => { parser.tokens = tokens; Ok(parser) },
+      Err((line, column, message)) =>
+      { Err(Parse_Error { file: file.to_owned(), line: line, column: column, message: message }) }
+    }
+  }
+
+  pub fn is_done(&self) -> bool
+  { self.position >= self.tokens.len() }
+
+  /* What is being read is named in the error if there's nothing left. */
+  pub fn next(&mut self, what: &str) -> Result<Token, Parse_Error>
+  {
+    if self.is_done()
+    { return Err(self.error(fmt!("Expected %s but reached the end", what))); }
+
+    self.position += 1;
+    Ok(self.tokens[self.position - 1].clone())
+  }
+
+  /* Reads a word, which can't be quoted. */
+  pub fn expect(&mut self, text: &str) -> Result<(), Parse_Error>
+  {
+    let token = read!(self.next(fmt!("'%s'", text)));
+    if token.quoted || token.text.as_slice() != text
+    { return Err(self.error_at(&token, fmt!("Expected '%s' but found '%s'", text, token.text))); }
+    Ok(())
+  }
+
+  pub fn read_string(&mut self, what: &str) -> Result<~str, Parse_Error>
+  {
+    let token = read!(self.next(what));
+    if !token.quoted
+    { return Err(self.error_at(&token, fmt!("Expected a quoted %s but found '%s'", what, token.text))); }
+    Ok(token.text)
+  }
+
+  pub fn read_i32(&mut self, what: &str) -> Result<i32, Parse_Error>
+  {
+    let token = read!(self.next(what));
+    match FromStr::from_str(token.text)
+    {
+      Some(x) if !token.quoted => { Ok(x) },
+      _ => { Err(self.error_at(&token, fmt!("Expected an integer %s but found '%s'", what, token.text))) }
+    }
+  }
+
+  pub fn read_f32(&mut self, what: &str) -> Result<f32, Parse_Error>
+  {
+    let token = read!(self.next(what));
+    match FromStr::from_str(token.text)
+    {
+      Some(x) if !token.quoted => { Ok(x) },
+      _ => { Err(self.error_at(&token, fmt!("Expected a number %s but found '%s'", what, token.text))) }
+    }
+  }
+
+  /* ( x y ) */
+  pub fn read_vec2(&mut self, what: &str) -> Result<math::Vec2f, Parse_Error>
+  {
+    read!(self.expect("("));
+    let x = read!(self.read_f32(what));
+    let y = read!(self.read_f32(what));
+    read!(self.expect(")"));
+    Ok(math::Vec2f::new(x, y))
+  }
+
+  /* ( x y z ) */
+  pub fn read_vec3(&mut self, what: &str) -> Result<math::Vec3f, Parse_Error>
+  {
+    read!(self.expect("("));
+    let x = read!(self.read_f32(what));
+    let y = read!(self.read_f32(what));
+    let z = read!(self.read_f32(what));
+    read!(self.expect(")"));
+    Ok(math::Vec3f::new(x, y, z))
+  }
+
+  /* Skips the rest of the line of the last token read. */
+  pub fn skip_line(&mut self)
+  {
+    if self.position == 0
+    { return; }
+
+    let line = self.tokens[self.position - 1].line;
+    while !self.is_done() && self.tokens[self.position].line == line
+    { self.position += 1; }
+  }
+
+  pub fn error_at(&self, token: &Token, message: ~str) -> Parse_Error
+  { Parse_Error { file: self.file.clone(), line: token.line, column: token.column, message: message } }
+
+  /* At the last token read, or the start if there isn't one. */
+  pub fn error(&self, message: ~str) -> Parse_Error
+  {
+    if self.position == 0
+    { Parse_Error { file: self.file.clone(), line: 1, column: 1, message: message } }
+    else
+    { self.error_at(&self.tokens[self.position - 1], message) }
+  }
+}
+
+/* Words are split on whitespace and brackets, which are their own
+ * tokens. Comments run from // to the end of the line. */
+fn tokenize(text: &str) -> Result<~[Token], (uint, uint, ~str)>
+{
+  let mut tokens = ~[];
+  let chars: ~[char] = text.iter().collect();
+  let (mut line, mut column) = (1u, 1u);
+  let mut i = 0;
+  while i < chars.len()
+  {
+    let ch = chars[i];
+    let (start_line, start_column) = (line, column);
+    if ch == '\n'
+    {
+      line += 1;
+      column = 1;
+      i += 1;
+    }
+    else if ch.is_whitespace()
+    {
+      column += 1;
+      i += 1;
+    }
+    else if ch == '/' && i + 1 < chars.len() && chars[i + 1] == '/'
+    {
+      while i < chars.len() && chars[i] != '\n'
+      { i += 1; }
+    }
+    else if ch == '"'
+    {
+      let mut token = ~"";
+      i += 1;
+      column += 1;
+      loop
+      {
+        if i >= chars.len() || chars[i] == '\n'
+        { return Err((start_line, start_column, ~"Unterminated string")); }
+        i += 1;
+        column += 1;
+        if chars[i - 1] == '"'
+        { break; }
+        token.push_char(chars[i - 1]);
+      }
+      tokens.push(Token { text: token, quoted: true, line: start_line, column: start_column });
+    }
+    else if is_bracket(ch)
+    {
+      tokens.push(Token { text: str::from_char(ch), quoted: false, line: start_line, column: start_column });
+      i += 1;
+      column += 1;
+    }
+    else
+    {
+      let mut token = ~"";
+      while i < chars.len() && !chars[i].is_whitespace() && !is_bracket(chars[i]) && chars[i] != '"'
+      {
+        token.push_char(chars[i]);
+        i += 1;
+        column += 1;
+      }
+      tokens.push(Token { text: token, quoted: false, line: start_line, column: start_column });
+    }
+  }
+  Ok(tokens)
+}
+
+fn is_bracket(ch: char) -> bool
+{ ch == '{' || ch == '}' || ch == '(' || ch == ')' }
+
+/* Shared by the tests of everything that's parsed. */
+#[cfg(test)]
+pub mod test_support
+{
+  use super::{ Parser, Parse_Error };
+
+  /* Parses the text into the item with its load function. */
+  pub fn load<T>(file: &str, text: &str, item: T,
+                 load: &fn(&mut T, &mut Parser) -> Result<(), Parse_Error>) -> Result<T, Parse_Error>
+  {
+    let mut parser = match Parser::new_from_str(file, text)
+    {
+      Ok(parser) => { parser },
+      Err(err) => { return Err(err); }
+    };
+    let mut item = item;
+    match load(&mut item, &mut parser)
+    {
+      Ok(()) => { Ok(item) },
+      Err(err) => { Err(err) }
+    }
+  }
+
+  /* Where a load that had to fail did. */
+  pub fn error_position<T>(res: Result<T, Parse_Error>) -> (uint, uint)
+  {
+    match res
+    {
+      Ok(_) => { fail!("Loaded malformed text"); },
+      Err(err) => { (err.line, err.column) }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test
+{
+  use super::{ Parser, Parse_Error };
+
+  fn parse(text: &str) -> Parser
+  {
+    match Parser::new_from_str("test.md5mesh", text)
+    {
+      Ok(parser) => { parser },
+      Err(err) => { fail!(err.to_str()) }
+    }
+  }
+
+  fn position(err: &Parse_Error) -> (uint, uint)
+  { (err.line, err.column) }
+
+  #[test]
+  fn tokens()
+  {
+    let mut parser = parse("joints { // Comment\n  \"root joint\" -1 ( 0.5 0 0 )\n}");
+    let expected = [ ("joints", false, 1, 1), ("{", false, 1, 8),
+                     ("root joint", true, 2, 3), ("-1", false, 2, 16),
+                     ("(", false, 2, 19), ("0.5", false, 2, 21), ("0", false, 2, 25),
+                     ("0", false, 2, 27), (")", false, 2, 29), ("}", false, 3, 1) ];
+    for &(text, quoted, line, column) in expected.iter()
+    {
+      let token = parser.next("a token").unwrap();
+      assert_eq!(token.text.as_slice(), text);
+      assert_eq!(token.quoted, quoted);
+      assert_eq!((token.line, token.column), (line, column));
+    }
+    assert!(parser.is_done());
+  }
+
+  #[test]
+  fn unterminated_string()
+  {
+    match Parser::new_from_str("test.md5mesh", "joints {\n  \"root -1")
+    {
+      Ok(_) => { fail!("Parsed an unterminated string"); },
+      Err(err) => { assert_eq!(position(&err), (2, 3)); }
+    }
+  }
+
+  #[test]
+  fn truncated()
+  {
+    let mut parser = parse("numJoints");
+    parser.expect("numJoints").unwrap();
+    match parser.read_i32("joint count")
+    {
+      Ok(_) => { fail!("Read past the end"); },
+      Err(err) => { assert_eq!(position(&err), (1, 1)); }
+    }
+  }
+
+  #[test]
+  fn bad_number()
+  {
+    let mut parser = parse("numJoints\n  \"one\" two");
+    parser.expect("numJoints").unwrap();
+    match parser.read_i32("joint count")
+    {
+      Ok(_) => { fail!("Read a quoted integer"); },
+      Err(err) => { assert_eq!(position(&err), (2, 3)); }
+    }
+    match parser.read_f32("joint position")
+    {
+      Ok(_) => { fail!("Read a word as a number"); },
+      Err(err) => { assert_eq!(position(&err), (2, 9)); }
+    }
+  }
+
+  #[test]
+  fn bad_vec()
+  {
+    let mut parser = parse("( 1 2 3 4 )");
+    match parser.read_vec3("position")
+    {
+      Ok(_) => { fail!("Read a vec3 with four components"); },
+      Err(err) => { assert_eq!(position(&err), (1, 9)); }
+    }
+  }
+}
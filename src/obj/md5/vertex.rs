@@ -14,7 +14,11 @@ use math;
 struct Vertex
 {
   position: math::Vec3f,
+
+  /* In the bind pose. The tangent's w is the handedness of the bitangent. */
   normal: math::Vec3f,
+  tangent: math::Vec4f,
+
   tex_coord: math::Vec2f,
   start_weight: i32,
   weight_count: i32,
@@ -28,6 +32,7 @@ impl Vertex
     {
       position: math::Vec3f::zero(),
       normal: math::Vec3f::zero(),
+      tangent: math::Vec4f::zero(),
       tex_coord: math::Vec2f::zero(),
       start_weight: 0,
       weight_count: 0,
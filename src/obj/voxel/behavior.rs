@@ -11,3 +11,10 @@
 
 pub static Visible: u32 = (1 << 31);
 
+/* Anchored voxels are indestructible and hold up
+ * everything that's connected to them. */
+pub static Anchor: u32 = (1 << 30);
+
+/* The remaining bits index into the map's voxel list. */
+pub static Index_Mask: u32 = !(Visible | Anchor);
+
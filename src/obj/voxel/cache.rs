@@ -0,0 +1,113 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/voxel/cache.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Saves and loads voxelized maps to and
+      from disk so that unchanged maps don't
+      need to be voxelized on every load.
+
+      Format (little endian):
+        magic     [u8, ..4]  "Q3VX"
+        version   u32
+        key       u32 length, then bytes
+        map       see Compressed_Map::write; each voxel's
+                  material (color and behaviors) is
+                  stored in its palette
+*/
+
+use std::{ io, os, path, str };
+use extra::sha1::Sha1;
+use extra::digest::Digest;
+use super::{ Map, Compressed_Map };
+
+static Magic: &'static str = "Q3VX";
+static Version: u32 = 1;
+
+/* Keys are a hash and the voxelizer's parameters; anything longer is corrupt. */
+static Max_Key_Length: uint = 256;
+
+/* Identifies a voxelization by its source file and the
+ * parameters given to the voxelizer. */
+pub fn key(source_file: &str, voxel_size: f32) -> Result<~str, ~str>
+{
+  let bytes = match io::read_whole_file(&path::Path(source_file))
+  {
+    Ok(b) => { b },
+    Err(err) => { return Err(err); }
+  };
+
+  let mut sha = Sha1::new();
+  sha.input(bytes);
+  Ok(fmt!("%s-%f-%u", sha.result_str(), voxel_size as float, Version as uint))
+}
+
+impl Map
+{
+  /* Written beside the file, then moved over it, so an
+   * interrupted save doesn't leave a truncated cache. */
+  pub fn save(&self, file: &str, key: &str) -> Result<(), ~str>
+  {
+    let file_path = path::Path(file);
+    let dir = file_path.dir_path();
+    if !os::path_exists(&dir) && !os::mkdir_recursive(&dir, 493 /* 0755 */)
+    { return Err(fmt!("Unable to create directory %s", dir.to_str())); }
+
+    let temp_path = path::Path(file + ".tmp");
+    {
+      /* The writer is closed at the end of this scope. */
+      let fio = match io::file_writer(&temp_path, [io::Create, io::Truncate])
+      {
+        Ok(w) => { w },
+        Err(err) => { return Err(err); }
+      };
+
+      fio.write(Magic.as_bytes());
+      fio.write_le_u32(Version);
+      fio.write_le_u32(key.len() as u32);
+      fio.write(key.as_bytes());
+      Compressed_Map::new(self).write(fio);
+    }
+
+    if !os::rename_file(&temp_path, &file_path)
+    {
+      os::remove_file(&temp_path);
+      return Err(fmt!("Unable to move %s to %s", temp_path.to_str(), file));
+    }
+
+    Ok(())
+  }
+
+  pub fn load(file: &str, key: &str) -> Result<Map, ~str>
+  {
+    let fio = match io::file_reader(&path::Path(file))
+    {
+      Ok(r) => { r },
+      Err(err) => { return Err(err); }
+    };
+
+    if fio.read_bytes(Magic.len()) != Magic.as_bytes().to_owned()
+    { return Err(fmt!("%s is not a voxel map", file)); }
+    let version = fio.read_le_u32();
+    if version != Version
+    { return Err(fmt!("Outdated voxel map version %u", version as uint)); }
+
+    let key_len = fio.read_le_u32() as uint;
+    if key_len > Max_Key_Length
+    { return Err(fmt!("%s is corrupt: key of %u bytes", file, key_len)); }
+    let key_bytes = fio.read_bytes(key_len);
+    if key_bytes.len() != key_len || !str::is_utf8(key_bytes)
+    { return Err(fmt!("%s is corrupt: invalid key", file)); }
+    if str::from_bytes(key_bytes).as_slice() != key
+    { return Err(~"Voxel map is stale"); }
+
+    match Compressed_Map::read(fio)
+    {
+      Ok(compressed) => { Ok(compressed.to_map()) }
+      Err(err) => { Err(fmt!("%s is corrupt: %s", file, err)) }
+    }
+  }
+}
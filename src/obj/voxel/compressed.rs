@@ -0,0 +1,644 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/voxel/compressed.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A compact, read-only copy of a voxel
+      map. Each distinct color and set of
+      behaviors goes into a palette and
+      each row of cells along X is run
+      length encoded, with runs storing
+      where they start so that any cell
+      can be found by binary search.
+
+      Rows can also be encoded on their
+      own, as deltas, which carry their
+      colors inline so that they can be
+      applied to any copy of the map.
+*/
+
+use std::{ io, sys, vec };
+use std::num::CheckedMul;
+use std::hashmap::HashMap;
+use math;
+use super::{ Map, Vertex, Visible, Index_Mask };
+use super::map::cell_position;
+
+/* More materials than this can't be indexed by a run. */
+static Max_Materials: uint = 65536;
+
+/* Masks for the color channels, tried in order until the
+ * materials fit; each keeps fewer shades than the last. */
+static Precisions: [u8, ..5] = [ 0xFF, 0xF8, 0xF0, 0xE0, 0xC0 ];
+
+#[deriving(Eq, Clone)]
+pub struct Material
+{
+  color: [u8, ..3],
+  behaviors: u8, /* The high state bits; visibility, anchor, etc. */
+}
+
+/* A stretch of same material cells, up to the next run. */
+pub struct Run
+{
+  start: u16,
+  material: u16,
+}
+
+pub struct Compressed_Map
+{
+  dimensions: math::Vec3i,
+  voxel_size: f32,
+
+  /* Entry zero is always empty space. */
+  palette: ~[Material],
+  runs: ~[Run],
+
+  /* Index of the first run of each row, rows being ordered by
+   * Z and then Y, plus one extra for the end of the last row. */
+  rows: ~[u32],
+}
+
+/* Replacement contents of whole rows of a map. */
+pub struct Delta
+{
+  rows: ~[(u32, ~[(u16, Material)])],
+}
+
+impl Material
+{
+  pub fn empty() -> Material
+  { Material { color: [0, 0, 0], behaviors: 0 } }
+
+  pub fn is_empty(&self) -> bool
+  { (self.behaviors as u32 << 24) & Visible == 0 }
+
+  pub fn get_color(&self) -> math::Vec3f
+  {
+    math::Vec3f::new( self.color[0] as f32 / 255.0,
+                      self.color[1] as f32 / 255.0,
+                      self.color[2] as f32 / 255.0)
+  }
+}
+
+impl Compressed_Map
+{
+  pub fn new(map: &Map) -> Compressed_Map
+  {
+    /* Fall back to fewer shades of each color if there are too many. */
+    for &mask in Precisions.iter()
+    {
+      match Compressed_Map::new_with_precision(map, mask)
+      {
+        Some(compressed) => { return compressed; },
+        None => { }
+      }
+    }
+
+    /* With a single color, there's a material per set of
+     * behaviors, of which there are at most 256. */
+    Compressed_Map::new_with_precision(map, 0x00).unwrap()
+  }
+
+  /* Color channels are masked to the given bits. */
+  fn new_with_precision(map: &Map, mask: u8) -> Option<Compressed_Map>
+  {
+    let dims = map.dimensions;
+    let mut compressed = Compressed_Map
+    {
+      dimensions: dims,
+      voxel_size: map.voxel_size,
+
+      palette: ~[Material::empty()],
+      runs: ~[],
+      rows: vec::with_capacity((dims.y * dims.z) as uint + 1),
+    };
+
+    let mut lookup = HashMap::new();
+    for row in range(0, (dims.y * dims.z) as uint)
+    {
+      compressed.rows.push(compressed.runs.len() as u32);
+      for (start, material) in encode_row(map, row).move_iter()
+      {
+        let material = Material
+        {
+          color: [ material.color[0] & mask, material.color[1] & mask, material.color[2] & mask ],
+          behaviors: material.behaviors
+        };
+        let key = (material.color[0], material.color[1], material.color[2], material.behaviors);
+        let index = if material.is_empty()
+        { 0 }
+        else
+        {
+          match lookup.find(&key)
+          {
+            Some(i) => { *i },
+            None =>
+            {
+              if compressed.palette.len() == Max_Materials
+              { return None; }
+              compressed.palette.push(material);
+              lookup.insert(key, compressed.palette.len() as u16 - 1);
+              compressed.palette.len() as u16 - 1
+            }
+          }
+        };
+
+        /* Masking may have merged neighbouring runs. */
+        match compressed.runs.last_opt()
+        {
+          Some(last) if (compressed.runs.len() as u32) > compressed.rows[row] && last.material == index =>
+          { loop; }
+          _ => { }
+        }
+        compressed.runs.push(Run { start: start, material: index });
+      }
+    }
+    compressed.rows.push(compressed.runs.len() as u32);
+
+    Some(compressed)
+  }
+
+  pub fn get(&self, cell: &math::Vec3i) -> Material
+  {
+    let dims = self.dimensions;
+    if  cell.x < 0 || cell.y < 0 || cell.z < 0 ||
+        cell.x >= dims.x || cell.y >= dims.y || cell.z >= dims.z
+    { return Material::empty(); }
+
+    /* Find the last run starting at or before the cell. */
+    let row = ((cell.z * dims.y) + cell.y) as uint;
+    let runs = self.runs.slice(self.rows[row] as uint, self.rows[row + 1] as uint);
+    let (mut low, mut high) = (0u, runs.len());
+    while high - low > 1
+    {
+      let mid = (low + high) / 2;
+      if runs[mid].start as i32 <= cell.x
+      { low = mid; }
+      else
+      { high = mid; }
+    }
+    self.palette[runs[low].material]
+  }
+
+  /* Expands back into a full map. */
+  pub fn to_map(&self) -> Map
+  {
+    let dims = self.dimensions;
+    let mut map = Map::new_empty(dims, self.voxel_size);
+    {
+      let states = &mut map.states;
+      for row in range(0, (dims.y * dims.z) as uint)
+      {
+        let (first, last) = (self.rows[row] as uint, self.rows[row + 1] as uint);
+        for r in range(first, last)
+        {
+          let material = &self.palette[self.runs[r].material];
+          if material.is_empty()
+          { loop; }
+
+          let end = if r + 1 < last { self.runs[r + 1].start as i32 } else { dims.x };
+          let (y, z) = ((row as i32) % dims.y, (row as i32) / dims.y);
+          for x in range(self.runs[r].start as i32, end)
+          {
+            map.voxels.push(Vertex::new(cell_position(&dims, &math::Vec3i::new(x, y, z)), material.get_color()));
+            states[(z * dims.x * dims.y) + (y * dims.x) + x] =
+              (map.voxels.len() as u32 - 1) | (material.behaviors as u32 << 24);
+          }
+        }
+      }
+    }
+    map.bake_occlusion();
+    map.build_lods();
+
+    map
+  }
+
+  /* Bytes used, including the vectors' contents. */
+  pub fn memory_usage(&self) -> uint
+  {
+    sys::size_of::<Compressed_Map>() +
+    (self.palette.len() * sys::size_of::<Material>()) +
+    (self.runs.len() * sys::size_of::<Run>()) +
+    (self.rows.len() * sys::size_of::<u32>())
+  }
+
+  /* Format (little endian):
+   *   dimensions [u32, ..3]
+   *   voxel_size f32
+   *   palette    u32 count, then [u8, ..4] (color, behaviors) each
+   *   rows       u32 first run of each row, plus the end
+   *   runs       u32 count, then u16 start and u16 material each */
+  pub fn write(&self, fio: @io::Writer)
+  {
+    fio.write_le_u32(self.dimensions.x as u32);
+    fio.write_le_u32(self.dimensions.y as u32);
+    fio.write_le_u32(self.dimensions.z as u32);
+    fio.write_le_f32(self.voxel_size);
+
+    fio.write_le_u32(self.palette.len() as u32);
+    for material in self.palette.iter()
+    {
+      fio.write(material.color);
+      fio.write_u8(material.behaviors);
+    }
+
+    for row in self.rows.iter()
+    { fio.write_le_u32(*row); }
+
+    fio.write_le_u32(self.runs.len() as u32);
+    for run in self.runs.iter()
+    {
+      fio.write_le_u16(run.start);
+      fio.write_le_u16(run.material);
+    }
+  }
+
+  /* Counts aren't trusted, so everything is read until the
+   * reader runs dry, rather than allocated up front. */
+  pub fn read(fio: @io::Reader) -> Result<Compressed_Map, ~str>
+  {
+    let dims = math::Vec3i::new(fio.read_le_u32() as i32,
+                                fio.read_le_u32() as i32,
+                                fio.read_le_u32() as i32);
+    let voxel_size = fio.read_le_f32();
+    if fio.eof() || dims.x <= 0 || dims.y <= 0 || dims.z <= 0 || !(voxel_size > 0.0)
+    { return Err(~"Invalid compressed map header"); }
+
+    /* Runs start within a u16, so rows can't be any longer. */
+    let row_count = (dims.y as uint).checked_mul(&(dims.z as uint));
+    let cell_count = row_count.chain(|rows| rows.checked_mul(&(dims.x as uint)));
+    if dims.x as uint > (1 << 16) || cell_count.is_none() || cell_count.unwrap() > (Index_Mask as uint)
+    { return Err(~"Invalid compressed map dimensions"); }
+    let row_count = row_count.unwrap();
+
+    let palette_len = read_u32(fio).unwrap_or(0) as uint;
+    if palette_len == 0 || palette_len > Max_Materials
+    { return Err(~"Invalid compressed map palette"); }
+    let mut palette = ~[];
+    while palette.len() < palette_len
+    {
+      match read_material(fio)
+      {
+        Some(material) => { palette.push(material); }
+        None => { return Err(~"Compressed map is truncated"); }
+      }
+    }
+
+    let mut rows = ~[];
+    while rows.len() < row_count + 1
+    {
+      match read_u32(fio)
+      {
+        Some(row) => { rows.push(row); }
+        None => { return Err(~"Compressed map is truncated"); }
+      }
+    }
+
+    let run_count = match read_u32(fio)
+    {
+      Some(count) => { count as uint },
+      None => { return Err(~"Compressed map is truncated"); }
+    };
+    let mut runs = ~[];
+    while runs.len() < run_count
+    {
+      /* The start and material, as u16s. */
+      match read_u32(fio)
+      {
+        Some(run) => { runs.push(Run { start: run as u16, material: (run >> 16) as u16 }); }
+        None => { return Err(~"Compressed map is truncated"); }
+      }
+    }
+
+    /* Every lookup indexes with these, so check them once here;
+     * each row needs at least one run, starting at its first cell. */
+    if rows[0] != 0
+    { return Err(~"Invalid compressed map rows"); }
+    for i in range(0, rows.len() - 1)
+    {
+      if rows[i] >= rows[i + 1] || rows[i + 1] as uint > run_count || runs[rows[i]].start != 0
+      { return Err(~"Invalid compressed map rows"); }
+    }
+    if runs.iter().any(|run| run.material as uint >= palette_len || run.start as i32 >= dims.x)
+    { return Err(~"Invalid compressed map runs"); }
+
+    Ok(Compressed_Map
+    {
+      dimensions: dims,
+      voxel_size: voxel_size,
+
+      palette: palette,
+      runs: runs,
+      rows: rows,
+    })
+  }
+}
+
+impl Map
+{
+  /* Bytes used by the dense representation, including levels of
+   * detail and occlusion, but not the renderer's copies. */
+  pub fn memory_usage(&self) -> uint
+  {
+    let states = self.states.len() * sys::size_of::<u32>();
+    let lods = self.lods.iter().fold(0, |acc, lod|
+    {
+      acc + (lod.states.len() * sys::size_of::<u32>()) +
+            (lod.voxels.len() * sys::size_of::<Vertex>())
+    });
+
+    sys::size_of::<Map>() + states + lods +
+    (self.voxels.len() * sys::size_of::<Vertex>()) +
+    (self.occlusion.len() * sys::size_of::<u8>())
+  }
+
+  /* Encodes every row containing one of the cells. */
+  pub fn encode_delta(&self, cells: &[uint]) -> Delta
+  {
+    let dims = self.dimensions;
+    let mut rows = cells.map(|cell| (*cell as i32 / dims.x) as u32);
+    rows.sort();
+    rows.dedup();
+
+    Delta { rows: rows.iter().map(|row| (*row, encode_row(self, *row as uint))).collect() }
+  }
+}
+
+impl Delta
+{
+  /* Format (little endian):
+   *   rows u32 count, then for each row:
+   *     row  u32
+   *     runs u16 count, then u16 start and [u8, ..4] material each */
+  pub fn to_bytes(&self) -> ~[u8]
+  {
+    do io::with_bytes_writer |fio|
+    {
+      fio.write_le_u32(self.rows.len() as u32);
+      for &(row, ref runs) in self.rows.iter()
+      {
+        fio.write_le_u32(row);
+        fio.write_le_u16(runs.len() as u16);
+        for &(start, ref material) in runs.iter()
+        {
+          fio.write_le_u16(start);
+          fio.write(material.color);
+          fio.write_u8(material.behaviors);
+        }
+      }
+    }
+  }
+
+  /* This comes from the network, so every count is checked
+   * against the bytes left before anything is allocated. */
+  pub fn from_bytes(bytes: &[u8]) -> Result<Delta, ~str>
+  {
+    do io::with_bytes_reader(bytes) |fio|
+    {
+      let remaining = || bytes.len() - fio.tell();
+      if remaining() < 4
+      { return Err(~"Delta is truncated"); }
+      let count = fio.read_le_u32() as uint;
+      if count > remaining() / 6
+      { return Err(~"Delta is truncated"); }
+
+      let mut rows = vec::with_capacity(count);
+      for _ in range(0, count)
+      {
+        if remaining() < 6
+        { return Err(~"Delta is truncated"); }
+        let row = fio.read_le_u32();
+        let run_count = fio.read_le_u16() as uint;
+        if run_count > remaining() / 6
+        { return Err(~"Delta is truncated"); }
+
+        let mut runs = vec::with_capacity(run_count);
+        for _ in range(0, run_count)
+        {
+          let start = fio.read_le_u16();
+          match read_material(fio)
+          {
+            Some(material) => { runs.push((start, material)); }
+            None => { return Err(~"Delta is truncated"); }
+          }
+        }
+        rows.push((row, runs));
+      }
+      Ok(Delta { rows: rows })
+    }
+  }
+}
+
+/* Color, then behaviors; None if the reader runs out. Readers
+ * don't fail past the end, so the length read is checked. */
+fn read_material(fio: @io::Reader) -> Option<Material>
+{
+  let bytes = fio.read_bytes(4);
+  if bytes.len() < 4
+  { None }
+  else
+  { Some(Material { color: [ bytes[0], bytes[1], bytes[2] ], behaviors: bytes[3] }) }
+}
+
+/* Little endian; None if the reader runs out. */
+fn read_u32(fio: @io::Reader) -> Option<u32>
+{
+  let bytes = fio.read_bytes(4);
+  if bytes.len() < 4
+  { None }
+  else
+  { Some(bytes[0] as u32 | (bytes[1] as u32 << 8) | (bytes[2] as u32 << 16) | (bytes[3] as u32 << 24)) }
+}
+
+/* The runs of a row, as (start, material) pairs. */
+fn encode_row(map: &Map, row: uint) -> ~[(u16, Material)]
+{
+  let dims = map.dimensions;
+  let states = &map.states;
+  let first = row * dims.x as uint;
+
+  let mut runs: ~[(u16, Material)] = ~[];
+  for x in range(0, dims.x as uint)
+  {
+    let state = states[first + x];
+    let material = if (state & Visible) == 0
+    { Material::empty() }
+    else
+    {
+      let color = map.voxels[state & Index_Mask].color;
+      Material
+      {
+        color: [ (color.x * 255.0) as u8, (color.y * 255.0) as u8, (color.z * 255.0) as u8 ],
+        behaviors: ((state & !Index_Mask) >> 24) as u8
+      }
+    };
+
+    match runs.last_opt()
+    {
+      Some(&(_, ref last)) if *last == material => { loop; }
+      _ => { }
+    }
+    runs.push((x as u16, material));
+  }
+  runs
+}
+
+#[cfg(test)]
+mod test
+{
+  use std::io;
+  use math;
+  use super::{ Compressed_Map, Delta, Material };
+  use super::super::{ Map, Vertex, Visible, Anchor, Index_Mask };
+  use super::super::map::{ cell_index, cell_position };
+
+  static Red: math::Vec3f = math::Vec3f { x: 1.0, y: 0.0, z: 0.0 };
+  static White: math::Vec3f = math::Vec3f { x: 1.0, y: 1.0, z: 1.0 };
+
+  /* A 4x4x4 grid with an anchored red floor and a white pillar. */
+  fn fixture() -> Map
+  {
+    let mut map = Map::new_empty(math::Vec3i::new(4, 4, 4), 0.5);
+    for z in range(0, 4)
+    { for x in range(0, 4)
+      { add(&mut map, &math::Vec3i::new(x, 0, z), Red, Anchor); }
+    }
+    for y in range(1, 4)
+    { add(&mut map, &math::Vec3i::new(1, y, 2), White, 0); }
+    map
+  }
+
+  fn add(map: &mut Map, cell: &math::Vec3i, color: math::Vec3f, behaviors: u32)
+  {
+    let dims = map.dimensions;
+    map.voxels.push(Vertex::new(cell_position(&dims, cell), color));
+    map.occlusion.push_all([255u8, ..6]);
+    let index = map.voxels.len() as u32 - 1;
+    assert!(map.set(cell, index | Visible | behaviors));
+  }
+
+  /* Visibility, behaviors, and colors match in every cell. */
+  fn assert_same(a: &Map, b: &Map)
+  {
+    assert_eq!(a.dimensions, b.dimensions);
+    for i in range(0, a.states.len())
+    {
+      let (sa, sb) = (a.states[i], b.states[i]);
+      assert_eq!(sa & !Index_Mask, sb & !Index_Mask);
+      if (sa & Visible) != 0
+      { assert_eq!(a.voxels[sa & Index_Mask].color, b.voxels[sb & Index_Mask].color); }
+    }
+  }
+
+  fn to_bytes(compressed: &Compressed_Map) -> ~[u8]
+  { do io::with_bytes_writer |fio| { compressed.write(fio); } }
+
+  fn from_bytes(bytes: &[u8]) -> Result<Compressed_Map, ~str>
+  { do io::with_bytes_reader(bytes) |fio| { Compressed_Map::read(fio) } }
+
+  #[test]
+  fn round_trip()
+  {
+    let map = fixture();
+    let compressed = Compressed_Map::new(&map);
+
+    /* Empty space, red, and white. */
+    assert_eq!(compressed.palette.len(), 3);
+    assert_same(&map, &compressed.to_map());
+
+    match from_bytes(to_bytes(&compressed))
+    {
+      Ok(read) =>
+      {
+        assert_eq!(read.rows, compressed.rows);
+        assert_eq!(read.runs.len(), compressed.runs.len());
+        assert_same(&map, &read.to_map());
+      }
+      Err(err) => { fail!(err); }
+    }
+  }
+
+  #[test]
+  fn get()
+  {
+    let compressed = Compressed_Map::new(&fixture());
+    let red = Material { color: [255, 0, 0], behaviors: ((Visible | Anchor) >> 24) as u8 };
+    let white = Material { color: [255, 255, 255], behaviors: (Visible >> 24) as u8 };
+
+    assert_eq!(compressed.get(&math::Vec3i::new(3, 0, 3)), red);
+    assert_eq!(compressed.get(&math::Vec3i::new(1, 3, 2)), white);
+    assert!(compressed.get(&math::Vec3i::new(0, 3, 2)).is_empty());
+    assert!(compressed.get(&math::Vec3i::new(2, 3, 2)).is_empty());
+
+    /* Outside of the grid. */
+    assert!(compressed.get(&math::Vec3i::new(4, 0, 0)).is_empty());
+    assert!(compressed.get(&math::Vec3i::new(0, -1, 0)).is_empty());
+  }
+
+  #[test]
+  fn delta_round_trip()
+  {
+    let map = fixture();
+    let dims = map.dimensions;
+    let cells = [ cell_index(&dims, &math::Vec3i::new(1, 2, 2)).unwrap(),
+                  cell_index(&dims, &math::Vec3i::new(3, 2, 2)).unwrap(),
+                  cell_index(&dims, &math::Vec3i::new(0, 0, 0)).unwrap() ];
+    let delta = map.encode_delta(cells);
+
+    /* The first two share a row. */
+    assert_eq!(delta.rows.len(), 2);
+    match Delta::from_bytes(delta.to_bytes())
+    {
+      Ok(read) => { assert!(read.rows == delta.rows); }
+      Err(err) => { fail!(err); }
+    }
+  }
+
+  #[test]
+  fn apply_delta()
+  {
+    let mut changed = fixture();
+    let mut copy = fixture();
+    let dims = changed.dimensions;
+
+    /* Move the top of the pillar over by one. */
+    let (from, to) = (math::Vec3i::new(1, 3, 2), math::Vec3i::new(2, 3, 2));
+    assert!(changed.set(&from, 0));
+    add(&mut changed, &to, Red, 0);
+
+    let delta = changed.encode_delta([ cell_index(&dims, &from).unwrap(), cell_index(&dims, &to).unwrap() ]);
+    assert_eq!(copy.apply_delta(&delta), Ok(2));
+    assert_same(&changed, &copy);
+
+    /* Applying it again changes nothing. */
+    assert_eq!(copy.apply_delta(&delta), Ok(0));
+
+    /* Rows past the grid are refused. */
+    let bad = Delta { rows: ~[(16, ~[(0, Material::empty())])] };
+    assert!(copy.apply_delta(&bad).is_err());
+  }
+
+  /* Every prefix is refused, rather than read past. */
+  #[test]
+  fn truncated()
+  {
+    let map = fixture();
+    let bytes = to_bytes(&Compressed_Map::new(&map));
+    for len in range(0, bytes.len())
+    { assert!(from_bytes(bytes.slice(0, len)).is_err()); }
+
+    let delta = map.encode_delta([0, 63]).to_bytes();
+    for len in range(0, delta.len())
+    { assert!(Delta::from_bytes(delta.slice(0, len)).is_err()); }
+
+    /* Counts far past the data. */
+    assert!(Delta::from_bytes([0xFF, 0xFF, 0xFF, 0xFF, 0, 0]).is_err());
+    let mut huge = bytes.clone();
+    huge[4] = 0xFF; huge[5] = 0xFF; huge[6] = 0xFF; huge[7] = 0x7F;
+    assert!(from_bytes(huge).is_err());
+  }
+}
@@ -0,0 +1,55 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/voxel/debris.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A chunk of voxels that has been
+      disconnected from the map and is
+      now falling freely.
+*/
+
+use math;
+
+/* World units per second, per second. */
+static Gravity: f32 = 9.8;
+
+/* How long (in seconds) debris sticks around before vanishing. */
+static Lifetime: f32 = 5.0;
+
+pub struct Debris
+{
+  /* Indices into the owning map's voxel list. The voxels
+   * keep their original positions; the whole body is
+   * moved by the offset. */
+  voxels: ~[u32],
+  offset: math::Vec3f,
+  velocity: math::Vec3f,
+  time_left: f32,
+}
+
+impl Debris
+{
+  pub fn new(voxels: ~[u32]) -> Debris
+  {
+    Debris
+    {
+      voxels: voxels,
+      offset: math::Vec3f::zero(),
+      velocity: math::Vec3f::zero(),
+      time_left: Lifetime,
+    }
+  }
+
+  pub fn update(&mut self, delta: f32) /* dt is in terms of seconds. */
+  {
+    self.velocity.y -= Gravity * delta;
+    self.offset = self.offset + (self.velocity * delta);
+    self.time_left -= delta;
+  }
+
+  pub fn is_dead(&self) -> bool
+  { self.time_left <= 0.0 }
+}
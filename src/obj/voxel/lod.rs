@@ -0,0 +1,148 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/voxel/lod.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A downsampled copy of the voxel grid,
+      for rendering distant chunks. Each
+      coarse cell averages the colors and
+      occupancy of the cells it covers.
+*/
+
+use std::vec;
+use math;
+use super::{ Vertex, Visible, Index_Mask };
+use super::map::cell_index;
+
+/* Scales of each level, relative to the map. Each must
+ * divide the chunk size, so chunks downsample cleanly. */
+pub static Scales: [i32, ..3] = [ 2, 4, 8 ];
+
+/* Fraction of covered cells that need to be filled for a coarse
+ * cell to be filled. This is low so that thin walls survive. */
+static Min_Occupancy: f32 = 0.1;
+
+/* The index of cells which have never had a voxel, since
+ * zero is the index of the first. */
+static No_Voxel: u32 = Index_Mask;
+
+pub struct Lod
+{
+  scale: i32,
+  dimensions: math::Vec3i,
+
+  /* Same layout as the map's; voxel positions are unused. */
+  states: ~[u32],
+  voxels: ~[Vertex],
+}
+
+impl Lod
+{
+  pub fn new(states: &[u32], voxels: &[Vertex], dims: &math::Vec3i, scale: i32) -> Lod
+  {
+    let lod_dims = math::Vec3i::new((dims.x + scale - 1) / scale,
+                                    (dims.y + scale - 1) / scale,
+                                    (dims.z + scale - 1) / scale);
+    let mut lod = Lod
+    {
+      scale: scale,
+      dimensions: lod_dims,
+
+      states: vec::from_elem((lod_dims.x * lod_dims.y * lod_dims.z) as uint, No_Voxel),
+      voxels: ~[],
+    };
+    lod.refresh(states, voxels, dims, &math::Vec3i::zero(), dims);
+
+    lod
+  }
+
+  /* Resamples every coarse cell covering the map's cells from
+   * start up to (but not including) end. */
+  pub fn refresh(&mut self, states: &[u32], voxels: &[Vertex], dims: &math::Vec3i,
+                 start: &math::Vec3i, end: &math::Vec3i)
+  {
+    let s = self.scale;
+    let lod_start = math::Vec3i::new(start.x / s, start.y / s, start.z / s);
+    let lod_end = math::Vec3i::new( (end.x + s - 1) / s,
+                                    (end.y + s - 1) / s,
+                                    (end.z + s - 1) / s);
+    let covered = (s * s * s) as f32;
+
+    for z in range(lod_start.z, lod_end.z)
+    { for y in range(lod_start.y, lod_end.y)
+      { for x in range(lod_start.x, lod_end.x)
+        {
+          let mut filled = 0;
+          let mut color = math::Vec3f::zero();
+          for fz in range(z * s, (z + 1) * s)
+          { for fy in range(y * s, (y + 1) * s)
+            { for fx in range(x * s, (x + 1) * s)
+              {
+                match cell_index(dims, &math::Vec3i::new(fx, fy, fz))
+                {
+                  Some(i) if (states[i] & Visible) != 0 =>
+                  {
+                    filled += 1;
+                    color = color + voxels[states[i] & Index_Mask].color;
+                  }
+                  _ => { }
+                }
+              }
+            }
+          }
+
+          let cell = math::Vec3i::new(x, y, z);
+          let index = cell_index(&self.dimensions, &cell).unwrap();
+          if (filled as f32 / covered) < Min_Occupancy
+          {
+            self.states[index] &= !Visible;
+            loop;
+          }
+
+          /* Reuse this cell's voxel if it's had one before. */
+          let voxel = Vertex::new(math::Vec3f::new(x as f32, y as f32, z as f32),
+                                  color * (1.0 / filled as f32));
+          if (self.states[index] & Index_Mask) != No_Voxel
+          { self.voxels[self.states[index] & Index_Mask] = voxel; }
+          else
+          {
+            self.voxels.push(voxel);
+            self.states[index] = self.voxels.len() as u32 - 1;
+          }
+          self.states[index] |= Visible;
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test
+{
+  use std::vec;
+  use math;
+  use super::Lod;
+  use super::super::{ Vertex, Visible };
+
+  /* The first voxel's cell is emptied and then filled again. */
+  #[test]
+  fn reuse_first_voxel()
+  {
+    let dims = math::Vec3i::new(2, 2, 2);
+    let voxels = [ Vertex::zero() ];
+    let filled = vec::from_elem(8, Visible);
+    let empty = vec::from_elem(8, 0u32);
+
+    let mut lod = Lod::new(filled, voxels, &dims, 2);
+    assert_eq!(lod.states, ~[Visible]);
+
+    lod.refresh(empty, voxels, &dims, &math::Vec3i::zero(), &dims);
+    assert_eq!(lod.states, ~[0]);
+    lod.refresh(filled, voxels, &dims, &math::Vec3i::zero(), &dims);
+    assert_eq!(lod.states, ~[Visible]);
+    assert_eq!(lod.voxels.len(), 1);
+  }
+}
@@ -10,51 +10,161 @@
       into OpenGL-ready cubes.
 */
 
-use std::{ vec, cmp };
+use std::{ vec, cmp, comm, task, rt, util };
+use std::hashmap::HashSet;
+use extra::arc;
 use math;
 use primitive::Triangle;
-use super::{ Vertex, Visible };
+use super::{ Vertex, Debris, Lod, Delta, Visible, Anchor, Index_Mask };
+use super::{ mesher, lod, occlusion };
 use util::Log;
 
 #[macro_escape]
 #[path = "../../util/log_macros.rs"]
 mod log_macros;
 
+/* Voxelization batches handed to each scheduler thread; more
+ * than one helps balance out dense areas of the map. */
+static Batches_Per_Thread: uint = 4;
+
+/* Face-adjacent neighbours of a cell. */
+pub static Neighbours: [(i32, i32, i32), ..6] =
+[
+  (1, 0, 0), (-1, 0, 0),
+  (0, 1, 0), (0, -1, 0),
+  (0, 0, 1), (0, 0, -1),
+];
+
 struct Map
 {
-  resolution: u32,
+  /* Number of cells along each axis. The cell at half of
+   * them is centered on the origin, so with four cells, the
+   * centers are at -2 through 1 (see cell_position). */
+  dimensions: math::Vec3i,
   voxel_size: f32,
 
-  states: Option<~[u32]>,
+  states: ~[u32],
   voxels: ~[Vertex],
+  debris: ~[Debris],
   error: ~str,
+
+  /* Six ambient occlusion values per voxel; see occlusion.rs. */
+  occlusion: ~[u8],
+
+  /* Downsampled copies of the grid, finest first. */
+  lods: ~[Lod],
+
+  /* Chunks whose states have changed and voxels whose
+   * data has changed, since the renderer last took them. */
+  dirty_chunks: HashSet<uint>,
+  dirty_voxels: HashSet<u32>,
+
+  /* Cells which have been filled or emptied, since
+   * navigation last took them. */
+  dirty_cells: HashSet<uint>,
 }
 
 impl Map
 {
-  pub fn new(tris: &[Triangle], res: u32) -> Result<@mut Map, ~str>
+  pub fn new(tris: &[Triangle], voxel_size: f32) -> Result<@mut Map, ~str>
+  {
+    match Map::new_with_progress(tris, voxel_size, |_, _| true)
+    {
+      Ok(map) => { Ok(@mut map) },
+      Err(err) => { Err(err) }
+    }
+  }
+
+  /* The progress callback receives the number of triangles voxelized
+   * so far and the total; returning false cancels voxelization. Since
+   * the map is owned, this can be run from any task. */
+  pub fn new_with_progress(tris: &[Triangle], voxel_size: f32, progress: &fn(uint, uint) -> bool) -> Result<Map, ~str>
+  {
+    let mut map = Map::new_empty(math::Vec3i::zero(), voxel_size);
+    if !map.voxelize(tris, progress)
+    { return Err(map.error.clone()); }
+
+    Ok(map)
+  }
+
+  /* A map with every cell empty. */
+  pub fn new_empty(dimensions: math::Vec3i, voxel_size: f32) -> Map
   {
-    let map = @mut Map
+    Map
     {
-      resolution: res,
-      voxel_size: 0.0,
+      dimensions: dimensions,
+      voxel_size: voxel_size,
 
-      states: None,
+      states: vec::from_elem((dimensions.x * dimensions.y * dimensions.z) as uint, 0u32),
       voxels: ~[],
+      debris: ~[],
       error: ~"",
+
+      occlusion: ~[],
+      lods: ~[],
+
+      dirty_chunks: HashSet::new(),
+      dirty_voxels: HashSet::new(),
+      dirty_cells: HashSet::new(),
+    }
+  }
+
+  pub fn world_to_cell(&self, world: &math::Vec3f) -> math::Vec3i
+  { world_to_cell(&self.dimensions, self.voxel_size, world) }
+
+  pub fn cell_to_world(&self, cell: &math::Vec3i) -> math::Vec3f
+  { cell_to_world(&self.dimensions, self.voxel_size, cell) }
+
+  pub fn cells_in_bb(&self, min: &math::Vec3f, max: &math::Vec3f) -> (math::Vec3i, math::Vec3i)
+  { cells_in_bb(&self.dimensions, self.voxel_size, min, max) }
+
+  /* The state of a cell, or None if it's outside of the grid. */
+  pub fn get(&self, cell: &math::Vec3i) -> Option<u32>
+  {
+    cell_index(&self.dimensions, cell).map(|i| self.states[*i])
+  }
+
+  /* Replaces the state of a cell, marking whatever depends on it
+   * as dirty. The state's voxel index must already be valid.
+   * Returns false if the cell is outside of the grid. */
+  pub fn set(&mut self, cell: &math::Vec3i, state: u32) -> bool
+  {
+    let index = match cell_index(&self.dimensions, cell)
+    {
+      Some(i) => { i },
+      None => { return false; }
     };
 
-    if !map.voxelize(tris)
-    { return Err(map.error.clone()); }
+    let old = self.states[index];
+    if old == state
+    { return true; }
+    self.states[index] = state;
 
-    Ok(map)
+    if (old & Visible) != (state & Visible)
+    { self.occupancy_changed(cell); }
+    else
+    {
+      self.mark_dirty(cell);
+      if (state & Visible) != 0
+      { self.dirty_voxels.insert(state & Index_Mask); }
+    }
+
+    true
   }
 
-  fn voxelize(&mut self, tris: &[Triangle]) -> bool
+  fn voxelize(&mut self, tris: &[Triangle], progress: &fn(uint, uint) -> bool) -> bool
+  {
+    let num_batches = rt::util::default_sched_threads() * Batches_Per_Thread;
+    self.voxelize_in_batches(tris, num_batches, progress)
+  }
+
+  fn voxelize_in_batches(&mut self, tris: &[Triangle], num_batches: uint, progress: &fn(uint, uint) -> bool) -> bool
   {
     /* Require at least one triangle. */
     if !(tris.len() >= 1)
     { self.error = ~"Invalid triangle count"; return false; }
+    if !(self.voxel_size > 0.0)
+    { self.error = ~"Invalid voxel size"; return false; }
 
     log_debug!("Incoming triangles: %ud", tris.len());
 
@@ -84,122 +194,617 @@ impl Map
                                   max.z - ((max.z - min.z) / 2.0));
     log_debug!("Center of mesh is %s", center.to_str());
 
-    /* Calculate, given resolution (how many states across), the dimensions of a voxel. */
-    self.voxel_size = cmp::max( max.x - min.x,
-                                cmp::max(max.y - min.y, max.z - min.z)) / (self.resolution as f32);
+    /* Calculate, given the voxel size, how many cells are needed along
+     * each axis. The grid is centered on the origin, so it needs to reach
+     * the furthest extent on either side, with a cell of padding since
+     * voxels reach half a cell past their centers. */
+    let voxel_size = self.voxel_size;
+    let fit = |low: f32, high: f32| -> i32
+    {
+      let cells = ((cmp::max(low.abs(), high.abs()) * 2.0) / voxel_size).ceil() as i32 + 2;
+      cells + (cells % 2)
+    };
+    self.dimensions = math::Vec3i::new(fit(min.x, max.x), fit(min.y, max.y), fit(min.z, max.z));
     log_debug!("Voxel size is %f", self.voxel_size as float);
-
-    /* World space mid point of the grid. */
-    let mid_offset = (((self.resolution as f32) / 2.0) * self.voxel_size); 
-    log_debug!("Midpoint offset is %f", mid_offset as float);
+    log_debug!("Grid dimensions are %s", self.dimensions.to_str());
 
     /* Create 3D array of states. */
-    self.states = Some(vec::with_capacity(((self.resolution + 1) as f32).pow(&3.0) as uint));
-    self.voxels = vec::with_capacity(self.states.get_mut_ref().len() / 2); /* Half is just a (generous) guess. */
-    for _z in range(0, self.resolution as uint) 
-    { for _y in range(0, self.resolution as uint)
-      { for _x in range(0, self.resolution as uint)
+    let cells = (self.dimensions.x * self.dimensions.y * self.dimensions.z) as uint;
+    self.states = vec::from_elem(cells, 0u32); /* Invisible. */
+    self.voxels = vec::with_capacity(cells / 8); /* An eighth is just a (generous) guess. */
+
+    /* Triangles are split into contiguous batches which are
+     * voxelized by separate tasks. Each batch produces its hits in
+     * triangle order and the batches are merged back in batch order,
+     * so the result is identical no matter how many tasks there are. */
+    let shared_tris = arc::Arc::new(vec::from_fn(tris.len(), |i| tris[i]));
+    let num_batches = cmp::max(cmp::min(tris.len(), num_batches), 1);
+    let batch_size = (tris.len() + num_batches - 1) / num_batches;
+    log_debug!("Voxelizing in %ud batches of %ud triangles", num_batches, batch_size);
+
+    let mut ports = vec::with_capacity(num_batches);
+    for batch in range(0, num_batches)
+    {
+      let (port, chan) = comm::stream();
+      ports.push(port);
+
+      let batch_tris = shared_tris.clone();
+      let dimensions = self.dimensions;
+      let voxel_size = self.voxel_size;
+      do task::spawn
+      {
+        let tris = batch_tris.get();
+        let start = batch * batch_size;
+        let end = cmp::min(start + batch_size, tris.len());
+        let mut hits = ~[];
+        for tri in tris.slice(start, end).iter()
+        { voxelize_triangle(tri, dimensions, voxel_size, &mut hits); }
+        /* The receiver is gone if voxelization was cancelled. */
+        chan.try_send(hits);
+      }
+    }
+
+    for (batch, port) in ports.iter().enumerate()
+    {
+      if !progress(cmp::min(batch * batch_size, tris.len()), tris.len())
+      { self.error = ~"Voxelization cancelled"; return false; }
+
+      let hits: ~[Hit] = port.recv();
+      for hit in hits.iter()
+      {
+        /* We have intersection; add a reference to this voxel to the index map. */
+        self.voxels.push(hit.voxel);
+
+        /* Update the state of the voxel. */
+        let index = cell_index(&self.dimensions, &hit.cell).unwrap();
+        self.states[index] = (self.voxels.len() as u32 - 1u32) | Visible;
+      }
+    }
+
+    progress(tris.len(), tris.len());
+    log_debug!("Enabled %ud of %ud voxels", self.voxels.len(), self.states.len());
+
+    self.anchor_hull();
+    self.bake_occlusion();
+    self.build_lods();
+
+    true
+  }
+
+  pub fn bake_occlusion(&mut self)
+  {
+    self.occlusion = occlusion::bake(self.states, self.voxels.len(), &self.dimensions);
+  }
+
+  pub fn build_lods(&mut self)
+  {
+    let dims = self.dimensions;
+    let states = &self.states;
+    self.lods = do lod::Scales.map |scale|
+    { Lod::new(*states, self.voxels, &dims, *scale) };
+    log_debug!("Built %ud levels of detail", self.lods.len());
+  }
+
+  /* Resamples the levels of detail over every dirty chunk. */
+  fn refresh_lods(&mut self)
+  {
+    let dims = self.dimensions;
+    let counts = mesher::chunk_counts(&dims);
+    let states = &self.states;
+    for index in self.dirty_chunks.iter()
+    {
+      let index = *index as i32;
+      let start = math::Vec3i::new( (index % counts.x) * mesher::Chunk_Size,
+                                    ((index / counts.x) % counts.y) * mesher::Chunk_Size,
+                                    (index / (counts.x * counts.y)) * mesher::Chunk_Size);
+      let end = math::Vec3i::new( cmp::min(start.x + mesher::Chunk_Size, dims.x),
+                                  cmp::min(start.y + mesher::Chunk_Size, dims.y),
+                                  cmp::min(start.z + mesher::Chunk_Size, dims.z));
+      for lod in self.lods.mut_iter()
+      { lod.refresh(*states, self.voxels, &dims, &start, &end); }
+    }
+  }
+
+  /* The outermost layer of the map (the hull, in Quake terms)
+   * is indestructible and anchors everything attached to it. */
+  fn anchor_hull(&mut self)
+  {
+    if self.voxels.len() == 0
+    { return; }
+
+    let dims = self.dimensions;
+    let mut min = dims;
+    let mut max = math::Vec3i::new(-1, -1, -1);
+    for vox in self.voxels.iter()
+    {
+      let cell = position_cell(&dims, &vox.position);
+      if cell_index(&dims, &cell).is_none()
+      { loop; }
+
+      min.x = cmp::min(min.x, cell.x); max.x = cmp::max(max.x, cell.x);
+      min.y = cmp::min(min.y, cell.y); max.y = cmp::max(max.y, cell.y);
+      min.z = cmp::min(min.z, cell.z); max.z = cmp::max(max.z, cell.z);
+    }
+
+    let states = &mut self.states;
+    let mut anchors = 0;
+    for vox in self.voxels.iter()
+    {
+      let cell = position_cell(&dims, &vox.position);
+      let index = match cell_index(&dims, &cell)
+      {
+        Some(i) => { i },
+        None => { loop; }
+      };
+
+      if  cell.x == min.x || cell.x == max.x ||
+          cell.y == min.y || cell.y == max.y ||
+          cell.z == min.z || cell.z == max.z
+      {
+        if (states[index] & Anchor) == 0
+        { anchors += 1; }
+        states[index] |= Anchor;
+      }
+    }
+    log_debug!("Anchored %d hull voxels", anchors as int);
+  }
+
+  /* Removes every destructible voxel within the sphere and
+   * breaks off anything that's no longer connected to an
+   * anchor. Returns the number of new debris bodies. */
+  pub fn carve(&mut self, center: math::Vec3f, radius: f32) -> uint
+  {
+    let dims = self.dimensions;
+    let voxel_size = self.voxel_size;
+
+    let (start, end) = self.cells_in_bb(&(center - math::Vec3f::new(radius, radius, radius)),
+                                        &(center + math::Vec3f::new(radius, radius, radius)));
+
+    let mut carved = ~[];
+    {
+      let states = &mut self.states;
+      for z in range(start.z, end.z)
+      { for y in range(start.y, end.y)
+        { for x in range(start.x, end.x)
+          {
+            let cell = math::Vec3i::new(x, y, z);
+            let index = cell_index(&dims, &cell).unwrap();
+
+            let dist = (cell_to_world(&dims, voxel_size, &cell) - center).length();
+            if dist > radius || (states[index] & Visible) == 0 || (states[index] & Anchor) != 0
+            { loop; }
+
+            states[index] = 0;
+            carved.push(cell);
+          }
+        }
+      }
+    }
+    log_debug!("Carved %ud voxels", carved.len());
+
+    if carved.len() == 0
+    { return 0; }
+
+    for cell in carved.iter()
+    { self.occupancy_changed(cell); }
+
+    let islands = self.find_islands(carved);
+    let count = islands.len();
+    for island in islands.move_iter()
+    {
+      log_debug!("Detached island of %ud voxels", island.len());
+      self.debris.push(Debris::new(island));
+    }
+
+    count
+  }
+
+  /* Flood fills outward from everything surrounding the carved
+   * cells. Any region that can't reach an anchor is removed from
+   * the grid and returned as a list of voxel indices. */
+  fn find_islands(&mut self, carved: &[math::Vec3i]) -> ~[~[u32]]
+  {
+    let dims = self.dimensions;
+    let mut islands = ~[];
+    let mut detached = ~[];
+    {
+      let states = &mut self.states;
+      let mut grounded = HashSet::new();
+
+      for cell in carved.iter()
+      {
+        for &(nx, ny, nz) in Neighbours.iter()
         {
-          self.states.get_mut_ref().push(0); /* Invisible. */
+          let seed = *cell + math::Vec3i::new(nx, ny, nz);
+          let seed_index = match cell_index(&dims, &seed)
+          {
+            Some(i) => { i },
+            None => { loop; }
+          };
+          if (states[seed_index] & Visible) == 0 || grounded.contains(&seed_index)
+          { loop; }
+
+          let mut region = ~[seed_index];
+          let mut visited = HashSet::new();
+          visited.insert(seed_index);
+          let mut open = ~[seed];
+          let mut anchored = (states[seed_index] & Anchor) != 0;
+
+          while open.len() > 0 && !anchored
+          {
+            let curr = open.pop();
+            for &(dx, dy, dz) in Neighbours.iter()
+            {
+              let next = curr + math::Vec3i::new(dx, dy, dz);
+              let index = match cell_index(&dims, &next)
+              {
+                Some(i) => { i },
+                None => { loop; }
+              };
+              if (states[index] & Visible) == 0 || visited.contains(&index)
+              { loop; }
+
+              /* Touching anything grounded grounds the whole region. */
+              if (states[index] & Anchor) != 0 || grounded.contains(&index)
+              { anchored = true; break; }
+
+              visited.insert(index);
+              region.push(index);
+              open.push(next);
+            }
+          }
+
+          if anchored
+          {
+            for index in region.iter()
+            { grounded.insert(*index); }
+          }
+          else
+          {
+            /* Pull the island out of the static grid. */
+            let island = do region.map |index|
+            {
+              let vox = states[*index] & Index_Mask;
+              states[*index] = 0;
+              vox
+            };
+            islands.push(island);
+            detached.push_all(region);
+          }
         }
       }
     }
 
-    //let mut voxels = extra::treemap::TreeSet::new(); /* TODO: Bring back in; maintain indices. */
-    for tri in tris.iter()
+    for index in detached.iter()
+    { self.occupancy_changed(&index_to_cell(&dims, *index)); }
+
+    islands
+  }
+
+  /* Marks the chunk containing the cell as dirty, along with any
+   * chunk touching the cell, since the exposed faces and occlusion
+   * of that chunk may have changed too. */
+  fn mark_dirty(&mut self, cell: &math::Vec3i)
+  {
+    if mesher::chunk_index(&self.dimensions, cell).is_none()
+    { return; }
+
+    for z in range(cell.z - 1, cell.z + 2)
+    { for y in range(cell.y - 1, cell.y + 2)
+      { for x in range(cell.x - 1, cell.x + 2)
+        {
+          match mesher::chunk_index(&self.dimensions, &math::Vec3i::new(x, y, z))
+          {
+            Some(i) => { self.dirty_chunks.insert(i); }
+            None => { }
+          }
+        }
+      }
+    }
+  }
+
+  /* A cell has been filled or emptied; its surroundings need
+   * to be re-meshed and their occlusion re-baked. */
+  fn occupancy_changed(&mut self, cell: &math::Vec3i)
+  {
+    self.mark_dirty(cell);
+    match cell_index(&self.dimensions, cell)
+    {
+      Some(i) => { self.dirty_cells.insert(i); }
+      None => { }
+    }
+
+    let touched = occlusion::refresh_around(self.occlusion, self.states, &self.dimensions, cell);
+    for vox in touched.iter()
+    { self.dirty_voxels.insert(*vox); }
+  }
+
+  /* Hands over, and forgets, the chunks modified since the last call.
+   * The levels of detail are brought up to date with them first. */
+  pub fn take_dirty_chunks(&mut self) -> ~[uint]
+  {
+    self.refresh_lods();
+    let dirty = util::replace(&mut self.dirty_chunks, HashSet::new());
+    dirty.move_iter().collect()
+  }
+
+  /* Hands over, and forgets, the voxels modified since the last call. */
+  pub fn take_dirty_voxels(&mut self) -> ~[u32]
+  {
+    let dirty = util::replace(&mut self.dirty_voxels, HashSet::new());
+    dirty.move_iter().collect()
+  }
+
+  /* Hands over, and forgets, the cells filled or emptied since the last call. */
+  pub fn take_dirty_cells(&mut self) -> ~[uint]
+  {
+    let dirty = util::replace(&mut self.dirty_cells, HashSet::new());
+    dirty.move_iter().collect()
+  }
+
+  /* Copies every voxel of the other map into this one, with the
+   * other map's center at the given cell. Anchored voxels are left
+   * alone. Returns the number of voxels copied. */
+  pub fn paste(&mut self, other: &Map, center: &math::Vec3i) -> uint
+  {
+    let dims = self.dimensions;
+    let other_dims = other.dimensions;
+    let other_states = &other.states;
+    let mut pasted = ~[];
     {
-      /* Calculate bounding box of the triangle. */
-      min = math::Vec3f::new(tri.verts[0].position.x, tri.verts[0].position.y, tri.verts[0].position.z);
-      max = math::Vec3f::new(tri.verts[0].position.x, tri.verts[0].position.y, tri.verts[0].position.z);
-      for vert in tri.verts.iter()
+      let states = &mut self.states;
+      for (i, state) in other_states.iter().enumerate()
       {
-        /* Adjust by half of a voxel to account for voxel centering. */
-        min.x = cmp::min(min.x, vert.position.x - (self.voxel_size / 2.0));
-        min.y = cmp::min(min.y, vert.position.y - (self.voxel_size / 2.0));
-        min.z = cmp::min(min.z, vert.position.z - (self.voxel_size / 2.0));
-
-        max.x = cmp::max(max.x, vert.position.x - (self.voxel_size / 2.0));
-        max.y = cmp::max(max.y, vert.position.y - (self.voxel_size / 2.0));
-        max.z = cmp::max(max.z, vert.position.z - (self.voxel_size / 2.0));
+        if (*state & Visible) == 0
+        { loop; }
+
+        let offset = index_to_cell(&other_dims, i);
+        let cell = *center + offset - math::Vec3i::new(other_dims.x / 2, other_dims.y / 2, other_dims.z / 2);
+        let index = match cell_index(&dims, &cell)
+        {
+          Some(index) => { index },
+          None => { loop; }
+        };
+        if (states[index] & Anchor) != 0
+        { loop; }
+
+        self.voxels.push(Vertex::new(cell_position(&dims, &cell), other.voxels[*state & Index_Mask].color));
+        states[index] = (self.voxels.len() as u32 - 1) | Visible;
+        self.occlusion.push_all([255u8, ..6]);
+        pasted.push(cell);
       }
+    }
+
+    for cell in pasted.iter()
+    { self.occupancy_changed(cell); }
 
-      /* The dimensions (in voxels) of the triangle's bounding box. */
-      let mut vox_amount = math::Vec3i::new((((max.x - min.x) / self.voxel_size)).ceil() as i32,
-                                            (((max.y - min.y) / self.voxel_size)).ceil() as i32,
-                                            (((max.z - min.z) / self.voxel_size)).ceil() as i32);
-      if vox_amount.x < 1
-      { vox_amount.x = 1; }
-      if vox_amount.y < 1
-      { vox_amount.y = 1; }
-      if vox_amount.z < 1
-      { vox_amount.z = 1; }
-      //log_debug!("[Per voxel] Checking %s surrounding states with SAT", vox_amount.to_str());
-
-      /* Get the starting indices of the triangle's bounding box. */
-      let start_voxels = math::Vec3i::new( ((min.x - -mid_offset) / self.voxel_size) as i32, 
-                                      ((min.y - -mid_offset) / self.voxel_size) as i32,
-                                      ((min.z - -mid_offset) / self.voxel_size) as i32);
-
-      /* Test intersection with each accepted voxel. */
-      for z in range(start_voxels.z, start_voxels.z + vox_amount.z)
-      { for y in range(start_voxels.y, start_voxels.y + vox_amount.y)
-        { for x in range(start_voxels.x, start_voxels.x + vox_amount.x)
+    pasted.len()
+  }
+
+  /* Replaces whole rows of cells with those of a delta from
+   * another copy of this map. Returns the number of changed cells. */
+  pub fn apply_delta(&mut self, delta: &Delta) -> Result<uint, ~str>
+  {
+    let dims = self.dimensions;
+    let row_count = (dims.y * dims.z) as u32;
+    for &(row, ref runs) in delta.rows.iter()
+    {
+      if row >= row_count || runs.len() == 0 || runs[0].first() != 0 ||
+         runs.iter().any(|&(start, _)| start as i32 >= dims.x)
+      { return Err(fmt!("Delta row %u is invalid", row as uint)); }
+    }
+
+    let mut filled = ~[];
+    let mut emptied = ~[];
+    {
+      let states = &mut self.states;
+      for &(row, ref runs) in delta.rows.iter()
+      {
+        let (y, z) = ((row as i32) % dims.y, (row as i32) / dims.y);
+        for (r, &(start, ref material)) in runs.iter().enumerate()
+        {
+          let end = if r + 1 < runs.len() { runs[r + 1].first() as i32 } else { dims.x };
+          for x in range(start as i32, end)
           {
-            /* Check for intersection. */
-            let c = math::Vec3f::new( ((x as f32 - (self.resolution as f32 / 2.0)) * self.voxel_size) + (self.voxel_size / 2.0), 
-                                ((y as f32 - (self.resolution as f32 / 2.0)) * self.voxel_size) + (self.voxel_size / 2.0),
-                                ((z as f32 - (self.resolution as f32 / 2.0)) * self.voxel_size) + (self.voxel_size / 2.0));
-            if tri_cube_intersect(c, self.voxel_size, tri)
+            let cell = math::Vec3i::new(x, y, z);
+            let index = cell_index(&dims, &cell).unwrap();
+            let state = states[index];
+            if material.is_empty()
             {
-              /* Calculate the average color from all three verts. */
-              let av_color = math::Vec3f::new
-              (
-                ((tri.verts[0].color.x + tri.verts[1].color.x + tri.verts[2].color.x) / 3.0) as f32 / 255.0,
-                ((tri.verts[0].color.y + tri.verts[1].color.y + tri.verts[2].color.y) / 3.0) as f32 / 255.0,
-                ((tri.verts[0].color.z + tri.verts[1].color.z + tri.verts[2].color.z) / 3.0) as f32 / 255.0
-              );
-
-              /* Enable some debug rendering of invalid voxels. */
-              let col = if x >= self.resolution as i32 || y >= self.resolution as i32 || z >= self.resolution as i32
-              { math::Vec3f::new(1.0, 0.0, 0.0) }
-              else if x < 0 || y < 0 || z < 0
-              { math::Vec3f::new(1.0, 0.0, 0.0) }
-              else
-              { av_color };
-
-              /* We have intersection; add a reference to this voxel to the index map. */
-              let new_val = true;
-              self.voxels.push(
-              Vertex
+              if (state & Visible) != 0
               {
-                position: math::Vec3f::new( x as f32 - (self.resolution / 2) as f32, 
-                                            y as f32 - (self.resolution / 2) as f32,
-                                            z as f32 - (self.resolution / 2) as f32), 
-                color: col
-              });
-              if new_val
+                states[index] = 0;
+                emptied.push(cell);
+              }
+              loop;
+            }
+
+            /* Existing voxels are recolored in place. */
+            let behaviors = material.behaviors as u32 << 24;
+            if (state & Visible) != 0
+            {
+              let vox = state & Index_Mask;
+              if self.voxels[vox].color != material.get_color() || (state & !Index_Mask) != behaviors
               {
-                /* Update the state of the voxel. */
-                let index = (z * ((self.resolution * self.resolution) as i32)) + (y * (self.resolution as i32)) + x;
-                self.states.get_mut_ref()[index] = self.voxels.len() as u32 - 1u32;
-                self.states.get_mut_ref()[index] |= Visible;
+                self.voxels[vox].color = material.get_color();
+                states[index] = vox | behaviors;
+                self.dirty_voxels.insert(vox);
+                self.mark_dirty(&cell);
               }
+              loop;
             }
+
+            self.voxels.push(Vertex::new(cell_position(&dims, &cell), material.get_color()));
+            states[index] = (self.voxels.len() as u32 - 1) | behaviors;
+            self.occlusion.push_all([255u8, ..6]);
+            filled.push(cell);
           }
         }
       }
     }
 
-    /* Move to array form. */
-    //for x in voxels.iter()
-    //{ self.voxels.push(*x); }
+    for cell in filled.iter().chain(emptied.iter())
+    { self.occupancy_changed(cell); }
 
-    log_debug!("Enabled %ud of %ud voxels", self.voxels.len(), self.states.get_mut_ref().len());
+    Ok(filled.len() + emptied.len())
+  }
 
-    true
+  /* Anchors only the lowest layer of voxels, so that standalone
+   * objects stay put when carved, as if resting on the ground. */
+  pub fn anchor_base(&mut self)
+  {
+    let dims = self.dimensions;
+    let states = &mut self.states;
+    let lowest = range(0, dims.y).find(|y|
+    {
+      range(0, dims.z).any(|z|
+      {
+        range(0, dims.x).any(|x| (states[(z * dims.x * dims.y) + (*y * dims.x) + x] & Visible) != 0)
+      })
+    });
+    let y = match lowest
+    {
+      Some(y) => { y },
+      None => { return; }
+    };
+
+    for state in states.mut_iter()
+    { *state &= !Anchor; }
+
+    for z in range(0, dims.z)
+    {
+      for x in range(0, dims.x)
+      {
+        let index = ((z * dims.x * dims.y) + (y * dims.x) + x) as uint;
+        if (states[index] & Visible) != 0
+        { states[index] |= Anchor; }
+      }
+    }
+    log_debug!("Anchored base layer %d", y as int);
+  }
+
+  pub fn update(&mut self, delta: f32) /* dt is in terms of seconds. */
+  {
+    for debris in self.debris.mut_iter()
+    { debris.update(delta); }
+    self.debris.retain(|debris| !debris.is_dead());
+  }
+}
+
+/* A voxel found to intersect a triangle, along with its cell. */
+struct Hit
+{
+  cell: math::Vec3i,
+  voxel: Vertex,
+}
+
+/* Finds every voxel intersecting the triangle. This only reads
+ * its arguments so that any number of tasks may run it at once.
+ * Parts of the triangle outside of the grid are ignored. */
+fn voxelize_triangle(tri: &Triangle, dims: math::Vec3i, voxel_size: f32, hits: &mut ~[Hit])
+{
+  /* Calculate bounding box of the triangle. */
+  let mut min = tri.verts[0].position;
+  let mut max = tri.verts[0].position;
+  for vert in tri.verts.iter()
+  {
+    min.x = cmp::min(min.x, vert.position.x);
+    min.y = cmp::min(min.y, vert.position.y);
+    min.z = cmp::min(min.z, vert.position.z);
+
+    max.x = cmp::max(max.x, vert.position.x);
+    max.y = cmp::max(max.y, vert.position.y);
+    max.z = cmp::max(max.z, vert.position.z);
+  }
+
+  /* Calculate the average color from all three verts. */
+  let color = math::Vec3f::new
+  (
+    ((tri.verts[0].color.x + tri.verts[1].color.x + tri.verts[2].color.x) / 3.0) as f32 / 255.0,
+    ((tri.verts[0].color.y + tri.verts[1].color.y + tri.verts[2].color.y) / 3.0) as f32 / 255.0,
+    ((tri.verts[0].color.z + tri.verts[1].color.z + tri.verts[2].color.z) / 3.0) as f32 / 255.0
+  );
+
+  /* Test intersection with each cell the bounding box touches. */
+  let (start, end) = cells_in_bb(&dims, voxel_size, &min, &max);
+  for z in range(start.z, end.z)
+  { for y in range(start.y, end.y)
+    { for x in range(start.x, end.x)
+      {
+        let cell = math::Vec3i::new(x, y, z);
+        if tri_cube_intersect(cell_to_world(&dims, voxel_size, &cell), voxel_size, tri)
+        {
+          hits.push(Hit
+          {
+            cell: cell,
+            voxel: Vertex::new(cell_position(&dims, &cell), color)
+          });
+        }
+      }
+    }
   }
 }
 
+/* Flattens a cell into an index for the states, if it's within the grid. */
+pub fn cell_index(dims: &math::Vec3i, cell: &math::Vec3i) -> Option<uint>
+{
+  if  cell.x < 0 || cell.y < 0 || cell.z < 0 ||
+      cell.x >= dims.x || cell.y >= dims.y || cell.z >= dims.z
+  { return None; }
+
+  Some(((cell.z * dims.x * dims.y) + (cell.y * dims.x) + cell.x) as uint)
+}
+
+/* The inverse of cell_index. */
+pub fn index_to_cell(dims: &math::Vec3i, index: uint) -> math::Vec3i
+{
+  let index = index as i32;
+  math::Vec3i::new(index % dims.x, (index / dims.x) % dims.y, index / (dims.x * dims.y))
+}
+
+/* A cell's position relative to the center of the grid, in cells;
+ * this is what voxels store as their position. */
+pub fn cell_position(dims: &math::Vec3i, cell: &math::Vec3i) -> math::Vec3f
+{
+  math::Vec3f::new( (cell.x - (dims.x / 2)) as f32,
+                    (cell.y - (dims.y / 2)) as f32,
+                    (cell.z - (dims.z / 2)) as f32)
+}
+
+/* The inverse of cell_position. */
+pub fn position_cell(dims: &math::Vec3i, position: &math::Vec3f) -> math::Vec3i
+{
+  math::Vec3i::new( position.x.round() as i32 + (dims.x / 2),
+                    position.y.round() as i32 + (dims.y / 2),
+                    position.z.round() as i32 + (dims.z / 2))
+}
+
+/* The cell whose voxel would contain the world position. Voxels
+ * are centered on their cells, so this rounds to the nearest cell.
+ * The cell may be outside of the grid. */
+pub fn world_to_cell(dims: &math::Vec3i, voxel_size: f32, world: &math::Vec3f) -> math::Vec3i
+{ position_cell(dims, &(*world * (1.0 / voxel_size))) }
+
+/* World position of the center of a cell. */
+pub fn cell_to_world(dims: &math::Vec3i, voxel_size: f32, cell: &math::Vec3i) -> math::Vec3f
+{ cell_position(dims, cell) * voxel_size }
+
+/* The cells, from start up to (but not including) end, whose voxels
+ * overlap the world space box. Both are clamped to the grid, so the
+ * range is empty if the box is entirely outside of it. */
+pub fn cells_in_bb(dims: &math::Vec3i, voxel_size: f32, min: &math::Vec3f, max: &math::Vec3f) -> (math::Vec3i, math::Vec3i)
+{
+  let low = world_to_cell(dims, voxel_size, min);
+  let high = world_to_cell(dims, voxel_size, max);
+  let start = math::Vec3i::new( cmp::min(cmp::max(low.x, 0), dims.x),
+                                cmp::min(cmp::max(low.y, 0), dims.y),
+                                cmp::min(cmp::max(low.z, 0), dims.z));
+  let end = math::Vec3i::new( cmp::max(cmp::min(high.x + 1, dims.x), start.x),
+                              cmp::max(cmp::min(high.y + 1, dims.y), start.y),
+                              cmp::max(cmp::min(high.z + 1, dims.z), start.z));
+  (start, end)
+}
+
 macro_rules! find_min_max
 (
   ($x0:expr, $x1:expr, $x2:expr) =>
@@ -408,3 +1013,131 @@ fn plane_cube_intersect(normal: &math::Vec3f, vert: &math::Vec3f, box_size: f32)
   false
 }
 
+
+#[cfg(test)]
+mod test
+{
+  use math;
+  use super::Map;
+  use primitive::Triangle;
+  use super::super::{ Vertex, Visible };
+
+  /* Four cells along each axis, centered on the origin. */
+  fn grid() -> Map
+  { Map::new_empty(math::Vec3i::new(4, 4, 4), 0.5) }
+
+  #[test]
+  fn round_trip()
+  {
+    let map = grid();
+    for z in range(0, 4)
+    { for y in range(0, 4)
+      { for x in range(0, 4)
+        {
+          let cell = math::Vec3i::new(x, y, z);
+          assert_eq!(map.world_to_cell(&map.cell_to_world(&cell)), cell);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn edges()
+  {
+    let map = grid();
+    assert_eq!(map.cell_to_world(&math::Vec3i::new(0, 0, 0)), math::Vec3f::new(-1.0, -1.0, -1.0));
+    assert_eq!(map.cell_to_world(&math::Vec3i::new(3, 3, 3)), math::Vec3f::new(0.5, 0.5, 0.5));
+    assert_eq!(map.cell_to_world(&math::Vec3i::new(0, 3, 0)), math::Vec3f::new(-1.0, 0.5, -1.0));
+
+    /* Within half a voxel of a cell's center rounds to it. */
+    assert_eq!(map.world_to_cell(&math::Vec3f::new(-1.2, 0.7, -0.8)), math::Vec3i::new(0, 3, 0));
+
+    /* Past the grid, cells are still given, but can't be used. */
+    let outside = map.world_to_cell(&math::Vec3f::new(1.0, -1.5, 0.0));
+    assert_eq!(outside, math::Vec3i::new(4, -1, 2));
+    assert_eq!(map.get(&outside), None);
+  }
+
+  #[test]
+  fn boxes()
+  {
+    let map = grid();
+    let v = |x: f32, y: f32, z: f32| math::Vec3f::new(x, y, z);
+
+    /* Everything. */
+    assert_eq!(map.cells_in_bb(&v(-10.0, -10.0, -10.0), &v(10.0, 10.0, 10.0)),
+               (math::Vec3i::new(0, 0, 0), math::Vec3i::new(4, 4, 4)));
+
+    /* The corners. */
+    assert_eq!(map.cells_in_bb(&v(-1.0, -1.0, -1.0), &v(-1.0, -1.0, -1.0)),
+               (math::Vec3i::new(0, 0, 0), math::Vec3i::new(1, 1, 1)));
+    assert_eq!(map.cells_in_bb(&v(0.5, 0.5, 0.5), &v(3.0, 3.0, 3.0)),
+               (math::Vec3i::new(3, 3, 3), math::Vec3i::new(4, 4, 4)));
+
+    /* Entirely outside, on either side and along one axis; the ranges are empty. */
+    for &(min, max) in [ (v(5.0, 5.0, 5.0), v(6.0, 6.0, 6.0)),
+                         (v(-10.0, -10.0, -10.0), v(-5.0, -5.0, -5.0)),
+                         (v(-1.0, 5.0, -1.0), v(0.5, 6.0, 0.5)) ].iter()
+    {
+      let (start, end) = map.cells_in_bb(&min, &max);
+      assert!(start.x >= end.x || start.y >= end.y || start.z >= end.z);
+      assert!(end.x <= 4 && end.y <= 4 && end.z <= 4);
+    }
+  }
+
+  #[test]
+  fn get_set()
+  {
+    let mut map = grid();
+    let cell = math::Vec3i::new(1, 2, 3);
+    assert_eq!(map.get(&cell), Some(0));
+
+    map.voxels.push(Vertex::zero());
+    map.occlusion.push_all([255u8, ..6]);
+    assert!(map.set(&cell, 0 | Visible));
+    assert_eq!(map.get(&cell), Some(Visible));
+    assert_eq!(map.take_dirty_cells(), ~[(3 * 16) + (2 * 4) + 1]);
+
+    /* Outside of the grid, on every side. */
+    for &(x, y, z) in [ (-1, 0, 0), (4, 0, 0), (0, -1, 0), (0, 4, 0), (0, 0, -1), (0, 0, 4) ].iter()
+    {
+      let outside = math::Vec3i::new(x, y, z);
+      assert_eq!(map.get(&outside), None);
+      assert!(!map.set(&outside, 0 | Visible));
+    }
+    assert_eq!(map.take_dirty_cells().len(), 0);
+  }
+
+  /* The merge is ordered, so the thread count can't change the result. */
+  #[test]
+  fn voxelize_batches()
+  {
+    let v = |x: f32, y: f32, z: f32| math::Vec3f::new(x, y, z);
+    let tris = ~[ Triangle::new_with_position(v(-2.0, 0.0, -2.0), v(2.0, 0.0, -2.0), v(2.0, 0.0, 2.0)),
+                  Triangle::new_with_position(v(-2.0, 0.0, -2.0), v(2.0, 0.0, 2.0), v(-2.0, 0.0, 2.0)),
+                  Triangle::new_with_position(v(-1.0, -1.0, 0.0), v(1.0, 1.5, 0.0), v(0.0, 1.0, 0.5)),
+                  Triangle::new_with_position(v(0.3, -0.7, 1.1), v(1.9, 0.2, -1.4), v(-0.6, 1.8, 0.4)),
+                  Triangle::new_with_position(v(-1.5, 1.5, -1.5), v(1.5, 1.5, 1.5), v(0.0, -1.5, 0.0)) ];
+
+    let voxelize = |num_batches: uint| -> Map
+    {
+      let mut map = Map::new_empty(math::Vec3i::zero(), 0.25);
+      assert!(map.voxelize_in_batches(tris, num_batches, |_, _| true));
+      map
+    };
+    let single = voxelize(1);
+    assert!(single.voxels.len() > 0);
+    for &num_batches in [2u, 3, 5, 16].iter()
+    {
+      let batched = voxelize(num_batches);
+      assert_eq!(batched.dimensions, single.dimensions);
+      assert!(batched.states == single.states);
+      assert_eq!(batched.voxels.len(), single.voxels.len());
+      for (a, b) in batched.voxels.iter().zip(single.voxels.iter())
+      {
+        assert_eq!(a.position, b.position);
+        assert_eq!(a.color, b.color);
+      }
+    }
+  }
+}
@@ -0,0 +1,371 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/voxel/mesher.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Builds triangle meshes out of chunks
+      of the voxel grid. Only exposed faces
+      are emitted and coplanar faces of the
+      same color are merged (greedy meshing).
+      Nothing here touches OpenGL.
+*/
+
+use std::{ vec, cmp };
+use math;
+use super::{ Vertex, Visible, Index_Mask };
+use super::map::{ cell_index, cell_to_world };
+
+/* Number of cells along each axis of a chunk. */
+pub static Chunk_Size: i32 = 32;
+
+/* Number of chunks along each axis needed to cover the grid. */
+pub fn chunk_counts(dims: &math::Vec3i) -> math::Vec3i
+{
+  math::Vec3i::new( (dims.x + Chunk_Size - 1) / Chunk_Size,
+                    (dims.y + Chunk_Size - 1) / Chunk_Size,
+                    (dims.z + Chunk_Size - 1) / Chunk_Size)
+}
+
+/* Flattens the chunk containing a cell into an index, if the cell is within the grid. */
+pub fn chunk_index(dims: &math::Vec3i, cell: &math::Vec3i) -> Option<uint>
+{
+  if cell_index(dims, cell).is_none()
+  { return None; }
+
+  let counts = chunk_counts(dims);
+  let chunk = math::Vec3i::new(cell.x / Chunk_Size, cell.y / Chunk_Size, cell.z / Chunk_Size);
+  Some(((chunk.z * counts.x * counts.y) + (chunk.y * counts.x) + chunk.x) as uint)
+}
+
+/* World space bounds (min, max) of the chunk at the given chunk coordinates. */
+pub fn chunk_bounds(dims: &math::Vec3i, voxel_size: f32, chunk: &math::Vec3i) -> (math::Vec3f, math::Vec3f)
+{
+  let min = math::Vec3i::new(chunk.x * Chunk_Size, chunk.y * Chunk_Size, chunk.z * Chunk_Size);
+  let max = math::Vec3i::new( cmp::min(min.x + Chunk_Size, dims.x),
+                              cmp::min(min.y + Chunk_Size, dims.y),
+                              cmp::min(min.z + Chunk_Size, dims.z));
+  let corner = grid_corner(dims, voxel_size);
+  (to_world(&corner, voxel_size, &min), to_world(&corner, voxel_size, &max))
+}
+
+/* World position of the grid's first cell corner. Cell corners are
+ * offset by half a voxel, since voxels are rendered centered on their
+ * grid positions. */
+pub fn grid_corner(dims: &math::Vec3i, voxel_size: f32) -> math::Vec3f
+{
+  let half = voxel_size / 2.0;
+  cell_to_world(dims, voxel_size, &math::Vec3i::zero()) - math::Vec3f::new(half, half, half)
+}
+
+/* Meshes every chunk of a whole grid into a single list of triangles. */
+pub fn mesh_map(states: &[u32], voxels: &[Vertex], occlusion: &[u8], dims: math::Vec3i,
+                voxel_size: f32) -> ~[Vertex]
+{
+  let counts = chunk_counts(&dims);
+  let mut verts = ~[];
+  for z in range(0, counts.z)
+  { for y in range(0, counts.y)
+    { for x in range(0, counts.x)
+      { verts.push_all_move(mesh_chunk(states, voxels, occlusion, dims, voxel_size, math::Vec3i::new(x, y, z))); }
+    }
+  }
+  verts
+}
+
+/* Meshes the chunk at the given chunk coordinates into a list of
+ * triangles (three vertices each) with world space positions. */
+pub fn mesh_chunk(states: &[u32], voxels: &[Vertex], occlusion: &[u8], dims: math::Vec3i,
+                  voxel_size: f32, chunk: math::Vec3i) -> ~[Vertex]
+{
+  let start = math::Vec3i::new(chunk.x * Chunk_Size, chunk.y * Chunk_Size, chunk.z * Chunk_Size);
+  let end = math::Vec3i::new( cmp::min(start.x + Chunk_Size, dims.x),
+                              cmp::min(start.y + Chunk_Size, dims.y),
+                              cmp::min(start.z + Chunk_Size, dims.z));
+  mesh_region(states, voxels, occlusion, dims, voxel_size, grid_corner(&dims, voxel_size), start, end)
+}
+
+/* Meshes the cells from start up to (but not including) end. The
+ * grid's first corner sits at `corner` and each cell is `cell_size`
+ * wide, which allows coarser grids to line up with the map. Faces
+ * are wound counter-clockwise when viewed from outside the voxel.
+ * Occlusion may be empty, in which case nothing is occluded. */
+pub fn mesh_region(states: &[u32], voxels: &[Vertex], occlusion: &[u8], dims: math::Vec3i, cell_size: f32,
+                   corner: math::Vec3f, start: math::Vec3i, end: math::Vec3i) -> ~[Vertex]
+{
+  let origin = start;
+  let size = [ cmp::min(end.x, dims.x) - origin.x,
+               cmp::min(end.y, dims.y) - origin.y,
+               cmp::min(end.z, dims.z) - origin.z ];
+  let mut verts = ~[];
+  if size[0] <= 0 || size[1] <= 0 || size[2] <= 0
+  { return verts; }
+
+  /* Visible voxel index (plus one, so zero is empty) of a cell. */
+  let voxel_at = |cell: &math::Vec3i| -> u32
+  {
+    match cell_index(&dims, cell)
+    {
+      Some(i) if (states[i] & Visible) != 0 => { (states[i] & Index_Mask) + 1 },
+      _ => { 0 }
+    }
+  };
+  /* Occlusion of a face; faces are ordered as the map's neighbours. */
+  let lit = |vox: u32, face: uint| -> u8
+  {
+    if occlusion.len() == 0
+    { 255 }
+    else
+    { occlusion[((vox - 1) * 6) + face as u32] }
+  };
+  let same = |a: u32, b: u32, face: uint| -> bool
+  {
+    a != 0 && b != 0 &&
+    voxels[a - 1].color == voxels[b - 1].color &&
+    lit(a, face) == lit(b, face)
+  };
+
+  for d in range(0u, 3u)
+  {
+    /* The slice axes; (u, v, d) is always right handed. */
+    let u = (d + 1) % 3;
+    let v = (d + 2) % 3;
+    let mut mask = vec::from_elem((size[u] * size[v]) as uint, 0u32);
+
+    for &dir in [1i32, -1i32].iter()
+    {
+      let face = (d * 2) + if dir > 0 { 0 } else { 1 };
+      for s in range(0, size[d])
+      {
+        /* Mark each face in this slice that isn't covered by a neighbour. */
+        for j in range(0, size[v])
+        {
+          for i in range(0, size[u])
+          {
+            let cell = origin + compose(d, s, i, j);
+            let vox = voxel_at(&cell);
+            let exposed = if vox != 0 && voxel_at(&(cell + compose(d, dir, 0, 0))) == 0
+            { vox }
+            else
+            { 0 };
+            mask[(j * size[u]) + i] = exposed;
+          }
+        }
+
+        /* Grow each face as wide, then as tall, as the mask allows. */
+        for j in range(0, size[v])
+        {
+          let mut i = 0;
+          while i < size[u]
+          {
+            let vox = mask[(j * size[u]) + i];
+            if vox == 0
+            { i += 1; loop; }
+
+            let mut width = 1;
+            while i + width < size[u] && same(vox, mask[(j * size[u]) + i + width], face)
+            { width += 1; }
+
+            let mut height = 1;
+            'grow: while j + height < size[v]
+            {
+              for k in range(0, width)
+              {
+                if !same(vox, mask[((j + height) * size[u]) + i + k], face)
+                { break 'grow; }
+              }
+              height += 1;
+            }
+
+            for y in range(0, height)
+            {
+              for x in range(0, width)
+              { mask[((j + y) * size[u]) + i + x] = 0; }
+            }
+
+            /* Positive faces sit on the far side of the cell. */
+            let plane = origin[d] + s + if dir > 0 { 1 } else { 0 };
+            let a = origin[u] + i;
+            let b = origin[v] + j;
+            let color = voxels[vox - 1].color * (lit(vox, face) as f32 / 255.0);
+            let vert = |cu: i32, cv: i32| -> Vertex
+            { Vertex::new(to_world(&corner, cell_size, &compose(d, plane, cu, cv)), color) };
+
+            let c0 = vert(a, b);
+            let c1 = vert(a + width, b);
+            let c2 = vert(a + width, b + height);
+            let c3 = vert(a, b + height);
+            if dir > 0
+            { verts.push_all([c0, c1, c2, c0, c2, c3]); }
+            else
+            { verts.push_all([c0, c2, c1, c0, c3, c2]); }
+
+            i += width;
+          }
+        }
+      }
+    }
+  }
+
+  verts
+}
+
+/* Builds a cell from components along the slice axes of d. */
+fn compose(d: uint, along_d: i32, along_u: i32, along_v: i32) -> math::Vec3i
+{
+  let mut p = [0i32, 0, 0];
+  p[d] = along_d;
+  p[(d + 1) % 3] = along_u;
+  p[(d + 2) % 3] = along_v;
+  math::Vec3i::new(p[0], p[1], p[2])
+}
+
+fn to_world(grid_corner: &math::Vec3f, cell_size: f32, corner: &math::Vec3i) -> math::Vec3f
+{
+  math::Vec3f::new( grid_corner.x + (corner.x as f32 * cell_size),
+                    grid_corner.y + (corner.y as f32 * cell_size),
+                    grid_corner.z + (corner.z as f32 * cell_size))
+}
+
+#[cfg(test)]
+mod test
+{
+  use std::vec;
+  use math;
+  use super::mesh_region;
+  use super::super::{ Vertex, Visible };
+  use super::super::map::cell_index;
+
+  static Red: math::Vec3f = math::Vec3f { x: 1.0, y: 0.0, z: 0.0 };
+  static Blue: math::Vec3f = math::Vec3f { x: 0.0, y: 0.0, z: 1.0 };
+
+  /* A grid with a voxel of the given color in each of the cells. */
+  fn grid(dims: math::Vec3i, cells: &[(math::Vec3i, math::Vec3f)]) -> (~[u32], ~[Vertex])
+  {
+    let mut states = vec::from_elem((dims.x * dims.y * dims.z) as uint, 0u32);
+    let mut voxels = ~[];
+    for &(cell, color) in cells.iter()
+    {
+      states[cell_index(&dims, &cell).unwrap()] = Visible | (voxels.len() as u32);
+      voxels.push(Vertex::new(math::Vec3f::zero(), color));
+    }
+    (states, voxels)
+  }
+
+  /* Meshes the whole grid, with unit cells starting at the origin. */
+  fn mesh(dims: math::Vec3i, states: &[u32], voxels: &[Vertex], occlusion: &[u8]) -> ~[Vertex]
+  { mesh_region(states, voxels, occlusion, dims, 1.0, math::Vec3f::zero(), math::Vec3i::zero(), dims) }
+
+  /* Total area covered by the triangles. */
+  fn area(verts: &[Vertex]) -> f32
+  {
+    let mut area = 0.0;
+    for i in range(0, verts.len() / 3)
+    {
+      let (a, b, c) = (verts[i * 3].position, verts[(i * 3) + 1].position, verts[(i * 3) + 2].position);
+      area += (b - a).cross(&(c - a)).length() / 2.0;
+    }
+    area
+  }
+
+  #[test]
+  fn empty()
+  {
+    let dims = math::Vec3i::new(2, 2, 2);
+    let (states, voxels) = grid(dims, []);
+    assert_eq!(mesh(dims, states, voxels, []).len(), 0);
+  }
+
+  #[test]
+  fn single()
+  {
+    let dims = math::Vec3i::new(3, 3, 3);
+    let (states, voxels) = grid(dims, [(math::Vec3i::new(1, 1, 1), Red)]);
+    let verts = mesh(dims, states, voxels, []);
+    assert_eq!(verts.len(), 6 * 6);
+    assert_eq!(area(verts), 6.0);
+
+    /* Every face is wound counter-clockwise from outside. */
+    let center = math::Vec3f::new(1.5, 1.5, 1.5);
+    for i in range(0, verts.len() / 3)
+    {
+      let (a, b, c) = (verts[i * 3].position, verts[(i * 3) + 1].position, verts[(i * 3) + 2].position);
+      assert!((b - a).cross(&(c - a)).dot(&(a - center)) > 0.0);
+      assert_eq!(verts[i * 3].color, Red);
+    }
+  }
+
+  #[test]
+  fn merged_row()
+  {
+    /* Three in a row share their long faces. */
+    let dims = math::Vec3i::new(3, 1, 1);
+    let (states, voxels) = grid(dims, [ (math::Vec3i::new(0, 0, 0), Red),
+                                        (math::Vec3i::new(1, 0, 0), Red),
+                                        (math::Vec3i::new(2, 0, 0), Red) ]);
+    let verts = mesh(dims, states, voxels, []);
+    assert_eq!(verts.len(), 6 * 6);
+    assert_eq!(area(verts), 14.0);
+  }
+
+  #[test]
+  fn merged_block()
+  {
+    /* A full 2x2x2 block is one quad per side. */
+    let dims = math::Vec3i::new(2, 2, 2);
+    let mut cells = ~[];
+    for z in range(0, 2)
+    { for y in range(0, 2)
+      { for x in range(0, 2)
+        { cells.push((math::Vec3i::new(x, y, z), Red)); }
+      }
+    }
+    let (states, voxels) = grid(dims, cells);
+    let verts = mesh(dims, states, voxels, []);
+    assert_eq!(verts.len(), 6 * 6);
+    assert_eq!(area(verts), 24.0);
+  }
+
+  #[test]
+  fn colors()
+  {
+    /* The hidden face between them is gone, but nothing merges. */
+    let dims = math::Vec3i::new(2, 1, 1);
+    let (states, voxels) = grid(dims, [ (math::Vec3i::new(0, 0, 0), Red),
+                                        (math::Vec3i::new(1, 0, 0), Blue) ]);
+    let verts = mesh(dims, states, voxels, []);
+    assert_eq!(verts.len(), 10 * 6);
+    assert_eq!(area(verts), 10.0);
+    assert_eq!(verts.iter().count(|v| v.color == Blue), 5 * 6);
+  }
+
+  #[test]
+  fn occlusion()
+  {
+    /* Only the top faces differ in occlusion, so they don't merge. */
+    let dims = math::Vec3i::new(2, 1, 1);
+    let (states, voxels) = grid(dims, [ (math::Vec3i::new(0, 0, 0), Red),
+                                        (math::Vec3i::new(1, 0, 0), Red) ]);
+    let mut occlusion = vec::from_elem(2 * 6, 255u8);
+    occlusion[6 + 2] = 0; /* +Y of the second voxel. */
+    let verts = mesh(dims, states, voxels, occlusion);
+    assert_eq!(verts.len(), 7 * 6);
+    assert_eq!(area(verts), 10.0);
+    assert_eq!(verts.iter().count(|v| v.color == math::Vec3f::zero()), 6);
+  }
+
+  #[test]
+  fn region()
+  {
+    /* Neighbours outside of the region still hide faces. */
+    let dims = math::Vec3i::new(2, 1, 1);
+    let (states, voxels) = grid(dims, [ (math::Vec3i::new(0, 0, 0), Red),
+                                        (math::Vec3i::new(1, 0, 0), Red) ]);
+    let verts = mesh_region(states, voxels, [], dims, 1.0, math::Vec3f::zero(),
+                            math::Vec3i::zero(), math::Vec3i::new(1, 1, 1));
+    assert_eq!(verts.len(), 5 * 6);
+    assert_eq!(area(verts), 5.0);
+  }
+}
@@ -11,9 +11,23 @@
 
 pub use self::map::Map;
 pub use self::vertex::Vertex;
-pub use self::behavior::{ Visible };
+pub use self::debris::Debris;
+pub use self::lod::Lod;
+pub use self::prop::Prop;
+pub use self::nav::Nav_Grid;
+pub use self::compressed::{ Compressed_Map, Delta, Material };
+pub use self::behavior::{ Visible, Anchor, Index_Mask };
 
 mod map;
 mod vertex;
+mod debris;
+mod lod;
+mod prop;
+mod nav;
+mod compressed;
+mod vox;
 mod behavior;
+pub mod cache;
+pub mod mesher;
+mod occlusion;
 
@@ -0,0 +1,435 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/voxel/nav.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A navigation grid of the cells that
+      can be stood in, derived from the
+      voxel map, with A* path queries
+      between them. Walkable cells are
+      empty, have a voxel beneath them,
+      and have room overhead.
+*/
+
+use std::cmp;
+use std::hashmap::{ HashSet, HashMap };
+use extra::priority_queue::PriorityQueue;
+use math;
+use super::{ Map, Visible };
+use super::map::{ cell_index, index_to_cell, world_to_cell, cell_to_world };
+
+/* Room needed above the floor to fit an agent, in world units. */
+static Headroom: f32 = 1.8;
+
+/* Highest ledge that can be stepped up, in world units. */
+static Max_Step: f32 = 0.45;
+
+/* Deepest that can be dropped down, in world units. */
+static Max_Drop: f32 = 1.35;
+
+/* Slack for limits which are a whole number of cells, so
+ * that float error doesn't round them the wrong way. */
+static Cell_Slack: f32 = 0.01;
+
+/* Extra cost, per cell, of changing height. */
+static Climb_Cost: f32 = 0.5;
+
+/* Gives up on paths which need more cells than this explored. */
+static Max_Expansions: uint = 100000;
+
+/* Horizontal moves between columns. */
+static Moves: [(i32, i32), ..4] = [ (1, 0), (-1, 0), (0, 1), (0, -1) ];
+
+pub struct Nav_Grid
+{
+  dimensions: math::Vec3i,
+  voxel_size: f32,
+
+  /* The limits, in cells, for the voxel size. */
+  headroom: i32,
+  max_step: i32,
+  max_drop: i32,
+
+  /* Cell indices of every walkable cell. */
+  walkable: HashSet<uint>,
+}
+
+/* An open cell in the search, ordered so the cheapest comes first. */
+struct Open_Cell
+{
+  estimate: f32,
+  cell: math::Vec3i,
+}
+
+impl Ord for Open_Cell
+{
+  /* The queue is a max heap, so invert the order. */
+  fn lt(&self, other: &Open_Cell) -> bool
+  { self.estimate > other.estimate }
+  fn le(&self, other: &Open_Cell) -> bool
+  { self.estimate >= other.estimate }
+  fn ge(&self, other: &Open_Cell) -> bool
+  { self.estimate <= other.estimate }
+  fn gt(&self, other: &Open_Cell) -> bool
+  { self.estimate < other.estimate }
+}
+
+impl Nav_Grid
+{
+  pub fn new(map: &Map) -> Nav_Grid
+  {
+    let mut nav = Nav_Grid
+    {
+      dimensions: map.dimensions,
+      voxel_size: map.voxel_size,
+
+      /* Agents need at least the room they ask for, but
+       * can't climb or drop any more than they're allowed. */
+      headroom: cmp::max(((Headroom / map.voxel_size) - Cell_Slack).ceil() as i32, 1),
+      max_step: ((Max_Step / map.voxel_size) + Cell_Slack).floor() as i32,
+      max_drop: ((Max_Drop / map.voxel_size) + Cell_Slack).floor() as i32,
+
+      walkable: HashSet::new(),
+    };
+
+    let dims = map.dimensions;
+    let headroom = nav.headroom;
+    let states = &map.states;
+    for z in range(0, dims.z)
+    { for y in range(0, dims.y)
+      { for x in range(0, dims.x)
+        {
+          let cell = math::Vec3i::new(x, y, z);
+          if standable(*states, &dims, &cell, headroom)
+          { nav.walkable.insert(cell_index(&dims, &cell).unwrap()); }
+        }
+      }
+    }
+
+    nav
+  }
+
+  /* Re-evaluates the cells affected by changes to the map since
+   * the last update. Returns the number of changed cells. */
+  pub fn update(&mut self, map: &mut Map) -> uint
+  {
+    let dirty = map.take_dirty_cells();
+    let dims = self.dimensions;
+    let states = &map.states;
+    for index in dirty.iter()
+    {
+      let changed = index_to_cell(&dims, *index);
+
+      /* A cell supports the one above it and limits the
+       * headroom of those below it. */
+      for y in range(changed.y - self.headroom + 1, changed.y + 2)
+      {
+        let cell = math::Vec3i::new(changed.x, y, changed.z);
+        let index = match cell_index(&dims, &cell)
+        {
+          Some(i) => { i },
+          None => { loop; }
+        };
+        if standable(*states, &dims, &cell, self.headroom)
+        { self.walkable.insert(index); }
+        else
+        { self.walkable.remove(&index); }
+      }
+    }
+
+    dirty.len()
+  }
+
+  pub fn is_walkable(&self, cell: &math::Vec3i) -> bool
+  {
+    match cell_index(&self.dimensions, cell)
+    {
+      Some(i) => { self.walkable.contains(&i) },
+      None => { false }
+    }
+  }
+
+  /* The walkable cell an agent at the world position would be
+   * standing in; positions above the floor fall down to it. */
+  pub fn find_cell(&self, position: &math::Vec3f) -> Option<math::Vec3i>
+  {
+    let cell = world_to_cell(&self.dimensions, self.voxel_size, position);
+    range(0, self.headroom + self.max_drop).map(|down| math::Vec3i::new(cell.x, cell.y - down, cell.z))
+                                           .find(|c| self.is_walkable(c))
+  }
+
+  /* World position of the floor of a cell. */
+  pub fn cell_position(&self, cell: &math::Vec3i) -> math::Vec3f
+  {
+    cell_to_world(&self.dimensions, self.voxel_size, cell) - math::Vec3f::new(0.0, self.voxel_size / 2.0, 0.0)
+  }
+
+  /* A* between two world positions. The path is returned as the
+   * floor positions of each cell along it, including both ends. */
+  pub fn find_path(&self, map: &Map, from: &math::Vec3f, to: &math::Vec3f) -> Result<~[math::Vec3f], ~str>
+  {
+    let start = match self.find_cell(from)
+    {
+      Some(c) => { c },
+      None => { return Err(~"Start is not on walkable ground"); }
+    };
+    let goal = match self.find_cell(to)
+    {
+      Some(c) => { c },
+      None => { return Err(~"Goal is not on walkable ground"); }
+    };
+
+    let heuristic = |cell: &math::Vec3i| -> f32
+    {
+      let diff = *cell - goal;
+      (diff.x.abs() + diff.z.abs()) as f32 + (diff.y.abs() as f32 * Climb_Cost)
+    };
+
+    /* Everything is keyed by cell index. */
+    let dims = self.dimensions;
+    let index_of = |cell: &math::Vec3i| -> uint { cell_index(&dims, cell).unwrap() };
+    let mut open = PriorityQueue::new();
+    let mut costs = HashMap::new();
+    let mut came_from = HashMap::new();
+    let mut closed = HashSet::new();
+    open.push(Open_Cell { estimate: heuristic(&start), cell: start });
+    costs.insert(index_of(&start), 0.0f32);
+
+    while !open.is_empty()
+    {
+      let curr = open.pop().cell;
+      if curr == goal
+      {
+        let mut path = ~[self.cell_position(&curr)];
+        let mut cell = curr;
+        loop
+        {
+          match came_from.find(&index_of(&cell))
+          {
+            Some(prev) => { cell = *prev; }
+            None => { break; }
+          }
+          path.push(self.cell_position(&cell));
+        }
+        path.reverse();
+        return Ok(path);
+      }
+
+      if !closed.insert(index_of(&curr))
+      { loop; }
+      if closed.len() > Max_Expansions
+      { return Err(~"Path search gave up"); }
+
+      let curr_cost = *costs.get(&index_of(&curr));
+      for &(next, step_cost) in self.neighbours(map, &curr).iter()
+      {
+        let next_index = index_of(&next);
+        if closed.contains(&next_index)
+        { loop; }
+
+        let cost = curr_cost + step_cost;
+        let better = match costs.find(&next_index)
+        {
+          Some(c) => { cost < *c },
+          None => { true }
+        };
+        if better
+        {
+          costs.insert(next_index, cost);
+          came_from.insert(next_index, curr);
+          open.push(Open_Cell { estimate: cost + heuristic(&next), cell: next });
+        }
+      }
+    }
+
+    Err(~"No path exists")
+  }
+
+  /* Walkable cells reachable in one move, along with their costs.
+   * Each neighbouring column is searched top down, so the highest
+   * reachable floor is preferred. */
+  fn neighbours(&self, map: &Map, cell: &math::Vec3i) -> ~[(math::Vec3i, f32)]
+  {
+    let dims = self.dimensions;
+    let states = &map.states;
+    let mut found = ~[];
+    for &(mx, mz) in Moves.iter()
+    {
+      let (x, z) = (cell.x + mx, cell.z + mz);
+      let mut dy = self.max_step;
+      while dy >= -self.max_drop
+      {
+        let next = math::Vec3i::new(x, cell.y + dy, z);
+        if self.is_walkable(&next)
+        {
+          /* Climbing needs room above the current cell; dropping
+           * needs room above the one below. */
+          let clear = if dy > 0
+          { clear(*states, &dims, cell.x, cell.z, cell.y + self.headroom, next.y + self.headroom) }
+          else
+          { clear(*states, &dims, x, z, next.y + self.headroom, cell.y + self.headroom) };
+          if clear
+          { found.push((next, 1.0 + (cmp::max(dy, -dy) as f32 * Climb_Cost))); }
+          break;
+        }
+        dy -= 1;
+      }
+    }
+    found
+  }
+}
+
+/* Whether an agent, needing the headroom in cells, could stand in the cell. */
+fn standable(states: &[u32], dims: &math::Vec3i, cell: &math::Vec3i, headroom: i32) -> bool
+{
+  let floor = match cell_index(dims, &math::Vec3i::new(cell.x, cell.y - 1, cell.z))
+  {
+    Some(i) => { states[i] },
+    None => { return false; }
+  };
+  if (floor & Visible) == 0 || cell_index(dims, cell).is_none()
+  { return false; }
+
+  clear(states, dims, cell.x, cell.z, cell.y, cell.y + headroom)
+}
+
+/* Whether the cells of a column from bottom up to (but not including)
+ * top are all empty. Cells outside of the grid are empty. */
+fn clear(states: &[u32], dims: &math::Vec3i, x: i32, z: i32, bottom: i32, top: i32) -> bool
+{
+  range(bottom, top).all(|y|
+  {
+    match cell_index(dims, &math::Vec3i::new(x, y, z))
+    {
+      Some(i) => { (states[i] & Visible) == 0 },
+      None => { true }
+    }
+  })
+}
+
+#[cfg(test)]
+mod test
+{
+  use math;
+  use super::Nav_Grid;
+  use super::super::{ Map, Vertex, Visible, Anchor };
+  use super::super::map::cell_position;
+
+  /* At this size, agents need 4 cells of room, can step 1 and drop 3. */
+  static Voxel_Size: f32 = 0.45;
+
+  fn add(map: &mut Map, x: i32, y: i32, z: i32, behaviors: u32)
+  {
+    let dims = map.dimensions;
+    let cell = math::Vec3i::new(x, y, z);
+    map.voxels.push(Vertex::new(cell_position(&dims, &cell), math::Vec3f::new(1.0, 1.0, 1.0)));
+    map.occlusion.push_all([255u8, ..6]);
+    let index = map.voxels.len() as u32 - 1;
+    assert!(map.set(&cell, index | Visible | behaviors));
+  }
+
+  /* A grid with an anchored floor of the given height in each column. */
+  fn grid(dims: math::Vec3i, floor: &fn(i32) -> i32) -> Map
+  {
+    let mut map = Map::new_empty(dims, Voxel_Size);
+    for z in range(0, dims.z)
+    { for x in range(0, dims.x)
+      { for y in range(0, floor(x))
+        { add(&mut map, x, y, z, Anchor); }
+      }
+    }
+    map
+  }
+
+  /* Between the centers of two cells. */
+  fn path(nav: &Nav_Grid, map: &Map, from: (i32, i32, i32), to: (i32, i32, i32)) -> Result<~[math::Vec3f], ~str>
+  {
+    let ((fx, fy, fz), (tx, ty, tz)) = (from, to);
+    nav.find_path(map, &map.cell_to_world(&math::Vec3i::new(fx, fy, fz)),
+                  &map.cell_to_world(&math::Vec3i::new(tx, ty, tz)))
+  }
+
+  #[test]
+  fn limits()
+  {
+    let nav = Nav_Grid::new(&Map::new_empty(math::Vec3i::new(1, 1, 1), Voxel_Size));
+    assert_eq!((nav.headroom, nav.max_step, nav.max_drop), (4, 1, 3));
+
+    /* Larger voxels still leave room, but can't be climbed. */
+    let nav = Nav_Grid::new(&Map::new_empty(math::Vec3i::new(1, 1, 1), 1.0));
+    assert_eq!((nav.headroom, nav.max_step, nav.max_drop), (2, 0, 1));
+  }
+
+  #[test]
+  fn step()
+  {
+    let map = grid(math::Vec3i::new(6, 8, 1), |x| if x < 3 { 1 } else { 2 });
+    let nav = Nav_Grid::new(&map);
+    let found = match path(&nav, &map, (0, 1, 0), (5, 2, 0))
+    {
+      Ok(found) => { found },
+      Err(err) => { fail!(err); }
+    };
+    assert_eq!(found.len(), 6);
+    assert_eq!(found[3], nav.cell_position(&math::Vec3i::new(3, 2, 0)));
+  }
+
+  #[test]
+  fn headroom()
+  {
+    /* Three empty cells under the ceiling, when four are needed. */
+    let mut map = grid(math::Vec3i::new(6, 8, 1), |_| 1);
+    add(&mut map, 3, 4, 0, 0);
+    let nav = Nav_Grid::new(&map);
+    assert!(nav.is_walkable(&math::Vec3i::new(2, 1, 0)));
+    assert!(!nav.is_walkable(&math::Vec3i::new(3, 1, 0)));
+    assert_eq!(path(&nav, &map, (0, 1, 0), (5, 1, 0)), Err(~"No path exists"));
+  }
+
+  #[test]
+  fn max_drop()
+  {
+    /* A drop of three cells is allowed, but not four. */
+    let map = grid(math::Vec3i::new(6, 8, 1), |x| if x < 3 { 5 } else { 2 });
+    let nav = Nav_Grid::new(&map);
+    assert!(path(&nav, &map, (0, 5, 0), (5, 2, 0)).is_ok());
+
+    let map = grid(math::Vec3i::new(6, 8, 1), |x| if x < 3 { 5 } else { 1 });
+    let nav = Nav_Grid::new(&map);
+    assert_eq!(path(&nav, &map, (0, 5, 0), (5, 1, 0)), Err(~"No path exists"));
+  }
+
+  #[test]
+  fn reroute()
+  {
+    /* A wall across all but the last row, which has to be walked around. */
+    let mut map = grid(math::Vec3i::new(5, 6, 3), |_| 1);
+    for z in range(0, 2)
+    { for y in range(1, 6)
+      { add(&mut map, 2, y, z, 0); }
+    }
+    let mut nav = Nav_Grid::new(&map);
+    map.take_dirty_cells();
+    match path(&nav, &map, (0, 1, 0), (4, 1, 0))
+    {
+      Ok(found) => { assert_eq!(found.len(), 9); },
+      Err(err) => { fail!(err); }
+    }
+
+    /* Carving a doorway through the first row opens a direct path. */
+    for y in range(1, 5)
+    {
+      let center = map.cell_to_world(&math::Vec3i::new(2, y, 0));
+      map.carve(center, 0.2);
+    }
+    assert!(nav.update(&mut map) > 0);
+    match path(&nav, &map, (0, 1, 0), (4, 1, 0))
+    {
+      Ok(found) => { assert_eq!(found.len(), 5); },
+      Err(err) => { fail!(err); }
+    }
+  }
+}
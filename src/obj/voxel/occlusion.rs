@@ -0,0 +1,115 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/voxel/occlusion.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Ambient occlusion for each face of
+      each voxel, based on how many cells
+      surround the space in front of it.
+
+      Values are stored six per voxel, in
+      the order of the map's neighbours,
+      where 255 is entirely unoccluded.
+*/
+
+use std::vec;
+use math;
+use super::{ Visible, Index_Mask };
+use super::map::{ Neighbours, cell_index };
+
+/* How dark a face gets when it's completely surrounded. */
+static Strength: f32 = 0.6;
+
+pub fn bake(states: &[u32], voxel_count: uint, dims: &math::Vec3i) -> ~[u8]
+{
+  let mut occlusion = vec::from_elem(voxel_count * 6, 255u8);
+  for z in range(0, dims.z)
+  { for y in range(0, dims.y)
+    { for x in range(0, dims.x)
+      { bake_cell(occlusion, states, dims, &math::Vec3i::new(x, y, z)); }
+    }
+  }
+  occlusion
+}
+
+/* Re-bakes every voxel which could be affected by the given cell
+ * changing. Returns the indices of the re-baked voxels. */
+pub fn refresh_around(occlusion: &mut [u8], states: &[u32], dims: &math::Vec3i, cell: &math::Vec3i) -> ~[u32]
+{
+  let mut touched = ~[];
+  for z in range(cell.z - 1, cell.z + 2)
+  { for y in range(cell.y - 1, cell.y + 2)
+    { for x in range(cell.x - 1, cell.x + 2)
+      {
+        match bake_cell(occlusion, states, dims, &math::Vec3i::new(x, y, z))
+        {
+          Some(vox) => { touched.push(vox); }
+          None => { }
+        }
+      }
+    }
+  }
+  touched
+}
+
+/* Returns the voxel index, if there's a voxel in the cell. */
+fn bake_cell(occlusion: &mut [u8], states: &[u32], dims: &math::Vec3i, cell: &math::Vec3i) -> Option<u32>
+{
+  let state = match cell_index(dims, cell)
+  {
+    Some(i) if (states[i] & Visible) != 0 => { states[i] },
+    _ => { return None; }
+  };
+  let vox = state & Index_Mask;
+
+  let filled = |c: &math::Vec3i| -> bool
+  {
+    match cell_index(dims, c)
+    {
+      Some(i) => { (states[i] & Visible) != 0 },
+      None => { false }
+    }
+  };
+
+  for (face, &(nx, ny, nz)) in Neighbours.iter().enumerate()
+  {
+    let normal = math::Vec3i::new(nx, ny, nz);
+    let front = *cell + normal;
+
+    /* Hidden faces are never seen, so just leave them dark. */
+    let value = if filled(&front)
+    { 0 }
+    else
+    {
+      /* Count the ring of cells around the one in front. */
+      let (t1, t2) = tangents(&normal);
+      let mut count = 0;
+      for a in range(-1, 2)
+      {
+        for b in range(-1, 2)
+        {
+          if (a != 0 || b != 0) && filled(&(front + (t1 * a) + (t2 * b)))
+          { count += 1; }
+        }
+      }
+      (255.0 * (1.0 - (Strength * (count as f32 / 8.0)))) as u8
+    };
+    occlusion[(vox * 6) + face as u32] = value;
+  }
+
+  Some(vox)
+}
+
+/* Two axes perpendicular to a face's normal. */
+fn tangents(normal: &math::Vec3i) -> (math::Vec3i, math::Vec3i)
+{
+  if normal.x != 0
+  { (math::Vec3i::new(0, 1, 0), math::Vec3i::new(0, 0, 1)) }
+  else if normal.y != 0
+  { (math::Vec3i::new(1, 0, 0), math::Vec3i::new(0, 0, 1)) }
+  else
+  { (math::Vec3i::new(1, 0, 0), math::Vec3i::new(0, 1, 0)) }
+}
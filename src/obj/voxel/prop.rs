@@ -0,0 +1,84 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/voxel/prop.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A standalone voxel object placed in
+      the world, separate from the map.
+*/
+
+use math;
+use md5;
+use super::Map;
+
+pub struct Prop
+{
+  name: ~str,
+
+  /* Centered on the prop's position. */
+  map: Map,
+  position: math::Vec3f,
+  orientation: math::Quaternion,
+}
+
+impl Prop
+{
+  pub fn new(name: &str, map: Map, position: math::Vec3f) -> Prop
+  {
+    Prop
+    {
+      name: name.to_owned(),
+      map: map,
+      position: position,
+      orientation: math::Quaternion::new(0.0, 0.0, 0.0, 1.0),
+    }
+  }
+
+  /* Voxelizes the model, as last skinned with Model::skin. MD5
+   * models are Z up, so their Y and Z axes are rotated into ours. */
+  pub fn new_from_model(name: &str, model: &md5::Model, voxel_size: f32,
+                        position: math::Vec3f) -> Result<Prop, ~str>
+  {
+    let tris = do model.get_triangles().map |tri|
+    {
+      let mut tri = *tri;
+      for vert in tri.verts.mut_iter()
+      {
+        let pos = vert.position;
+        vert.position = math::Vec3f::new(pos.x, pos.z, -pos.y);
+      }
+      tri
+    };
+
+    match Map::new_with_progress(tris, voxel_size, |_, _| true)
+    {
+      Ok(map) =>
+      {
+        let mut map = map;
+        map.anchor_base();
+        Ok(Prop::new(name, map, position))
+      }
+      Err(err) => { Err(err) }
+    }
+  }
+
+  /* Object space to world space. */
+  pub fn get_transform(&self) -> math::Mat4x4
+  {
+    self.orientation.to_mat() *
+      math::Mat4x4::new_translation(self.position.x, self.position.y, self.position.z)
+  }
+
+  /* Carves the prop with a sphere in world space. */
+  pub fn carve(&mut self, center: math::Vec3f, radius: f32) -> uint
+  {
+    let local = self.orientation.get_conjugate().rotate_vec(&(center - self.position));
+    self.map.carve(local, radius)
+  }
+
+  pub fn update(&mut self, delta: f32) /* dt is in terms of seconds. */
+  { self.map.update(delta); }
+}
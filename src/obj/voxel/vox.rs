@@ -0,0 +1,490 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: obj/voxel/vox.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Imports and exports MagicaVoxel .vox
+      files, so that voxel content can be
+      touched up or authored by hand.
+
+      Only the first model of a file is
+      used. MagicaVoxel is Z up, so its Y
+      and Z axes are rotated into ours.
+      Behaviors (anchors, etc.) are lost
+      on export.
+*/
+
+use std::{ io, path, vec, cmp };
+use std::num::CheckedAdd;
+use std::hashmap::HashMap;
+use math;
+use super::{ Map, Vertex, Visible, Index_Mask };
+use super::map::{ cell_index, cell_position };
+use util::Log;
+
+#[macro_escape]
+#[path = "../../util/log_macros.rs"]
+mod log_macros;
+
+static Magic: &'static str = "VOX ";
+static Version: u32 = 150;
+
+/* Models can't be larger than this along any axis. */
+static Max_Size: i32 = 256;
+
+impl Map
+{
+  /* Exports the cells from start up to (but not including) end.
+   * Returns the number of voxels written. */
+  pub fn export_vox(&self, file: &str, start: &math::Vec3i, end: &math::Vec3i) -> Result<uint, ~str>
+  {
+    let states = &self.states;
+
+    let dims = self.dimensions;
+    let start = math::Vec3i::new(cmp::max(start.x, 0), cmp::max(start.y, 0), cmp::max(start.z, 0));
+    let end = math::Vec3i::new(cmp::min(end.x, dims.x), cmp::min(end.y, dims.y), cmp::min(end.z, dims.z));
+    let size = end - start;
+    if size.x <= 0 || size.y <= 0 || size.z <= 0
+    { return Err(~"Region is empty"); }
+    if size.x > Max_Size || size.y > Max_Size || size.z > Max_Size
+    { return Err(fmt!("Region %s is larger than %d along an axis", size.to_str(), Max_Size as int)); }
+
+    /* Gather the voxels, in .vox space, with their colors. */
+    let mut found = ~[];
+    for z in range(start.z, end.z)
+    { for y in range(start.y, end.y)
+      { for x in range(start.x, end.x)
+        {
+          let state = states[cell_index(&dims, &math::Vec3i::new(x, y, z)).unwrap()];
+          if (state & Visible) == 0
+          { loop; }
+
+          let color = self.voxels[state & Index_Mask].color;
+          found.push(([ (x - start.x) as u8, (size.z - 1 - (z - start.z)) as u8, (y - start.y) as u8 ],
+                      [ (color.x * 255.0) as u8, (color.y * 255.0) as u8, (color.z * 255.0) as u8 ]));
+        }
+      }
+    }
+
+    let (palette, indices) = build_palette(found.map(|&(_, color)| color));
+
+    let mut size_chunk = ~[];
+    push_u32(&mut size_chunk, size.x as u32);
+    push_u32(&mut size_chunk, size.z as u32);
+    push_u32(&mut size_chunk, size.y as u32);
+
+    let mut xyzi_chunk = ~[];
+    push_u32(&mut xyzi_chunk, found.len() as u32);
+    for (i, &(pos, _)) in found.iter().enumerate()
+    {
+      xyzi_chunk.push_all(pos);
+      xyzi_chunk.push(indices[i]);
+    }
+
+    let mut rgba_chunk = ~[];
+    for i in range(0, 256)
+    {
+      let color = if i < palette.len() { palette[i] } else { [0u8, 0, 0] };
+      rgba_chunk.push_all(color);
+      rgba_chunk.push(255);
+    }
+
+    let mut children = ~[];
+    push_chunk(&mut children, "SIZE", size_chunk);
+    push_chunk(&mut children, "XYZI", xyzi_chunk);
+    push_chunk(&mut children, "RGBA", rgba_chunk);
+
+    let fio = match io::file_writer(&path::Path(file), [io::Create, io::Truncate])
+    {
+      Ok(w) => { w },
+      Err(err) => { return Err(err); }
+    };
+    fio.write(Magic.as_bytes());
+    fio.write_le_u32(Version);
+    fio.write("MAIN".as_bytes());
+    fio.write_le_u32(0);
+    fio.write_le_u32(children.len() as u32);
+    fio.write(children);
+
+    Ok(found.len())
+  }
+
+  /* Imports the first model of a .vox file as a standalone
+   * map, centered on the origin. */
+  pub fn import_vox(file: &str, voxel_size: f32) -> Result<Map, ~str>
+  {
+    let data = match io::read_whole_file(&path::Path(file))
+    {
+      Ok(d) => { d },
+      Err(err) => { return Err(err); }
+    };
+
+    if data.len() < 8 || data.slice(0, Magic.len()) != Magic.as_bytes()
+    { return Err(fmt!("%s is not a .vox file", file)); }
+    let version = read_u32(data, 4);
+    log_debug!("Importing %s (version %u)", file, version as uint);
+
+    if data.len() < 20 || data.slice(8, 12) != "MAIN".as_bytes()
+    { return Err(fmt!("%s is missing its main chunk", file)); }
+    /* Everything else is a child of main. */
+    let mut offset = match (read_u32(data, 12) as uint).checked_add(&20)
+    {
+      Some(o) if o <= data.len() => { o },
+      _ => { return Err(fmt!("%s has a truncated main chunk", file)); }
+    };
+
+    let mut size = None;
+    let mut found: &[u8] = &[];
+    let mut palette = None;
+    while data.len() - offset >= 12
+    {
+      let id = data.slice(offset, offset + 4);
+      let content_size = read_u32(data, offset + 4) as uint;
+      let children_size = read_u32(data, offset + 8) as uint;
+      let start = offset + 12;
+      if content_size > data.len() - start
+      { return Err(fmt!("%s has a truncated chunk", file)); }
+      let content = data.slice(start, start + content_size);
+
+      if id == "SIZE".as_bytes() && size.is_none()
+      {
+        if content_size < 12
+        { return Err(fmt!("%s has a truncated SIZE chunk", file)); }
+        let (x, y, z) = (read_u32(content, 0), read_u32(content, 4), read_u32(content, 8));
+        if x == 0 || y == 0 || z == 0 || x > Max_Size as u32 || y > Max_Size as u32 || z > Max_Size as u32
+        { return Err(fmt!("%s has a model of invalid size %u x %u x %u", file, x as uint, y as uint, z as uint)); }
+        size = Some(math::Vec3i::new(x as i32, y as i32, z as i32));
+      }
+      else if id == "XYZI".as_bytes() && size.is_some() && found.len() == 0
+      {
+        if content_size < 4
+        { return Err(fmt!("%s has a truncated XYZI chunk", file)); }
+        let count = read_u32(content, 0) as uint;
+        if count > (content_size - 4) / 4
+        { return Err(fmt!("%s has %u voxels in an XYZI chunk of %u bytes", file, count, content_size)); }
+        found = content.slice(4, 4 + (count * 4));
+      }
+      else if id == "RGBA".as_bytes()
+      {
+        if content_size < 256 * 4
+        { return Err(fmt!("%s has a truncated RGBA chunk", file)); }
+        palette = Some(content.slice(0, 256 * 4));
+      }
+      /* Anything else is additional models, materials, scene graph, etc. */
+
+      offset = match (start + content_size).checked_add(&children_size)
+      {
+        Some(o) if o <= data.len() => { o },
+        _ => { return Err(fmt!("%s has a chunk larger than the file", file)); }
+      };
+    }
+
+    let size = match size
+    {
+      Some(s) => { s },
+      None => { return Err(fmt!("%s has no models", file)); }
+    };
+
+    /* Our Y is their Z; keep each axis even so the grid is centered. */
+    let dims = math::Vec3i::new(size.x + (size.x % 2), size.z + (size.z % 2), size.y + (size.y % 2));
+    let mut map = Map::new_empty(dims, voxel_size);
+    {
+      let states = &mut map.states;
+      for i in range(0, found.len() / 4)
+      {
+        let entry = found.slice(i * 4, (i * 4) + 4);
+        if entry[3] == 0
+        { loop; }
+
+        let cell = math::Vec3i::new(entry[0] as i32, entry[2] as i32, size.y - 1 - (entry[1] as i32));
+        let index = match cell_index(&dims, &cell)
+        {
+          Some(i) => { i },
+          None => { loop; }
+        };
+
+        /* Palette entries are one-based; files without a palette use MagicaVoxel's. */
+        let color = match palette
+        {
+          Some(p) =>
+          {
+            let i = (entry[3] as uint - 1) * 4;
+            [ p[i], p[i + 1], p[i + 2] ]
+          }
+          None => { default_color(entry[3]) }
+        };
+        let color = math::Vec3f::new(color[0] as f32 / 255.0, color[1] as f32 / 255.0, color[2] as f32 / 255.0);
+
+        map.voxels.push(Vertex::new(cell_position(&dims, &cell), color));
+        states[index] = (map.voxels.len() as u32 - 1) | Visible;
+      }
+    }
+    log_debug!("Imported %u voxels from %s", map.voxels.len(), file);
+
+    map.bake_occlusion();
+    map.build_lods();
+
+    Ok(map)
+  }
+}
+
+/* Picks up to 255 colors and maps each voxel to its (one-based)
+ * palette entry. If there are too many distinct colors, they're
+ * snapped to a fixed palette of evenly spaced colors instead. */
+fn build_palette(colors: &[[u8, ..3]]) -> (~[[u8, ..3]], ~[u8])
+{
+  let mut palette = ~[];
+  let mut lookup = HashMap::new();
+  let mut indices = vec::with_capacity(colors.len());
+  for color in colors.iter()
+  {
+    let key = (color[0], color[1], color[2]);
+    let index = match lookup.find(&key)
+    {
+      Some(i) => { *i },
+      None =>
+      {
+        if palette.len() == 255
+        { return snap_palette(colors); }
+        palette.push(*color);
+        lookup.insert(key, palette.len() as u8);
+        palette.len() as u8
+      }
+    };
+    indices.push(index);
+  }
+
+  (palette, indices)
+}
+
+/* 6 levels of red and blue and 7 of green; 252 colors. */
+fn snap_palette(colors: &[[u8, ..3]]) -> (~[[u8, ..3]], ~[u8])
+{
+  let mut palette = ~[];
+  for r in range(0, 6)
+  { for g in range(0, 7)
+    { for b in range(0, 6)
+      { palette.push([ (r * 51) as u8, (g * 42) as u8, (b * 51) as u8 ]); }
+    }
+  }
+
+  let indices = do colors.map |color|
+  {
+    let r = ((color[0] as uint * 5) + 127) / 255;
+    let g = ((color[1] as uint * 6) + 127) / 255;
+    let b = ((color[2] as uint * 5) + 127) / 255;
+    ((r * 42) + (g * 6) + b + 1) as u8
+  };
+
+  (palette, indices)
+}
+
+/* MagicaVoxel's default palette, for the (one-based) index: a 6x6x6
+ * color cube from white down, without black, then ramps of red,
+ * green, blue, and grey. */
+fn default_color(index: u8) -> [u8, ..3]
+{
+  static Ramp: [u8, ..10] = [ 0xEE, 0xDD, 0xBB, 0xAA, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11 ];
+
+  let index = index as uint;
+  if index == 0
+  { [0, 0, 0] }
+  else if index <= 215
+  {
+    let i = index - 1;
+    [ ((5 - (i / 36)) * 51) as u8, ((5 - ((i / 6) % 6)) * 51) as u8, ((5 - (i % 6)) * 51) as u8 ]
+  }
+  else
+  {
+    let i = index - 216;
+    let level = Ramp[i % 10];
+    match i / 10
+    {
+      0 => { [level, 0, 0] },
+      1 => { [0, level, 0] },
+      2 => { [0, 0, level] },
+      _ => { [level, level, level] }
+    }
+  }
+}
+
+fn read_u32(bytes: &[u8], offset: uint) -> u32
+{
+  bytes[offset] as u32 | (bytes[offset + 1] as u32 << 8) |
+  (bytes[offset + 2] as u32 << 16) | (bytes[offset + 3] as u32 << 24)
+}
+
+fn push_u32(bytes: &mut ~[u8], value: u32)
+{
+  for i in range(0, 4)
+  { bytes.push((value >> (i * 8)) as u8); }
+}
+
+fn push_chunk(bytes: &mut ~[u8], id: &str, content: &[u8])
+{
+  bytes.push_all(id.as_bytes());
+  push_u32(bytes, content.len() as u32);
+  push_u32(bytes, 0);
+  bytes.push_all(content);
+}
+
+#[cfg(test)]
+mod test
+{
+  use std::{ io, os, path, vec };
+  use math;
+  use super::{ build_palette, default_color, push_u32, push_chunk };
+  use super::super::{ Map, Vertex, Visible, Index_Mask };
+  use super::super::map::cell_position;
+
+  /* A file in the temp directory, unique to the test. */
+  fn temp_file(name: &str) -> ~str
+  { os::tmpdir().push(fmt!("q3-vox-test-%s.vox", name)).to_str() }
+
+  fn write_file(file: &str, bytes: &[u8])
+  {
+    match io::file_writer(&path::Path(file), [io::Create, io::Truncate])
+    {
+      Ok(fio) => { fio.write(bytes); }
+      Err(err) => { fail!(err); }
+    }
+  }
+
+  fn add(map: &mut Map, cell: math::Vec3i, color: math::Vec3f)
+  {
+    let dims = map.dimensions;
+    map.voxels.push(Vertex::new(cell_position(&dims, &cell), color));
+    map.occlusion.push_all([255u8, ..6]);
+    let index = map.voxels.len() as u32 - 1;
+    assert!(map.set(&cell, index | Visible));
+  }
+
+  /* A file of the given chunks, as children of main. */
+  fn vox(chunks: &[(&str, ~[u8])]) -> ~[u8]
+  {
+    let mut children = ~[];
+    for &(id, ref content) in chunks.iter()
+    { push_chunk(&mut children, id, content.as_slice()); }
+
+    let mut bytes = "VOX ".as_bytes().to_owned();
+    push_u32(&mut bytes, 150);
+    bytes.push_all("MAIN".as_bytes());
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, children.len() as u32);
+    bytes.push_all(children);
+    bytes
+  }
+
+  fn u32s(values: &[u32]) -> ~[u8]
+  {
+    let mut bytes = ~[];
+    for value in values.iter()
+    { push_u32(&mut bytes, *value); }
+    bytes
+  }
+
+  fn import_error(name: &str, bytes: &[u8]) -> ~str
+  {
+    let file = temp_file(name);
+    write_file(file, bytes);
+    let res = Map::import_vox(file, 0.5);
+    os::remove_file(&path::Path(file));
+    match res
+    {
+      Ok(_) => { fail!("Imported a malformed file"); },
+      Err(err) => { err.replace(file, "test.vox") }
+    }
+  }
+
+  #[test]
+  fn round_trip()
+  {
+    let red = math::Vec3f::new(1.0, 0.0, 0.0);
+    let blue = math::Vec3f::new(0.0, 0.0, 1.0);
+    let mut map = Map::new_empty(math::Vec3i::new(6, 4, 2), 0.5);
+    add(&mut map, math::Vec3i::new(0, 0, 0), red);
+    add(&mut map, math::Vec3i::new(5, 2, 1), blue);
+
+    let file = temp_file("round-trip");
+    assert_eq!(map.export_vox(file, &math::Vec3i::new(0, 0, 0), &map.dimensions), Ok(2));
+
+    /* Their Y is our Z, and their Z is our Y. */
+    let bytes = match io::read_whole_file(&path::Path(file))
+    {
+      Ok(bytes) => { bytes },
+      Err(err) => { fail!(err); }
+    };
+    assert_eq!(bytes.slice(20, 24), "SIZE".as_bytes());
+    assert_eq!(bytes.slice(32, 44), u32s([6, 2, 4]).as_slice());
+
+    let imported = match Map::import_vox(file, 0.5)
+    {
+      Ok(imported) => { imported },
+      Err(err) => { fail!(err); }
+    };
+    os::remove_file(&path::Path(file));
+
+    assert_eq!(imported.dimensions, map.dimensions);
+    assert_eq!(imported.voxels.len(), 2);
+    for i in range(0, map.states.len())
+    {
+      let (a, b) = (map.states[i], imported.states[i]);
+      assert_eq!(a & Visible, b & Visible);
+      if (a & Visible) != 0
+      { assert_eq!(map.voxels[a & Index_Mask].color, imported.voxels[b & Index_Mask].color); }
+    }
+  }
+
+  #[test]
+  fn snap_palette()
+  {
+    /* Up to 255 colors are kept as they are. */
+    let colors = do vec::from_fn(255) |i| { [ i as u8, 0, 0 ] };
+    let (palette, indices) = build_palette(colors);
+    assert_eq!(palette.len(), 255);
+    assert_eq!(indices[254], 255);
+
+    /* Any more are snapped to the nearest of the fixed colors. */
+    let colors = do vec::from_fn(300) |i| { [ (i % 256) as u8, (i / 256) as u8, 0 ] };
+    let (palette, indices) = build_palette(colors);
+    assert_eq!(palette.len(), 252);
+    assert_eq!(indices.len(), 300);
+    assert_eq!(indices[0], 1);
+    assert_eq!(palette[indices[255] as uint - 1].to_owned(), ~[255u8, 0, 0]);
+    assert!(indices.iter().all(|i| *i >= 1 && *i as uint <= palette.len()));
+  }
+
+  #[test]
+  fn default_colors()
+  {
+    assert_eq!(default_color(1).to_owned(), ~[255u8, 255, 255]);
+    assert_eq!(default_color(215).to_owned(), ~[0u8, 0, 51]);
+    assert_eq!(default_color(216).to_owned(), ~[0xEEu8, 0, 0]);
+    assert_eq!(default_color(255).to_owned(), ~[0x11u8, 0x11, 0x11]);
+  }
+
+  #[test]
+  fn truncated()
+  {
+    let size = u32s([2, 2, 2]);
+    let xyzi = ~[1u8, 0, 0, 0, 0, 0, 0, 1];
+    let rgba = [255u8, ..(256 * 4)].to_owned();
+
+    /* Chunks too small for their contents. */
+    assert_eq!(import_error("size", vox([("SIZE", u32s([2, 2]))])),
+               ~"test.vox has a truncated SIZE chunk");
+    assert_eq!(import_error("xyzi", vox([("SIZE", size.clone()), ("XYZI", u32s([2, 0]))])),
+               ~"test.vox has 2 voxels in an XYZI chunk of 8 bytes");
+    assert_eq!(import_error("rgba", vox([("SIZE", size.clone()), ("XYZI", xyzi.clone()),
+                                         ("RGBA", rgba.slice(0, 512).to_owned())])),
+               ~"test.vox has a truncated RGBA chunk");
+
+    /* Files which end partway through each chunk. */
+    let valid = vox([("SIZE", size.clone()), ("XYZI", xyzi.clone()), ("RGBA", rgba.clone())]);
+    for &(name, len) in [ ("end-size", 40u), ("end-xyzi", 60), ("end-rgba", valid.len() - 1) ].iter()
+    { assert_eq!(import_error(name, valid.slice(0, len)), ~"test.vox has a truncated chunk"); }
+  }
+}
@@ -0,0 +1,263 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: res/manager.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Caches shaders, textures, fonts, and
+      models by path, so each is only loaded
+      once and shared by everything using it.
+      Every get needs a matching release;
+      assets are unloaded on their last one.
+*/
+
+use std::local_data;
+use std::hashmap::HashMap;
+use gl2 = opengles::gl2;
+use gl;
+use ui;
+use md5;
+use md3;
+use iqm;
+use util::Log;
+
+#[macro_escape]
+#[path = "../util/log_macros.rs"]
+mod log_macros;
+
+static tls_key: local_data::Key<@mut Manager> = &local_data::Key;
+
+struct Entry<T>
+{
+  asset: T,
+  references: uint,
+  size: uint, /* Approximate bytes, CPU and GPU. */
+}
+
+struct Cache<T>
+{
+  entries: HashMap<~str, Entry<T>>,
+}
+
+impl<T> Cache<T>
+{
+  fn new() -> Cache<T>
+  { Cache { entries: HashMap::new() } }
+
+  /* Loads the asset, along with its size, if it isn't cached. */
+  fn acquire<'a>(&'a mut self, key: &str, load: &fn() -> Result<(T, uint), ~str>) -> Result<&'a T, ~str>
+  {
+    let key = key.to_owned();
+    if !self.entries.contains_key(&key)
+    {
+      match load()
+      {
+        Ok((asset, size)) =>
+        {
+          log_debug!("Loaded asset %s (%u KiB)", key, size / 1024);
+          self.entries.insert(key.clone(), Entry { asset: asset, references: 0, size: size });
+        }
+        Err(err) => { return Err(err); }
+      }
+    }
+
+    let entry = self.entries.find_mut(&key).unwrap();
+    entry.references += 1;
+    Ok(&entry.asset)
+  }
+
+  fn release(&mut self, key: &str)
+  {
+    let key = key.to_owned();
+    let unused = match self.entries.find_mut(&key)
+    {
+      Some(entry) => { entry.references -= 1; entry.references == 0 },
+      None => { log_error!("Releasing asset %s, which isn't loaded", key); false }
+    };
+    if unused
+    {
+      log_debug!("Unloading asset %s", key);
+      self.entries.remove(&key);
+    }
+  }
+
+  /* One line per asset; returns the total size. */
+  fn describe(&self, kind: &str, lines: &mut ~[~str]) -> uint
+  {
+    let mut total = 0;
+    for (key, entry) in self.entries.iter()
+    {
+      lines.push(fmt!("%s \\5%s\\1 (%u refs, %u KiB)", kind, *key, entry.references, entry.size / 1024));
+      total += entry.size;
+    }
+    total
+  }
+}
+
+pub struct Manager
+{
+  shaders: Cache<@mut gl::Shader>,
+  textures: Cache<@gl::Texture>,
+  fonts: Cache<@ui::Font>,
+  md5_models: Cache<@md5::Model>,
+  md3_models: Cache<@md3::Model>,
+}
+
+impl Manager
+{
+  pub fn initialize() -> @mut Manager
+  {
+    let manager = @mut Manager
+    {
+      shaders: Cache::new(),
+      textures: Cache::new(),
+      fonts: Cache::new(),
+      md5_models: Cache::new(),
+      md3_models: Cache::new(),
+    };
+
+    /* Store in task-local storage. (singleton) */
+    local_data::set(tls_key, manager);
+
+    manager
+  }
+
+  pub fn get() -> @mut Manager
+  {
+    local_data::get(tls_key,
+    |opt|
+    {
+      match opt
+      {
+        Some(x) => *x,
+        None => fail!("Singleton not available: Manager")
+      }
+    })
+  }
+
+  /* Shaders are keyed by both of their files. The same program is
+   * shared, so uniforms need to be updated before each use. */
+  pub fn get_shader(&mut self, vert_file: &str, frag_file: &str) -> @mut gl::Shader
+  {
+    match self.shaders.acquire(shader_key(vert_file, frag_file),
+                               || Ok((gl::Shader_Builder::new_with_files(vert_file, frag_file), 0)))
+    {
+      Ok(shader) => { *shader },
+      Err(err) => { fail!(err) }
+    }
+  }
+
+  /* The program is deleted once nothing else holds the shader. */
+  pub fn release_shader(&mut self, vert_file: &str, frag_file: &str)
+  { self.shaders.release(shader_key(vert_file, frag_file)); }
+
+  pub fn get_texture(&mut self, file: &str) -> @gl::Texture
+  {
+    match self.textures.acquire(file, ||
+    {
+      let texture = gl::Texture::new(gl2::TEXTURE_2D, file);
+      let size = texture.memory_usage();
+      Ok((@texture, size))
+    })
+    {
+      Ok(texture) => { *texture },
+      Err(err) => { fail!(err) }
+    }
+  }
+
+  pub fn release_texture(&mut self, file: &str)
+  { self.textures.release(file); }
+
+  /* Each size of a font is its own atlas. */
+  pub fn get_font(&mut self, file: &str, size: i32) -> @ui::Font
+  {
+    match self.fonts.acquire(font_key(file, size), ||
+    {
+      let font = ui::Font::new(file, size);
+      let bytes = font.memory_usage();
+      Ok((@font, bytes))
+    })
+    {
+      Ok(font) => { *font },
+      Err(err) => { fail!(err) }
+    }
+  }
+
+  pub fn release_font(&mut self, file: &str, size: i32)
+  { self.fonts.release(font_key(file, size)); }
+
+  /* Models are shared as loaded; animated ones, which need
+   * setting up first, are better loaded directly. IQM files
+   * are loaded as MD5 models, with their animations. */
+  pub fn get_md5_model(&mut self, file: &str) -> Result<@md5::Model, ~str>
+  {
+    let model = do self.md5_models.acquire(file)
+    {
+      let model = if file.ends_with(".iqm")
+      { iqm::load(file) }
+      else
+      {
+        match md5::Model::new(file)
+        {
+          Ok(model) => { Ok(model) },
+          Err(err) => { Err(err.to_str()) }
+        }
+      };
+      match model
+      {
+        Ok(model) => { let size = model.memory_usage(); Ok((@model, size)) },
+        Err(err) => { Err(err) }
+      }
+    };
+    match model
+    {
+      Ok(model) => { Ok(*model) },
+      Err(err) => { Err(err) }
+    }
+  }
+
+  pub fn release_md5_model(&mut self, file: &str)
+  { self.md5_models.release(file); }
+
+  pub fn get_md3_model(&mut self, file: &str) -> Result<@md3::Model, ~str>
+  {
+    let model = do self.md3_models.acquire(file)
+    {
+      match md3::Model::new(file)
+      {
+        Ok(model) => { let size = model.memory_usage(); Ok((@model, size)) },
+        Err(err) => { Err(err) }
+      }
+    };
+    match model
+    {
+      Ok(model) => { Ok(*model) },
+      Err(err) => { Err(err) }
+    }
+  }
+
+  pub fn release_md3_model(&mut self, file: &str)
+  { self.md3_models.release(file); }
+
+  /* Every loaded asset, then the total size. */
+  pub fn describe(&self) -> ~str
+  {
+    let mut lines = ~[];
+    let total = self.shaders.describe("shader", &mut lines) +
+                self.textures.describe("texture", &mut lines) +
+                self.fonts.describe("font", &mut lines) +
+                self.md5_models.describe("md5", &mut lines) +
+                self.md3_models.describe("md3", &mut lines);
+    let count = lines.len();
+    lines.push(fmt!("%u assets, %u KiB", count, total / 1024));
+    lines.connect("\n")
+  }
+}
+
+fn shader_key(vert_file: &str, frag_file: &str) -> ~str
+{ fmt!("%s %s", vert_file, frag_file) }
+
+fn font_key(file: &str, size: i32) -> ~str
+{ fmt!("%s@%d", file, size as int) }
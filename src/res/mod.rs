@@ -0,0 +1,15 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: res/mod.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      An aggregator of resource items.
+*/
+
+pub use self::manager::Manager;
+
+mod manager;
+
@@ -10,10 +10,10 @@
       of the in-game console.
 */
 
-use gl2 = opengles::gl2;
 use gl;
 use ui;
 use math;
+use res;
 use super::{ State, Console };
 use util::Log;
 
@@ -21,15 +21,22 @@ use util::Log;
 #[path = "../../util/log_macros.rs"]
 mod log_macros;
 
+static Font_File: &'static str = "data/fonts/test.ttf";
+static Font_Size: i32 = 16;
+
+static Left_Texture: &'static str = "data/img/console/left.png";
+static Right_Texture: &'static str = "data/img/console/right.png";
+static Middle_Texture: &'static str = "data/img/console/middle.png";
+
 struct Console_Renderer
 {
   console: @mut Console,
 
-  font: ui::Font,
+  font: @ui::Font,
 
-  tex_left: gl::Texture,
-  tex_middle: gl::Texture,
-  tex_right: gl::Texture,
+  tex_left: @gl::Texture,
+  tex_middle: @gl::Texture,
+  tex_right: @gl::Texture,
 }
 
 impl Console_Renderer
@@ -40,11 +47,11 @@ impl Console_Renderer
     {
       console: model,
 
-      font: ui::Font::new("data/fonts/test.ttf", 16),
+      font: res::Manager::get().get_font(Font_File, Font_Size),
 
-      tex_left: gl::Texture::new(gl2::TEXTURE_2D, "data/img/console/left.png"),
-      tex_right: gl::Texture::new(gl2::TEXTURE_2D, "data/img/console/right.png"),
-      tex_middle: gl::Texture::new(gl2::TEXTURE_2D, "data/img/console/middle.png"),
+      tex_left: res::Manager::get().get_texture(Left_Texture),
+      tex_right: res::Manager::get().get_texture(Right_Texture),
+      tex_middle: res::Manager::get().get_texture(Middle_Texture),
     };
 
     c
@@ -57,7 +64,14 @@ impl State for Console_Renderer
   { log_debug!("Loading console renderer state"); }
 
   fn unload(&mut self)
-  { log_debug!("Unloading console renderer state"); }
+  {
+    log_debug!("Unloading console renderer state");
+
+    let manager = res::Manager::get();
+    manager.release_font(Font_File, Font_Size);
+    for file in [Left_Texture, Right_Texture, Middle_Texture].iter()
+    { manager.release_texture(*file); }
+  }
 
   fn get_key(&self) -> &str
   { &"console_renderer" }
@@ -88,11 +102,11 @@ impl State for Console_Renderer
     let middle_pos = math::Vec2f::new(self.tex_left.size.x as f32, self.console.position.y);
     let middle_size = math::Vec2f::new(right_pos.x - self.tex_left.size.x as f32, self.tex_middle.size.y as f32);
 
-    renderer.render_texture(&self.tex_left, &self.console.position);
-    renderer.render_texture(&self.tex_right, &right_pos);
-    renderer.render_texture_scale_clamp(&self.tex_middle, &middle_pos, &middle_size);
+    renderer.render_texture(&*self.tex_left, &self.console.position);
+    renderer.render_texture(&*self.tex_right, &right_pos);
+    renderer.render_texture_scale_clamp(&*self.tex_middle, &middle_pos, &middle_size);
 
-    renderer.render_font(self.console.body, math::Vec2f::new(self.tex_left.size.x as f32, 0.0 + self.console.position.y), &self.font);
+    renderer.render_font(self.console.body, math::Vec2f::new(self.tex_left.size.x as f32, 0.0 + self.console.position.y), &*self.font);
     renderer.render_font
     (
       self.console.prefix,
@@ -101,7 +115,7 @@ impl State for Console_Renderer
         self.tex_left.size.x as f32,
         self.tex_left.size.y as f32 - 35.0 + self.console.position.y
       ), 
-      &self.font
+      &*self.font
     );
     renderer.render_font
     (
@@ -111,7 +125,7 @@ impl State for Console_Renderer
         self.tex_left.size.x as f32 + 20.0,
         self.tex_left.size.y as f32 - 35.0 + self.console.position.y
       ),
-      &self.font
+      &*self.font
     );
 
     renderer.end();
@@ -10,7 +10,7 @@
       and render signals.
 */
 
-use std::local_data;
+use std::{ local_data, util };
 use util::Log;
 
 static tls_key: local_data::Key<Director> = &local_data::Key;
@@ -125,11 +125,15 @@ impl Director
     { director.deferreds.push(def); }
   }
 
-  fn run_deferreds(&mut self)
+  /* Deferreds are taken out of the director before being run so
+   * that they're free to modify it (push or pull states, etc). */
+  fn run_deferreds()
   {
-    for x in self.deferreds.iter()
+    let deferreds = do Director::get_mut |director|
+    { util::replace(&mut director.deferreds, ~[]) };
+
+    for x in deferreds.iter()
     { (*x)(); }
-    self.deferreds.clear();
   }
 
   pub fn remove_if(&mut self, cmp: &fn(@mut State) -> bool)
@@ -206,8 +210,7 @@ impl Director
       { break; }
     }
     
-    do Director::get_mut |director|
-    { director.run_deferreds(); }
+    Director::run_deferreds();
   }
 
   pub fn render()
@@ -222,8 +225,7 @@ impl Director
       { break; }
     }
 
-    do Director::get_mut |director|
-    { director.run_deferreds(); }
+    Director::run_deferreds();
   }
 
   /** Input handling. **/
@@ -239,8 +241,7 @@ impl Director
       { break; }
     }
 
-    do Director::get_mut |director|
-    { director.run_deferreds(); }
+    Director::run_deferreds();
   }
 
   pub fn key_char(ch: char)
@@ -255,8 +256,7 @@ impl Director
       { break; }
     }
 
-    do Director::get_mut |director|
-    { director.run_deferreds(); }
+    Director::run_deferreds();
   }
 
   pub fn mouse_action(button: i32, action: i32, mods: i32)
@@ -271,8 +271,7 @@ impl Director
       { break; }
     }
 
-    do Director::get_mut |director|
-    { director.run_deferreds(); }
+    Director::run_deferreds();
   }
 
   pub fn mouse_moved(x: f32, y: f32)
@@ -287,8 +286,7 @@ impl Director
       { break; }
     }
 
-    do Director::get_mut |director|
-    { director.run_deferreds(); }
+    Director::run_deferreds();
   }
 }
 
@@ -0,0 +1,185 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: state/game/actor_renderer.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Animates and renders the MD5 actors
+      walking around the map, along with
+      any MD3 models attached to their
+      joints. Every actor is an instance
+      of the same model; the first is the
+      one controlled by the console.
+*/
+
+use math;
+use md5;
+use md3;
+use res;
+use attachment;
+use util::Log;
+
+#[macro_escape]
+#[path = "../../util/log_macros.rs"]
+mod log_macros;
+
+static Model_File: &'static str = "data/models/bob/bob.md5mesh";
+
+/* Animation states; one-shots return to idle. */
+static Animations: [(&'static str, &'static str, Option<&'static str>), ..2] =
+[
+  ("idle", "data/models/bob/bob.md5anim", None),
+  ("wave", "data/models/bob/wave.md5anim", Some("idle")),
+];
+
+pub struct Actor_Renderer
+{
+  model: Option<@md5::Model>,
+  model_renderer: Option<md5::Model_Renderer>,
+  instances: ~[@mut md5::Instance],
+
+  /* MD3 models attached to the first instance's joints, with their files. */
+  attached: ~[(~str, @mut md3::Instance, md3::Model_Renderer)],
+}
+
+impl Actor_Renderer
+{
+  pub fn new() -> @mut Actor_Renderer
+  {
+    @mut Actor_Renderer
+    {
+      model: None,
+      model_renderer: None,
+      instances: ~[],
+
+      attached: ~[],
+    }
+  }
+
+  /* Without the model, there are no actors. */
+  pub fn load(&mut self)
+  {
+    let mut model = match md5::Model::new(Model_File)
+    {
+      Ok(model) => { model },
+      Err(err) => { log_error!("Failed to load actors: %s", err.to_str()); return; }
+    };
+    for &(name, file, next) in Animations.iter()
+    {
+      let next = match next
+      {
+        Some(next) => { Some(next.to_owned()) },
+        None => { None }
+      };
+      match model.load_animation(name, file, next)
+      {
+        Ok(()) => { },
+        Err(err) => { log_error!("Failed to load animation state %s: %s", name, err); }
+      }
+    }
+    model.animator.add_transition("", "wave", 0.2);
+
+    let model = @model;
+    self.model = Some(model);
+    self.model_renderer = Some(md5::Model_Renderer::new(model));
+    self.instances.push(@mut md5::Instance::new(model));
+  }
+
+  /* Gives the shaders, textures, and MD3 models back to the resource manager. */
+  pub fn unload(&mut self)
+  {
+    for attached in self.attached.mut_iter()
+    {
+      let (ref file, _, ref mut renderer) = *attached;
+      renderer.release();
+      res::Manager::get().release_md3_model(*file);
+    }
+    self.attached.clear();
+
+    match self.model_renderer
+    {
+      Some(ref mut renderer) => { renderer.release(); }
+      None => { }
+    }
+    self.model_renderer = None;
+    self.model = None;
+    self.instances.clear();
+  }
+
+  pub fn update(&mut self, delta: f32) /* dt is in terms of seconds. */
+  {
+    /* Attached models follow joints, so they go after. */
+    for instance in self.instances.iter()
+    { instance.update(delta); }
+    for &(_, instance, _) in self.attached.iter()
+    { instance.update(delta); }
+  }
+
+  pub fn render(&mut self)
+  {
+    match self.model_renderer
+    {
+      Some(ref renderer) =>
+      {
+        for instance in self.instances.iter()
+        { renderer.render(*instance); }
+      }
+      None => { }
+    }
+    for &(_, instance, ref renderer) in self.attached.iter()
+    { instance.render(renderer); }
+  }
+
+  /* Switches the first instance's animation state. */
+  pub fn set_animation(&mut self, name: &str) -> Result<(), ~str>
+  {
+    match self.instances.iter().next()
+    {
+      Some(instance) => { instance.set_animation(name) },
+      None => { Err(~"There are no actors") }
+    }
+  }
+
+  /* Attaches an MD3 model to one of the first instance's joints. */
+  pub fn attach_md3(&mut self, file: &str, joint: &str) -> Result<(), ~str>
+  {
+    let (model, instance) = match (self.model, self.instances.iter().next())
+    {
+      (Some(model), Some(instance)) => { (model, *instance) },
+      _ => { return Err(~"There are no actors"); }
+    };
+    if model.get_joint_transform(instance.get_pose(), joint).is_none()
+    { return Err(fmt!("No joint named %s", joint)); }
+
+    match res::Manager::get().get_md3_model(file)
+    {
+      Ok(md3_model) =>
+      {
+        let md3_instance = @mut md3::Instance::new(md3_model);
+        md3_instance.attach_to(instance as @mut attachment::Attachable, joint);
+        self.attached.push((file.to_owned(), md3_instance, md3::Model_Renderer::new(md3_model)));
+        Ok(())
+      }
+      Err(err) => { Err(err) }
+    }
+  }
+
+  /* Adds an instance in the same state as the first. Returns the
+   * number of instances. */
+  pub fn spawn_instance(&mut self, position: math::Vec3f) -> Result<uint, ~str>
+  {
+    let (model, first) = match (self.model, self.instances.iter().next())
+    {
+      (Some(model), Some(instance)) => { (model, *instance) },
+      _ => { return Err(~"There are no actors"); }
+    };
+
+    let spawned = @mut md5::Instance::new(model);
+    spawned.animator = first.animator.clone();
+    spawned.position = position;
+    self.instances.push(spawned);
+    Ok(self.instances.len())
+  }
+}
@@ -17,6 +17,7 @@ use super::{ State, Game_Renderer };
 use std::sys;
 use gl2 = opengles::gl2;
 use gl;
+use res;
 use ui;
 use math;
 use bsp;
@@ -29,6 +30,9 @@ mod log_macros;
 #[path = "../../gl/check.rs"]
 mod check;
 
+static Vert_Shader: &'static str = "data/shaders/color.vert";
+static Frag_Shader: &'static str = "data/shaders/color.frag";
+
 pub struct BSP_Renderer
 {
   game_renderer: @mut Game_Renderer,
@@ -52,7 +56,7 @@ impl BSP_Renderer
       vao: 0,
       vbo: 0,
 
-      shader: gl::Shader_Builder::new_with_files("data/shaders/color.vert", "data/shaders/color.frag"),
+      shader: res::Manager::get().get_shader(Vert_Shader, Frag_Shader),
       proj_loc: 0,
       world_loc: 0,
     };
@@ -114,7 +118,10 @@ impl State for BSP_Renderer
   }
 
   fn unload(&mut self)
-  { log_debug!("Unloading bsp renderer state."); }
+  {
+    log_debug!("Unloading bsp renderer state.");
+    res::Manager::get().release_shader(Vert_Shader, Frag_Shader);
+  }
 
   fn get_key(&self) -> &str
   { &"bsp_renderer" }
@@ -144,7 +151,7 @@ impl State for BSP_Renderer
         ui_renderer.render_font(
           fmt!("%d", fps as int), 
           math::Vec2f::new(self.game_renderer.camera.window_size.x as f32 - 40.0, 0.0), 
-          &self.game_renderer.fps_font); 
+          &*self.game_renderer.fps_font); 
       }
     } ui_renderer.end();
 
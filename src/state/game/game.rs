@@ -14,9 +14,11 @@
       is included.
 */
 
+use std::{ comm, task, cell };
 use extra;
 use BSP_Map = bsp::Map;
 use Voxel_Map = voxel::Map;
+use voxel;
 use super::State;
 use util::Log;
 
@@ -28,32 +30,123 @@ pub struct Game
 {
   bsp_map: BSP_Map,
   voxel_map: @mut Voxel_Map,
+  props: ~[@mut voxel::Prop],
+  nav: voxel::Nav_Grid,
+}
+
+/* Sent from the loading task as the load progresses. */
+pub enum Load_Event
+{
+  Parsing(f32), /* Fraction of the BSP that has been parsed. */
+  Voxelizing(uint, uint), /* Triangles voxelized, total triangles. */
+  Loaded(BSP_Map, Voxel_Map, voxel::Nav_Grid),
+  Failed(~str),
 }
 
 impl Game
 {
-  pub fn new(map_name: &str) -> Result<@mut Game, ~str>
+  pub fn new(bmap: BSP_Map, vmap: Voxel_Map, nav: voxel::Nav_Grid) -> @mut Game
+  {
+    let game = @mut Game
+    {
+      voxel_map: @mut vmap,
+      bsp_map: bmap,
+      props: ~[],
+      nav: nav,
+    };
+
+    game
+  }
+
+  /* Parses and voxelizes the map, then builds its navigation grid.
+   * This doesn't touch any task-local state, so it's meant to be
+   * run in a background task; progress is sent through the events
+   * channel and any message on the cancel port stops the load. The
+   * work runs in its own task, so that even a failure (such as on
+   * a corrupt file) is sent as an event. */
+  pub fn load(map_name: ~str, voxel_size: f32, events: comm::SharedChan<Load_Event>, cancel: comm::Port<()>)
   {
-    let bmap = BSP_Map::new(~"data/maps/" + map_name + ".bsp");
+    let worker_events = events.clone();
+    let cancel = cell::Cell::new(cancel);
+    let res = do task::try
+    {
+      Log::initialize(); /* Done for every task. */
+      let cancel = cancel.take();
+
+      /* The receiving end may have gone away; don't fail if so. */
+      match Game::load_maps(map_name, voxel_size, &worker_events, &cancel)
+      {
+        Ok((bmap, vmap)) =>
+        {
+          /* Scans the whole grid, so it's kept off of the main task. */
+          let start_time = extra::time::precise_time_s();
+          let nav = voxel::Nav_Grid::new(&vmap);
+          log_info!("Built navigation grid of %u walkable cells in %f seconds",
+                    nav.walkable.len(), extra::time::precise_time_s() - start_time);
+
+          worker_events.try_send(Loaded(bmap, vmap, nav));
+        }
+        Err(err) => { worker_events.try_send(Failed(err)); }
+      }
+    };
+
+    if res.is_err()
+    { events.try_send(Failed(~"Loading failed unexpectedly; see the log")); }
+  }
+
+  fn load_maps(map_name: &str, voxel_size: f32, events: &comm::SharedChan<Load_Event>, cancel: &comm::Port<()>) -> Result<(BSP_Map, Voxel_Map), ~str>
+  {
+    let bsp_file = ~"data/maps/" + map_name + ".bsp";
+    let bmap = BSP_Map::new_with_progress(bsp_file, |done| { events.try_send(Parsing(done)); });
     if bmap.is_err()
     { return Err(bmap.unwrap_err()); }
     let bmap = bmap.unwrap();
 
-    let start_time = extra::time::precise_time_s();
-    let vmap = Voxel_Map::new(bmap.tris, 300);
-    let time = extra::time::precise_time_s() - start_time;
-    log_info!("Voxelization took %f seconds", time);
-    if vmap.is_err()
-    { return Err(vmap.unwrap_err()); }
-    let vmap = vmap.unwrap();
+    if cancel.peek()
+    { return Err(~"Loading cancelled"); }
 
-    let game = @mut Game
+    let cache_file = ~"data/cache/" + map_name + ".q3vx";
+    let cache_key = match voxel::cache::key(bsp_file, voxel_size)
     {
-      voxel_map: vmap,
-      bsp_map: bmap,
+      Ok(key) => { key },
+      Err(err) => { return Err(err); }
     };
 
-    Ok(game)
+    /* Try to skip voxelization by loading a previous run. */
+    let vmap = match Voxel_Map::load(cache_file, cache_key)
+    {
+      Ok(vmap) =>
+      {
+        log_info!("Loaded cached voxel map %s", cache_file);
+        vmap
+      }
+      Err(err) =>
+      {
+        log_info!("Not using voxel cache: %s", err);
+
+        let start_time = extra::time::precise_time_s();
+        let vmap = do Voxel_Map::new_with_progress(bmap.tris, voxel_size) |done, total|
+        {
+          events.try_send(Voxelizing(done, total));
+          !cancel.peek()
+        };
+        let time = extra::time::precise_time_s() - start_time;
+        log_info!("Voxelization took %f seconds", time);
+        if vmap.is_err()
+        { return Err(vmap.unwrap_err()); }
+        let vmap = vmap.unwrap();
+
+        match vmap.save(cache_file, cache_key)
+        {
+          Ok(()) => { log_info!("Cached voxel map to %s", cache_file); }
+          Err(err) => { log_error!("Failed to cache voxel map: %s", err); }
+        }
+
+        vmap
+      }
+    };
+
+    Ok((bmap, vmap))
   }
 }
 
@@ -67,8 +160,15 @@ impl State for Game
   fn get_key(&self) -> &str
   { &"game" }
 
-  fn update(&mut self, _delta: f32) -> bool /* dt is in terms of seconds. */
-  { false }
+  fn update(&mut self, delta: f32) -> bool /* dt is in terms of seconds. */
+  {
+    self.voxel_map.update(delta);
+    self.nav.update(self.voxel_map);
+    for prop in self.props.iter()
+    { prop.update(delta); }
+
+    false
+  }
   fn render(&mut self) -> bool
   { false }
 }
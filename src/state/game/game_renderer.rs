@@ -8,17 +8,29 @@
     Description:
       A client-only state that depends on
       the shared game state. This state owns the
-      camera and uses it to render the map data.
+      camera and uses it to render the map data
+      and the actors.
 */
 
+use std::{ f32, i32 };
+use extra;
 use super::{ State, Game };
 use gl;
 use ui;
 use math;
+use voxel;
+use res;
+use state;
 use self::map_renderer::Map_Renderer;
+use self::prop_renderer::Prop_Renderer;
+use self::item_renderer::Item_Renderer;
+use self::actor_renderer::Actor_Renderer;
 use util::Log;
 
 mod map_renderer;
+mod prop_renderer;
+mod item_renderer;
+mod actor_renderer;
 
 #[path = "../../gl/check.rs"]
 mod check;
@@ -27,13 +39,19 @@ mod check;
 #[path = "../../util/log_macros.rs"]
 mod log_macros;
 
+static Font_File: &'static str = "data/fonts/test.ttf";
+static Font_Size: i32 = 30;
+
 pub struct Game_Renderer
 {
   game: @mut Game,
   camera: @mut gl::Camera,
   map_renderer: @mut Map_Renderer,
+  prop_renderer: @mut Prop_Renderer,
+  item_renderer: @mut Item_Renderer,
+  actor_renderer: @mut Actor_Renderer,
 
-  fps_font: ui::Font,
+  fps_font: @ui::Font,
 }
 
 impl Game_Renderer
@@ -45,13 +63,172 @@ impl Game_Renderer
       game: game,
       camera: gl::Camera::get_active(),
       map_renderer: Map_Renderer::new(game.voxel_map),
+      prop_renderer: Prop_Renderer::new(game),
+      item_renderer: Item_Renderer::new(game),
+      actor_renderer: Actor_Renderer::new(),
 
-      fps_font: ui::Font::new("data/fonts/test.ttf", 30),
+      fps_font: res::Manager::get().get_font(Font_File, Font_Size),
     };
 
+    /* Console functions. */
+    do state::Director::push_deferred ||
+    {
+      state::Console::get().add_function(~"carve", |_, radius| -> (bool, ~str)
+      {
+        let radius = match f32::from_str(radius)
+        {
+          Some(x) => { x },
+          None => { return (false, ~"\\2Error: \\1Invalid radius (use a floating point number)"); }
+        };
+
+        /* Blow a hole around the camera, through the world and any props. */
+        let center = gr.camera.position;
+        let mut islands = gr.game.voxel_map.carve(center, radius);
+        for prop in gr.game.props.iter()
+        { islands += prop.carve(center, radius); }
+        (true, fmt!("Carved; %u islands detached", islands))
+      });
+      state::Console::get().add_function(~"nav_path", |_, params| -> (bool, ~str)
+      {
+        let coords: ~[Option<f32>] = params.word_iter().map(|p| f32::from_str(p)).collect();
+        if coords.len() != 3 || coords.iter().any(|c| c.is_none())
+        { return (false, ~"\\2Error: \\1Usage: nav_path <x> <y> <z>"); }
+        let goal = math::Vec3f::new(coords[0].unwrap(), coords[1].unwrap(), coords[2].unwrap());
+
+        /* Route from wherever the camera is standing. */
+        match gr.game.nav.find_path(gr.game.voxel_map, &gr.camera.position, &goal)
+        {
+          Ok(path) =>
+          {
+            let mut length = 0.0;
+            for i in range(1, path.len())
+            { length += (path[i] - path[i - 1]).length(); }
+            (true, fmt!("Found path of %u cells (%f units)", path.len(), length as float))
+          }
+          Err(err) => { (false, ~"\\2Error: \\1" + err) }
+        }
+      });
+      state::Console::get().add_function(~"spawn_md5", |_, file| -> (bool, ~str)
+      {
+        /* Voxelized in the bind pose, standing where the camera is. */
+        let model = match res::Manager::get().get_md5_model(file)
+        {
+          Ok(model) => { model },
+          Err(err) => { return (false, ~"\\2Error: \\1" + err); }
+        };
+        let prop = voxel::Prop::new_from_model(file, &*model, gr.game.voxel_map.voxel_size, gr.camera.position);
+        res::Manager::get().release_md5_model(file);
+        match prop
+        {
+          Ok(prop) =>
+          {
+            let count = prop.map.voxels.len();
+            gr.game.props.push(@mut prop);
+            (true, fmt!("Spawned %s (%u voxels)", file, count))
+          }
+          Err(err) => { (false, ~"\\2Error: \\1" + err) }
+        }
+      });
+      state::Console::get().add_function(~"anim_state", |_, name| -> (bool, ~str)
+      {
+        match gr.actor_renderer.set_animation(name)
+        {
+          Ok(()) => { (true, ~"Animation state: \\5" + name + "\\1") },
+          Err(err) => { (false, ~"\\2Error: \\1" + err) }
+        }
+      });
+      state::Console::get().add_function(~"attach_md3", |_, params| -> (bool, ~str)
+      {
+        let words: ~[&str] = params.word_iter().collect();
+        if words.len() != 2
+        { return (false, ~"\\2Error: \\1Usage: attach_md3 <file> <joint>"); }
+        let (file, joint) = (words[0], words[1]);
+
+        match gr.actor_renderer.attach_md3(file, joint)
+        {
+          Ok(()) => { (true, fmt!("Attached \\5%s\\1 to \\5%s\\1", file, joint)) }
+          Err(err) => { (false, ~"\\2Error: \\1" + err) }
+        }
+      });
+      state::Console::get().add_function(~"spawn_instance", |_, _| -> (bool, ~str)
+      {
+        /* Standing where the camera is, in the same state as the first. */
+        match gr.actor_renderer.spawn_instance(gr.camera.position)
+        {
+          Ok(count) => { (true, fmt!("Spawned instance %u", count)) }
+          Err(err) => { (false, ~"\\2Error: \\1" + err) }
+        }
+      });
+      state::Console::get().add_function(~"map_memory", |_, _| -> (bool, ~str)
+      {
+        let start_time = extra::time::precise_time_s();
+        let compressed = voxel::Compressed_Map::new(gr.game.voxel_map);
+        let time = extra::time::precise_time_s() - start_time;
+
+        let dense = gr.game.voxel_map.memory_usage();
+        let packed = compressed.memory_usage();
+        (true, fmt!("Dense: %u KiB; compressed: %u KiB (%u materials, %u runs) in %f seconds; %f%%",
+                    dense / 1024, packed / 1024, compressed.palette.len(), compressed.runs.len(),
+                    time, (packed as float / dense as float) * 100.0))
+      });
+      state::Console::get().add_function(~"export_vox", |_, params| -> (bool, ~str)
+      {
+        let params: ~[&str] = params.word_iter().collect();
+        if params.len() != 2
+        { return (false, ~"\\2Error: \\1Usage: export_vox <file> <radius>"); }
+        let radius = match i32::from_str(params[1])
+        {
+          Some(x) if x > 0 => { x },
+          _ => { return (false, ~"\\2Error: \\1Invalid radius (use a positive integer)"); }
+        };
+
+        /* Export the cube of cells around the camera. */
+        let cell = camera_cell(gr.game.voxel_map);
+        let extent = math::Vec3i::new(radius, radius, radius);
+        match gr.game.voxel_map.export_vox(params[0], &(cell - extent), &(cell + extent))
+        {
+          Ok(count) => { (true, fmt!("Exported %u voxels to %s", count, params[0])) }
+          Err(err) => { (false, ~"\\2Error: \\1" + err) }
+        }
+      });
+      state::Console::get().add_function(~"import_vox", |_, file| -> (bool, ~str)
+      {
+        /* Pasted into the world, centered on the camera. */
+        match voxel::Map::import_vox(file, gr.game.voxel_map.voxel_size)
+        {
+          Ok(model) =>
+          {
+            let count = gr.game.voxel_map.paste(&model, &camera_cell(gr.game.voxel_map));
+            (true, fmt!("Imported %u voxels from %s", count, file))
+          }
+          Err(err) => { (false, ~"\\2Error: \\1" + err) }
+        }
+      });
+      state::Console::get().add_function(~"spawn_vox", |_, file| -> (bool, ~str)
+      {
+        match voxel::Map::import_vox(file, gr.game.voxel_map.voxel_size)
+        {
+          Ok(model) =>
+          {
+            let mut model = model;
+            model.anchor_base();
+            let count = model.voxels.len();
+            gr.game.props.push(@mut voxel::Prop::new(file, model, gr.camera.position));
+            (true, fmt!("Spawned %s (%u voxels)", file, count))
+          }
+          Err(err) => { (false, ~"\\2Error: \\1" + err) }
+        }
+      });
+    }
+
     gr
   }
+}
 
+/* The map cell the camera is in, which may be outside of the grid. */
+fn camera_cell(map: &voxel::Map) -> math::Vec3i
+{
+  map.world_to_cell(&gl::Camera::get_active().position)
 }
 
 impl State for Game_Renderer
@@ -61,12 +238,19 @@ impl State for Game_Renderer
     log_debug!("Loading game renderer state");
 
     (self.map_renderer as @mut State).load();
+    self.prop_renderer.load();
+    self.item_renderer.load();
+    self.actor_renderer.load();
   }
 
   fn unload(&mut self)
   {
     log_debug!("Unloading game renderer state");
     (self.map_renderer as @mut State).unload();
+    self.prop_renderer.unload();
+    self.item_renderer.unload();
+    self.actor_renderer.unload();
+    res::Manager::get().release_font(Font_File, Font_Size);
   }
 
   fn get_key(&self) -> &str
@@ -76,6 +260,9 @@ impl State for Game_Renderer
   {
     self.camera.update(delta);
     self.map_renderer.update(delta);
+    self.prop_renderer.update();
+    self.item_renderer.update(delta);
+    self.actor_renderer.update(delta);
 
     false
   }
@@ -83,14 +270,17 @@ impl State for Game_Renderer
   fn render(&mut self) -> bool
   {
     (self.map_renderer as @mut State).render();
+    self.prop_renderer.render();
+    self.item_renderer.render();
+    self.actor_renderer.render();
 
     let fps = self.camera.frame_rate;
 
     let ui_renderer = ui::Renderer::get();
     ui_renderer.begin();
     if self.camera.show_fps
-    { ui_renderer.render_font(fmt!("%d", fps as int), math::Vec2f::new(0.0, 0.0), &self.fps_font); }
-    ui_renderer.render_font(fmt!("%s", self.camera.position.to_str()), math::Vec2f::new(0.0, self.camera.window_size.y as f32 - 40.0), &self.fps_font);
+    { ui_renderer.render_font(fmt!("%d", fps as int), math::Vec2f::new(0.0, 0.0), &*self.fps_font); }
+    ui_renderer.render_font(fmt!("%s", self.camera.position.to_str()), math::Vec2f::new(0.0, self.camera.window_size.y as f32 - 40.0), &*self.fps_font);
     ui_renderer.end();
 
     false
@@ -0,0 +1,173 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: state/game/item_renderer.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Renders the map's items (weapons,
+      armor, etc.) with their stock Q3 MD3
+      models, at the positions given by the
+      map's entities. As in Q3, items spin
+      and bob in place. Items whose models
+      aren't in the data directory are
+      skipped.
+*/
+
+use std::f32;
+use math;
+use md3;
+use res;
+use state::Game;
+use util::Log;
+
+#[macro_escape]
+#[path = "../../util/log_macros.rs"]
+mod log_macros;
+
+/* Entity class names and the models used for them. */
+static Item_Models: [(&'static str, &'static str), ..20] =
+[
+  ("weapon_shotgun", "models/weapons2/shotgun/shotgun.md3"),
+  ("weapon_machinegun", "models/weapons2/machinegun/machinegun.md3"),
+  ("weapon_grenadelauncher", "models/weapons2/grenadel/grenadel.md3"),
+  ("weapon_rocketlauncher", "models/weapons2/rocketl/rocketl.md3"),
+  ("weapon_lightning", "models/weapons2/lightning/lightning.md3"),
+  ("weapon_railgun", "models/weapons2/railgun/railgun.md3"),
+  ("weapon_plasmagun", "models/weapons2/plasma/plasma.md3"),
+  ("weapon_bfg", "models/weapons2/bfg/bfg.md3"),
+  ("ammo_bullets", "models/powerups/ammo/machinegunam.md3"),
+  ("ammo_shells", "models/powerups/ammo/shotgunam.md3"),
+  ("ammo_rockets", "models/powerups/ammo/rocketam.md3"),
+  ("ammo_cells", "models/powerups/ammo/plasmaam.md3"),
+  ("ammo_slugs", "models/powerups/ammo/railgunam.md3"),
+  ("item_armor_shard", "models/powerups/armor/shard.md3"),
+  ("item_armor_combat", "models/powerups/armor/armor_yel.md3"),
+  ("item_armor_body", "models/powerups/armor/armor_red.md3"),
+  ("item_health", "models/powerups/health/medium_cross.md3"),
+  ("item_health_large", "models/powerups/health/large_cross.md3"),
+  ("item_health_mega", "models/powerups/health/mega_cross.md3"),
+  ("item_quad", "models/powerups/instant/quad.md3"),
+];
+
+static Data_Directory: &'static str = "data/";
+
+/* Degrees per second. */
+static Spin_Speed: f32 = 90.0;
+
+/* Height (in Q3 units) and speed of bobbing. */
+static Bob_Height: f32 = 4.0;
+static Bob_Speed: f32 = 2.0;
+
+struct Item
+{
+  position: math::Vec3f, /* In Q3 units. */
+  model: uint,
+}
+
+pub struct Item_Renderer
+{
+  game: @mut Game,
+
+  /* Loaded models and their paths. */
+  models: ~[(~str, md3::Model_Renderer)],
+  items: ~[Item],
+
+  time: f32,
+}
+
+impl Item_Renderer
+{
+  pub fn new(game: @mut Game) -> @mut Item_Renderer
+  {
+    @mut Item_Renderer
+    {
+      game: game,
+
+      models: ~[],
+      items: ~[],
+
+      time: 0.0,
+    }
+  }
+
+  pub fn load(&mut self)
+  {
+    let mut missing = ~[];
+    for entity in self.game.bsp_map.entities.iter()
+    {
+      let file = match Item_Models.iter().find(|&&(class, _)| entity.class_name.as_slice() == class)
+      {
+        Some(&(_, file)) => { file },
+        None => { loop; }
+      };
+      let origin = match entity.get_origin()
+      {
+        Some(o) => { o },
+        None => { loop; }
+      };
+      if missing.iter().any(|m| *m == file)
+      { loop; }
+
+      /* Each model is only loaded once. */
+      let index = match self.models.iter().position(|&(ref path, _)| path.as_slice() == file)
+      {
+        Some(i) => { i },
+        None =>
+        {
+          match res::Manager::get().get_md3_model(Data_Directory + file)
+          {
+            Ok(model) =>
+            {
+              self.models.push((file.to_owned(), md3::Model_Renderer::new(model)));
+              self.models.len() - 1
+            }
+            Err(err) =>
+            {
+              log_info!("Skipping %s items: %s", entity.class_name, err);
+              missing.push(file);
+              loop;
+            }
+          }
+        }
+      };
+      self.items.push(Item { position: origin, model: index });
+    }
+    log_debug!("Placed %u items with %u models", self.items.len(), self.models.len());
+  }
+
+  /* Gives the models back to the resource manager. */
+  pub fn unload(&mut self)
+  {
+    for model in self.models.mut_iter()
+    {
+      let (ref file, ref mut renderer) = *model;
+      renderer.release();
+      res::Manager::get().release_md3_model(Data_Directory + *file);
+    }
+    self.models.clear();
+    self.items.clear();
+  }
+
+  pub fn update(&mut self, delta: f32) /* dt is in terms of seconds. */
+  { self.time += delta; }
+
+  pub fn render(&mut self)
+  {
+    let spin = (self.time * Spin_Speed) % 360.0;
+    let bob = (self.time * Bob_Speed * f32::consts::pi).sin() * Bob_Height;
+    for item in self.items.iter()
+    {
+      let position = self.game.bsp_map.to_local(&(item.position + math::Vec3f::new(0.0, 0.0, bob)));
+
+      /* Spin about Q3's up, then rotate Z up into Y up. */
+      let world = math::Mat4x4::new_scale(1.0 / 32.0, 1.0 / 32.0, 1.0 / 32.0) *
+                  math::Mat4x4::new_rotation_z(spin) *
+                  math::Mat4x4::new_rotation_x(90.0) *
+                  math::Mat4x4::new_translation(position.x, position.y, position.z);
+      let (_, ref renderer) = self.models[item.model];
+      renderer.render(&world, 0.0);
+    }
+  }
+}
@@ -9,13 +9,22 @@
       A client-only state that depends on
       the shared game state. This state is
       used only to render the voxel map.
+
+      The map is either drawn as greedy
+      meshed chunks or, for comparison, as
+      one instanced cube per visible voxel.
+      Either way, only the chunks which the
+      map reports as dirty are re-uploaded.
+      Distant meshed chunks are drawn from
+      the map's coarser levels of detail.
 */
 
-use std::{ vec, ptr, sys, cast, cell };
+use std::{ vec, sys, cmp, f32 };
 use extra;
 use state::State;
 use gl2 = opengles::gl2;
 use gl;
+use res;
 use math;
 use voxel;
 use state;
@@ -24,25 +33,58 @@ use util::Log;
 #[path = "../../gl/check.rs"]
 mod check;
 
+static Voxel_Vert_Shader: &'static str = "data/shaders/voxel.vert";
+static Voxel_Frag_Shader: &'static str = "data/shaders/voxel.frag";
+static Mesh_Vert_Shader: &'static str = "data/shaders/color.vert";
+static Mesh_Frag_Shader: &'static str = "data/shaders/color.frag";
+
 #[macro_escape]
 #[path = "../../util/log_macros.rs"]
 mod log_macros;
 
+/* A greedy mesh of one chunk at one level of detail. */
+struct Chunk_Mesh
+{
+  vbo: gl2::GLuint,
+  count: i32,
+  capacity: uint, /* The buffer only grows. */
+}
+
+/* The uploaded data of a single chunk. */
+struct Chunk
+{
+  /* The full detail mesh, followed by one for each level of detail. */
+  meshes: ~[Chunk_Mesh],
+
+  /* Visible voxel indices, in the shared index buffer.
+   * Each chunk has room for every one of its cells. */
+  index_offset: uint,
+  index_count: i32,
+
+  /* World space bounds, for culling. */
+  min: math::Vec3f,
+  max: math::Vec3f,
+}
+
 pub struct Map_Renderer
 {
   map: @mut voxel::Map,
 
+  /* Greedy meshed chunks, rather than instanced cubes. */
+  meshed: bool,
+  lod_distance: f32, /* Distance at which chunks drop to the first level of detail. */
+  chunk_vao: gl2::GLuint,
+  chunks: ~[Chunk],
+
   vao: gl2::GLuint,
   vox_vbo: gl2::GLuint,
   offset_tex_vbo: gl2::GLuint,
   offset_tex: gl2::GLuint,
-  ibos: ~[gl2::GLuint],
-  curr_ibo: u32,
-  visible_voxels: Option<~[u32]>,
-  prev_visible_voxel_count: u32,
-
-  /* states, visible */
-  map_stream: extra::comm::DuplexStream<(cell::Cell<~[u32]>, cell::Cell<~[u32]>), (~[u32], ~[u32])>,
+  occlusion_tex_vbo: gl2::GLuint,
+  occlusion_tex: gl2::GLuint,
+  voxel_capacity: uint, /* Voxels the texture buffers were allocated for. */
+  index_vbo: gl2::GLuint,
+  debris_vbo: gl2::GLuint,
 
   wireframe: bool,
 
@@ -51,36 +93,48 @@ pub struct Map_Renderer
   world_loc: gl2::GLint,
   voxel_size_loc: gl2::GLint,
   offsets_loc: gl2::GLint,
+  occlusion_loc: gl2::GLint,
+
+  mesh_shader: @mut gl::Shader,
+  mesh_proj_loc: gl2::GLint,
+  mesh_world_loc: gl2::GLint,
 }
 
 impl Map_Renderer
 {
   pub fn new(map: @mut voxel::Map) -> @mut Map_Renderer
   {
-    let (local_stream, _) = extra::comm::DuplexStream();
-
     let mr = @mut Map_Renderer
     {
       map: map,
 
+      meshed: true,
+      lod_distance: 20.0,
+      chunk_vao: 0,
+      chunks: ~[],
+
       vao: 0,
       vox_vbo: 0,
       offset_tex_vbo: 0,
       offset_tex: 0,
-      ibos: vec::from_elem(2, 2u32),
-      curr_ibo: 0,
-      visible_voxels: Some(vec::from_elem((map.resolution * map.resolution * map.resolution) as uint, 0u32)),
-      prev_visible_voxel_count: 0,
-
-      map_stream: local_stream,
+      occlusion_tex_vbo: 0,
+      occlusion_tex: 0,
+      voxel_capacity: 0,
+      index_vbo: 0,
+      debris_vbo: 0,
 
       wireframe: false,
 
-      shader: gl::Shader_Builder::new_with_files("data/shaders/voxel.vert", "data/shaders/voxel.frag"),
+      shader: res::Manager::get().get_shader(Voxel_Vert_Shader, Voxel_Frag_Shader),
       proj_loc: 0,
       world_loc: 0,
       voxel_size_loc: 0,
       offsets_loc: 0,
+      occlusion_loc: 0,
+
+      mesh_shader: res::Manager::get().get_shader(Mesh_Vert_Shader, Mesh_Frag_Shader),
+      mesh_proj_loc: 0,
+      mesh_world_loc: 0,
     };
 
     /* Single voxel that will be instance-rendered. */
@@ -110,26 +164,25 @@ impl Map_Renderer
     assert!(names.len() == 1);
     mr.vao = names[0];
 
-    let names = check!(gl2::gen_buffers(4));
-    assert!(names.len() == 4);
+    let names = check!(gl2::gen_buffers(5));
+    assert!(names.len() == 5);
     mr.vox_vbo = names[0];
     mr.offset_tex_vbo = names[1];
-    mr.ibos[0] = names[2];
-    mr.ibos[1] = names[3];
+    mr.occlusion_tex_vbo = names[2];
+    mr.index_vbo = names[3];
+    mr.debris_vbo = names[4];
 
     check!(gl2::bind_vertex_array(mr.vao));
     check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, mr.vox_vbo));
     check!(gl2::buffer_data(gl2::ARRAY_BUFFER, voxel, gl2::STATIC_DRAW));
 
-    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, mr.ibos[0]));
-    let ibo_buf = vec::from_elem((mr.map.resolution * mr.map.resolution * mr.map.resolution) as uint, 0);
-    check!(gl2::buffer_data(gl2::ARRAY_BUFFER, ibo_buf, gl2::DYNAMIC_DRAW));
-
-    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, mr.ibos[1]));
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, mr.index_vbo));
+    let ibo_buf = vec::from_elem((mr.map.dimensions.x * mr.map.dimensions.y * mr.map.dimensions.z) as uint, 0u32);
     check!(gl2::buffer_data(gl2::ARRAY_BUFFER, ibo_buf, gl2::DYNAMIC_DRAW));
 
+    /* Voxel colors may change, so this is updated in place. */
     check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, mr.offset_tex_vbo));
-    check!(gl2::buffer_data(gl2::TEXTURE_BUFFER, mr.map.voxels, gl2::STATIC_DRAW));
+    check!(gl2::buffer_data(gl2::TEXTURE_BUFFER, mr.map.voxels, gl2::DYNAMIC_DRAW));
 
     let name = check!(gl2::gen_textures(1));
     assert!(name.len() == 1);
@@ -137,6 +190,22 @@ impl Map_Renderer
     check!(gl2::bind_texture(gl2::TEXTURE_BUFFER, mr.offset_tex));
     check!(gl2::tex_buffer(gl2::TEXTURE_BUFFER, 0x8815 /* RGB32F */, mr.offset_tex_vbo));
 
+    check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, mr.occlusion_tex_vbo));
+    check!(gl2::buffer_data(gl2::TEXTURE_BUFFER, mr.map.occlusion, gl2::DYNAMIC_DRAW));
+    mr.voxel_capacity = mr.map.voxels.len();
+
+    let name = check!(gl2::gen_textures(1));
+    assert!(name.len() == 1);
+    mr.occlusion_tex = name[0];
+    check!(gl2::bind_texture(gl2::TEXTURE_BUFFER, mr.occlusion_tex));
+    check!(gl2::tex_buffer(gl2::TEXTURE_BUFFER, 0x8229 /* R8 */, mr.occlusion_tex_vbo));
+    check!(gl2::bind_texture(gl2::TEXTURE_BUFFER, 0));
+
+    let names = check!(gl2::gen_vertex_arrays(1));
+    assert!(names.len() == 1);
+    mr.chunk_vao = names[0];
+    mr.create_chunks();
+
     /* Console functions. */
     do state::Director::push_deferred ||
     {
@@ -157,92 +226,244 @@ impl Map_Renderer
         else
         { Some(error) }
       });
+      state::Console::get().add_accessor("map.meshed", |_|
+      { mr.meshed.to_str() });
+      state::Console::get().add_mutator("map.meshed", |p, x|
+      {
+        let mut error = ~"";
+        if x == "true"
+        { mr.meshed = true; }
+        else if x == "false"
+        { mr.meshed = false; }
+        else
+        { error = fmt!("Invalid value for %s (use 'true' or 'false')", p); }
+
+        if error.len() == 0
+        { None }
+        else
+        { Some(error) }
+      });
+      state::Console::get().add_accessor("map.lod_distance", |_|
+      { mr.lod_distance.to_str() });
+      state::Console::get().add_mutator("map.lod_distance", |p, x|
+      {
+        match f32::from_str(x)
+        {
+          Some(dist) if dist > 0.0 => { mr.lod_distance = dist; None }
+          _ => { Some(fmt!("Invalid value for %s (use a positive floating point number)", p)) }
+        }
+      });
     }
 
     mr
   }
 
-  #[fixed_stack_segment]
-  pub fn update_visibility(&mut self)
+  /* Lays out every chunk's slot in the index buffer and
+   * uploads everything once. */
+  fn create_chunks(&mut self)
   {
-    self.prev_visible_voxel_count = self.visible_voxels.get_ref().len() as u32;
-
-    let cam = gl::Camera::get_active();
-    let dist = (cam.near_far.y  / self.map.voxel_size) as i32; /* How far the camera can see. */
-    let res = self.map.resolution as f32;
-    let pos = math::Vec3f::new(cam.position.x / self.map.voxel_size,
-                               cam.position.y / self.map.voxel_size,
-                               cam.position.z / self.map.voxel_size)
-                               + math::Vec3f::new(res / 2.0, res / 2.0, res / 2.0);
-    let start = math::Vec3i::new
-    (
-      (pos.x - dist as f32).clamp(&0.0, &(res - 1.0)) as i32,
-      (pos.y - dist as f32).clamp(&0.0, &(res - 1.0)) as i32,
-      (pos.z - dist as f32).clamp(&0.0, &(res - 1.0)) as i32
-    );
-    let end = math::Vec3i::new
-    (
-      (pos.x + dist as f32).clamp(&0.0, &(res - 1.0)) as i32,
-      (pos.y + dist as f32).clamp(&0.0, &(res - 1.0)) as i32,
-      (pos.z + dist as f32).clamp(&0.0, &(res - 1.0)) as i32
-    );
-
-    self.visible_voxels.get_mut_ref().clear();
-
-    /* Updating visible voxels is an expensive task. To remedy this,
-     * the work is done on a background thread that has a shared OpenGL
-     * context. While that work is being done, the map renderer will
-     * not have visible voxels or voxel states, since they're moved
-     * to the task. Once the task is finished, however, the fields are
-     * sent back. */
-    let (local_stream, remote_stream) = extra::comm::DuplexStream();
-    self.map_stream = local_stream;
-
-    /* Send out the voxel states and visible voxels. */
-    self.map_stream.send((cell::Cell::new(self.map.states.take_unwrap()), cell::Cell::new(self.visible_voxels.take_unwrap())));
-
-    /* Start the new background task of culling far-away voxels. */
-    let resolution = self.map.resolution;
-    let ibo = self.ibos[self.curr_ibo];
-    do gl::Worker::new_task
+    let dims = self.map.dimensions;
+    let counts = voxel::mesher::chunk_counts(&dims);
+    let total = (counts.x * counts.y * counts.z) as uint;
+    let levels = self.map.lods.len() + 1;
+    let names = check!(gl2::gen_buffers((total * levels) as i32));
+    assert!(names.len() == total * levels);
+
+    let mut offset = 0;
+    self.chunks = vec::with_capacity(total);
+    for i in range(0, total)
     {
-      let (cell_states, cell_visible_voxels) = remote_stream.recv();
-      let states = cell_states.take();
-      let mut visible_voxels = cell_visible_voxels.take();
+      let coords = chunk_coords(&counts, i);
+      let (start, end) = chunk_cells(&dims, &coords);
+      let (min, max) = voxel::mesher::chunk_bounds(&dims, self.map.voxel_size, &coords);
+      self.chunks.push(Chunk
+      {
+        meshes: do vec::from_fn(levels) |level|
+        { Chunk_Mesh { vbo: names[(i * levels) + level], count: 0, capacity: 0 } },
+
+        index_offset: offset,
+        index_count: 0,
 
-      for z in range(start.z, end.z)
+        min: min,
+        max: max,
+      });
+      offset += ((end.x - start.x) * (end.y - start.y) * (end.z - start.z)) as uint;
+    }
+
+    let mut faces = 0;
+    for i in range(0, total)
+    { faces += self.update_chunk(i); }
+    self.map.take_dirty_chunks(); /* Everything is fresh. */
+    log_debug!("Meshed %ud chunks into %ud faces", total, faces);
+  }
+
+  /* Rebuilds a chunk's visible voxel indices and meshes, and uploads
+   * them in place. Returns the number of full detail faces. */
+  fn update_chunk(&mut self, index: uint) -> uint
+  {
+    let dims = self.map.dimensions;
+    let counts = voxel::mesher::chunk_counts(&dims);
+    let coords = chunk_coords(&counts, index);
+    let (start, end) = chunk_cells(&dims, &coords);
+    let states = &self.map.states;
+    let chunk = &mut self.chunks[index];
+
+    let mut visible = ~[];
+    for z in range(start.z, end.z)
+    {
+      for y in range(start.y, end.y)
       {
-        for y in range(start.y, end.y)
+        for x in range(start.x, end.x)
         {
-          for x in range(start.x, end.x)
-          {
-            let index = (z * ((resolution * resolution) as i32)) + (y * (resolution as i32)) + x;
-            if (states[index] & voxel::Visible) != 0
-            { visible_voxels.push(states[index] & !voxel::Visible); }
-          }
+          let state = states[(z * dims.x * dims.y) + (y * dims.x) + x];
+          if (state & voxel::Visible) != 0
+          { visible.push(state & voxel::Index_Mask); }
         }
       }
+    }
+    chunk.index_count = visible.len() as i32;
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.index_vbo));
+    if visible.len() > 0
+    {
+      check!(gl2::buffer_sub_data(gl2::ARRAY_BUFFER,
+                                  (chunk.index_offset * sys::size_of::<u32>()) as i64, visible));
+    }
 
-      /* Upload the data in an unsynchronized manner. This will prevent
-       * OpenGL from trying to synchronize the mapping, which means it
-       * can be mapped without effectively without the need to glFinish.
-       * This is safe since an alternate set is being renderered while
-       * this is being updated. */
-      check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, ibo));
-      unsafe
-      {
-        let size = visible_voxels.len() * sys::size_of::<u32>();
-        let mem = check!(gl2::map_buffer_range(gl2::ARRAY_BUFFER, 0, size as i64, 2 | 32));
-        ptr::copy_nonoverlapping_memory(cast::transmute(mem), vec::raw::to_ptr(visible_voxels), size);
-        check!(gl2::unmap_buffer(gl2::ARRAY_BUFFER));
-      }
+    let verts = voxel::mesher::mesh_chunk(*states, self.map.voxels, self.map.occlusion, dims, self.map.voxel_size, coords);
+    let faces = verts.len() / 6;
+    upload_mesh(&mut chunk.meshes[0], verts);
+
+    /* Coarser levels cover the same region with fewer, larger cells. */
+    let corner = voxel::mesher::grid_corner(&dims, self.map.voxel_size);
+    for (level, lod) in self.map.lods.iter().enumerate()
+    {
+      let s = lod.scale;
+      let verts = voxel::mesher::mesh_region(lod.states, lod.voxels, [], lod.dimensions,
+                                             self.map.voxel_size * (s as f32), corner,
+                                             math::Vec3i::new(start.x / s, start.y / s, start.z / s),
+                                             math::Vec3i::new((end.x + s - 1) / s,
+                                                              (end.y + s - 1) / s,
+                                                              (end.z + s - 1) / s));
+      upload_mesh(&mut chunk.meshes[level + 1], verts);
+    }
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, 0));
+
+    faces
+  }
+
+  /* Uploads the modified voxels, and their occlusion, in contiguous runs. */
+  fn update_voxels(&mut self, dirty: ~[u32])
+  {
+    /* Pasted content adds voxels, so the buffers may need to grow. */
+    if self.map.voxels.len() > self.voxel_capacity
+    {
+      check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, self.offset_tex_vbo));
+      check!(gl2::buffer_data(gl2::TEXTURE_BUFFER, self.map.voxels, gl2::DYNAMIC_DRAW));
+      check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, self.occlusion_tex_vbo));
+      check!(gl2::buffer_data(gl2::TEXTURE_BUFFER, self.map.occlusion, gl2::DYNAMIC_DRAW));
+      check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, 0));
+      self.voxel_capacity = self.map.voxels.len();
+      return;
+    }
+    if dirty.len() == 0
+    { return; }
+
+    let mut dirty = dirty;
+    extra::sort::quick_sort3(dirty);
+    let mut first = 0;
+    while first < dirty.len()
+    {
+      let mut last = first;
+      while last + 1 < dirty.len() && dirty[last + 1] == dirty[last] + 1
+      { last += 1; }
+
+      let (start, end) = (dirty[first] as uint, dirty[last] as uint + 1);
+      check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, self.offset_tex_vbo));
+      check!(gl2::buffer_sub_data(gl2::TEXTURE_BUFFER, (start * sys::size_of::<voxel::Vertex>()) as i64,
+                                  self.map.voxels.slice(start, end)));
+      check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, self.occlusion_tex_vbo));
+      check!(gl2::buffer_sub_data(gl2::TEXTURE_BUFFER, (start * 6) as i64,
+                                  self.map.occlusion.slice(start * 6, end * 6)));
+      first = last + 1;
+    }
+    check!(gl2::bind_buffer(gl2::TEXTURE_BUFFER, 0));
+  }
+
+  fn render_chunks(&mut self)
+  {
+    let camera = gl::Camera::get_active();
+    let frustum = camera.get_frustum();
+    let last_level = self.map.lods.len() as f32;
+
+    self.mesh_shader.bind();
+    self.mesh_shader.update_uniform_mat(self.mesh_proj_loc, &camera.projection);
+    self.mesh_shader.update_uniform_mat(self.mesh_world_loc, &camera.view);
 
-      /* Send the member data back. */
-      remote_stream.send((states, visible_voxels));
+    check!(gl2::bind_vertex_array(self.chunk_vao));
+    check!(gl2::enable_vertex_attrib_array(0));
+    check!(gl2::enable_vertex_attrib_array(1));
+    for chunk in self.chunks.iter()
+    {
+      if !frustum.intersects_box(&chunk.min, &chunk.max)
+      { loop; }
+
+      /* Each doubling of the distance drops a level of detail. */
+      let center = (chunk.min + chunk.max) * 0.5;
+      let dist = (center - camera.position).length() / self.lod_distance;
+      let level = if dist < 1.0
+      { 0.0 }
+      else
+      { cmp::min(dist.log2().floor() + 1.0, last_level) };
+
+      let mesh = &chunk.meshes[level as uint];
+      if mesh.count == 0
+      { loop; }
+
+      check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, mesh.vbo));
+      check!(gl2::vertex_attrib_pointer_f32(0, 3, false, sys::size_of::<voxel::Vertex>() as i32, 0));
+      check!(gl2::vertex_attrib_pointer_f32(1, 3, false, sys::size_of::<voxel::Vertex>() as i32,
+                                             sys::size_of::<math::Vec3f>() as u32));
+      check!(gl2::draw_arrays(gl2::TRIANGLES, 0, mesh.count));
+    }
+    check!(gl2::disable_vertex_attrib_array(0));
+    check!(gl2::disable_vertex_attrib_array(1));
+    check!(gl2::bind_vertex_array(0));
+  }
 
-      false /* Don't kill the GL worker. */
+  /* Each chunk's slot of the index buffer feeds the instances. */
+  fn render_instanced(&mut self)
+  {
+    let frustum = gl::Camera::get_active().get_frustum();
+
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.index_vbo));
+    for chunk in self.chunks.iter()
+    {
+      if chunk.index_count == 0 || !frustum.intersects_box(&chunk.min, &chunk.max)
+      { loop; }
+
+      check!(gl2::vertex_attrib_i_pointer_i32(1, 1, 0, (chunk.index_offset * sys::size_of::<u32>()) as u32));
+      check!(gl2::draw_arrays_instanced(gl2::TRIANGLE_STRIP, 0, 24, chunk.index_count));
     }
   }
+
+  /* Debris voxels still live in the offset texture; each body
+   * is drawn with its own index list and world offset. */
+  fn render_debris(&mut self)
+  {
+    let camera = gl::Camera::get_active();
+
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.debris_vbo));
+    check!(gl2::vertex_attrib_i_pointer_i32(1, 1, 0, 0));
+    for debris in self.map.debris.iter()
+    {
+      let world = math::Mat4x4::new_translation(debris.offset.x, debris.offset.y, debris.offset.z) * camera.view;
+      self.shader.update_uniform_mat(self.world_loc, &world);
+
+      check!(gl2::buffer_data(gl2::ARRAY_BUFFER, debris.voxels, gl2::STREAM_DRAW));
+      check!(gl2::draw_arrays_instanced(gl2::TRIANGLE_STRIP, 0, 24, debris.voxels.len() as i32));
+    }
+    self.shader.update_uniform_mat(self.world_loc, &camera.view);
+  }
 }
 
 impl State for Map_Renderer
@@ -256,22 +477,21 @@ impl State for Map_Renderer
     self.world_loc = self.shader.get_uniform_location("world");
     self.voxel_size_loc = self.shader.get_uniform_location("voxel_size");
     self.offsets_loc = self.shader.get_uniform_location("offsets");
+    self.occlusion_loc = self.shader.get_uniform_location("occlusion");
 
     self.shader.update_uniform_i32(self.offsets_loc, 0);
+    self.shader.update_uniform_i32(self.occlusion_loc, 1);
 
-    self.update_visibility();
+    self.mesh_shader.bind();
+    self.mesh_proj_loc = self.mesh_shader.get_uniform_location("proj");
+    self.mesh_world_loc = self.mesh_shader.get_uniform_location("world");
   }
 
   fn unload(&mut self)
   {
     log_debug!("Unloading map renderer state");
-    
-    /* Since the background worker is doing its thing, we'll
-     * need to wait for it to finish so that it doesn't try
-     * to update us when we're dead. */
-    let (states, visible_voxels) = self.map_stream.recv();
-    self.map.states = Some(states);
-    self.visible_voxels = Some(visible_voxels);
+    res::Manager::get().release_shader(Voxel_Vert_Shader, Voxel_Frag_Shader);
+    res::Manager::get().release_shader(Mesh_Vert_Shader, Mesh_Frag_Shader);
   }
 
   fn get_key(&self) -> &str
@@ -279,34 +499,31 @@ impl State for Map_Renderer
 
   fn update(&mut self, _delta: f32) -> bool /* dt is in terms of seconds. */
   {
-    /* Check if there is data available between the background
-     * thread and us. The last thing it does is send back some
-     * member data that we'll need to put back in place before
-     * doing any more work. */
-    if !self.map_stream.peek()
-    { return false; }
-
-    /* Extract the new data. */
-    let (states, visible_voxels) = self.map_stream.recv();
-    self.map.states = Some(states);
-    self.visible_voxels = Some(visible_voxels);
-    
-    /* TODO: Work goes here. */
+    let dirty = self.map.take_dirty_chunks();
+    if dirty.len() > 0
+    {
+      let mut faces = 0;
+      for index in dirty.iter()
+      { faces += self.update_chunk(*index); }
+      log_debug!("Updated %ud dirty chunks (%ud faces)", dirty.len(), faces);
+    }
 
-    /* Swap the current IBO and begin updating the old one. */
-    if self.curr_ibo == 0
-    { self.curr_ibo = 1; }
-    else
-    { self.curr_ibo = 0; }
-    self.update_visibility();
+    let dirty = self.map.take_dirty_voxels();
+    self.update_voxels(dirty);
 
-    false      
+    false
   }
 
   fn render(&mut self) -> bool
   {
     let camera = gl::Camera::get_active();
 
+    if self.wireframe
+    { check!(gl2::polygon_mode(gl2::FRONT_AND_BACK, gl2::LINE)); }
+
+    if self.meshed
+    { self.render_chunks(); }
+
     self.shader.bind();
     self.shader.update_uniform_mat(self.proj_loc, &camera.projection);
     self.shader.update_uniform_mat(self.world_loc, &camera.view);
@@ -318,20 +535,18 @@ impl State for Map_Renderer
     check!(gl2::vertex_attrib_pointer_f32(0, 3, false, 0, 0));
     check!(gl2::enable_vertex_attrib_array(0));
 
-    if self.curr_ibo == 0
-    { check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.ibos[1])); }
-    else
-    { check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.ibos[0])); }
-    check!(gl2::vertex_attrib_i_pointer_i32(1, 1, 0, 0));
     check!(gl2::enable_vertex_attrib_array(1));
     check!(gl2::vertex_attrib_divisor(1, 1));
 
+    check!(gl2::active_texture(gl2::TEXTURE0 + 1));
+    check!(gl2::bind_texture(gl2::TEXTURE_BUFFER, self.occlusion_tex));
+    check!(gl2::active_texture(gl2::TEXTURE0));
     check!(gl2::bind_texture(gl2::TEXTURE_BUFFER, self.offset_tex));
 
-    if self.wireframe
-    { check!(gl2::polygon_mode(gl2::FRONT_AND_BACK, gl2::LINE)); }
+    if !self.meshed
+    { self.render_instanced(); }
 
-    check!(gl2::draw_arrays_instanced(gl2::TRIANGLE_STRIP, 0, 24, self.prev_visible_voxel_count as i32));
+    self.render_debris();
 
     if self.wireframe
     { check!(gl2::polygon_mode(gl2::FRONT_AND_BACK, gl2::FILL)); }
@@ -345,3 +560,36 @@ impl State for Map_Renderer
   }
 }
 
+/* Uploads the vertices into the mesh's buffer, reallocating
+ * only once it's been outgrown. */
+fn upload_mesh(mesh: &mut Chunk_Mesh, verts: ~[voxel::Vertex])
+{
+  mesh.count = verts.len() as i32;
+  check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, mesh.vbo));
+  if verts.len() > mesh.capacity
+  {
+    mesh.capacity = verts.len();
+    check!(gl2::buffer_data(gl2::ARRAY_BUFFER, verts, gl2::DYNAMIC_DRAW));
+  }
+  else if verts.len() > 0
+  { check!(gl2::buffer_sub_data(gl2::ARRAY_BUFFER, 0, verts)); }
+}
+
+/* Chunk coordinates from a chunk index. */
+fn chunk_coords(counts: &math::Vec3i, index: uint) -> math::Vec3i
+{
+  let index = index as i32;
+  math::Vec3i::new(index % counts.x, (index / counts.x) % counts.y, index / (counts.x * counts.y))
+}
+
+/* The first cell of a chunk and one past its last cell. */
+fn chunk_cells(dims: &math::Vec3i, coords: &math::Vec3i) -> (math::Vec3i, math::Vec3i)
+{
+  let start = math::Vec3i::new(coords.x * voxel::mesher::Chunk_Size,
+                               coords.y * voxel::mesher::Chunk_Size,
+                               coords.z * voxel::mesher::Chunk_Size);
+  let end = math::Vec3i::new(cmp::min(start.x + voxel::mesher::Chunk_Size, dims.x),
+                             cmp::min(start.y + voxel::mesher::Chunk_Size, dims.y),
+                             cmp::min(start.z + voxel::mesher::Chunk_Size, dims.z));
+  (start, end)
+}
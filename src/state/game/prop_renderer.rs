@@ -0,0 +1,205 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: state/game/prop_renderer.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      Renders the game's voxel props. Props
+      are small, so each is greedy meshed
+      as a whole, and re-meshed whenever
+      any part of it changes. Debris broken
+      off of props is drawn as plain cubes.
+*/
+
+use std::{ sys, managed, vec };
+use gl2 = opengles::gl2;
+use gl;
+use res;
+use math;
+use voxel;
+use state::Game;
+use util::Log;
+
+#[path = "../../gl/check.rs"]
+mod check;
+
+static Vert_Shader: &'static str = "data/shaders/color.vert";
+static Frag_Shader: &'static str = "data/shaders/color.frag";
+
+#[macro_escape]
+#[path = "../../util/log_macros.rs"]
+mod log_macros;
+
+/* Corners of each face of a unit cube, in the map's neighbour order. */
+static Cube_Faces: [((f32, f32, f32), (f32, f32, f32), (f32, f32, f32), (f32, f32, f32)), ..6] =
+[
+  ((1.0, -1.0, -1.0), (1.0, 1.0, -1.0), (1.0, 1.0, 1.0), (1.0, -1.0, 1.0)),
+  ((-1.0, -1.0, -1.0), (-1.0, -1.0, 1.0), (-1.0, 1.0, 1.0), (-1.0, 1.0, -1.0)),
+  ((-1.0, 1.0, -1.0), (-1.0, 1.0, 1.0), (1.0, 1.0, 1.0), (1.0, 1.0, -1.0)),
+  ((-1.0, -1.0, -1.0), (1.0, -1.0, -1.0), (1.0, -1.0, 1.0), (-1.0, -1.0, 1.0)),
+  ((-1.0, -1.0, 1.0), (1.0, -1.0, 1.0), (1.0, 1.0, 1.0), (-1.0, 1.0, 1.0)),
+  ((-1.0, -1.0, -1.0), (-1.0, 1.0, -1.0), (1.0, 1.0, -1.0), (1.0, -1.0, -1.0)),
+];
+
+struct Prop_Mesh
+{
+  prop: @mut voxel::Prop,
+  vbo: gl2::GLuint,
+  count: i32,
+}
+
+pub struct Prop_Renderer
+{
+  game: @mut Game,
+  meshes: ~[Prop_Mesh],
+
+  vao: gl2::GLuint,
+  debris_vbo: gl2::GLuint,
+  shader: @mut gl::Shader,
+  proj_loc: gl2::GLint,
+  world_loc: gl2::GLint,
+}
+
+impl Prop_Renderer
+{
+  pub fn new(game: @mut Game) -> @mut Prop_Renderer
+  {
+    let pr = @mut Prop_Renderer
+    {
+      game: game,
+      meshes: ~[],
+
+      vao: 0,
+      debris_vbo: 0,
+      shader: res::Manager::get().get_shader(Vert_Shader, Frag_Shader),
+      proj_loc: 0,
+      world_loc: 0,
+    };
+
+    let names = check!(gl2::gen_vertex_arrays(1));
+    assert!(names.len() == 1);
+    pr.vao = names[0];
+    let names = check!(gl2::gen_buffers(1));
+    assert!(names.len() == 1);
+    pr.debris_vbo = names[0];
+
+    pr
+  }
+
+  pub fn load(&mut self)
+  {
+    self.shader.bind();
+    self.proj_loc = self.shader.get_uniform_location("proj");
+    self.world_loc = self.shader.get_uniform_location("world");
+  }
+
+  pub fn unload(&mut self)
+  { res::Manager::get().release_shader(Vert_Shader, Frag_Shader); }
+
+  /* Picks up new props and re-meshes modified ones. */
+  pub fn update(&mut self)
+  {
+    for prop in self.game.props.iter()
+    {
+      if !self.meshes.iter().any(|mesh| managed::mut_ptr_eq(mesh.prop, *prop))
+      {
+        let names = check!(gl2::gen_buffers(1));
+        assert!(names.len() == 1);
+        self.meshes.push(Prop_Mesh { prop: *prop, vbo: names[0], count: 0 });
+        upload(self.meshes.mut_iter().last().unwrap());
+        log_debug!("Meshed prop %s", prop.name);
+      }
+    }
+
+    for mesh in self.meshes.mut_iter()
+    {
+      let chunks = mesh.prop.map.take_dirty_chunks();
+      let voxels = mesh.prop.map.take_dirty_voxels();
+      if chunks.len() > 0 || voxels.len() > 0
+      { upload(mesh); }
+    }
+  }
+
+  pub fn render(&mut self)
+  {
+    let camera = gl::Camera::get_active();
+
+    self.shader.bind();
+    self.shader.update_uniform_mat(self.proj_loc, &camera.projection);
+
+    check!(gl2::bind_vertex_array(self.vao));
+    check!(gl2::enable_vertex_attrib_array(0));
+    check!(gl2::enable_vertex_attrib_array(1));
+    for mesh in self.meshes.iter()
+    {
+      let transform = mesh.prop.get_transform();
+      if mesh.count > 0
+      {
+        self.shader.update_uniform_mat(self.world_loc, &(transform * camera.view));
+        check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, mesh.vbo));
+        bind_attribs();
+        check!(gl2::draw_arrays(gl2::TRIANGLES, 0, mesh.count));
+      }
+
+      let map = &mesh.prop.map;
+      check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, self.debris_vbo));
+      for debris in map.debris.iter()
+      {
+        let offset = math::Mat4x4::new_translation(debris.offset.x, debris.offset.y, debris.offset.z);
+        self.shader.update_uniform_mat(self.world_loc, &(offset * transform * camera.view));
+
+        let verts = debris_cubes(map, debris);
+        check!(gl2::buffer_data(gl2::ARRAY_BUFFER, verts, gl2::STREAM_DRAW));
+        bind_attribs();
+        check!(gl2::draw_arrays(gl2::TRIANGLES, 0, verts.len() as i32));
+      }
+    }
+    check!(gl2::disable_vertex_attrib_array(0));
+    check!(gl2::disable_vertex_attrib_array(1));
+    check!(gl2::bind_vertex_array(0));
+    check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, 0));
+  }
+}
+
+fn upload(mesh: &mut Prop_Mesh)
+{
+  let map = &mesh.prop.map;
+  let verts = voxel::mesher::mesh_map(map.states, map.voxels, map.occlusion,
+                                      map.dimensions, map.voxel_size);
+  mesh.count = verts.len() as i32;
+
+  check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, mesh.vbo));
+  check!(gl2::buffer_data(gl2::ARRAY_BUFFER, verts, gl2::DYNAMIC_DRAW));
+  check!(gl2::bind_buffer(gl2::ARRAY_BUFFER, 0));
+}
+
+fn bind_attribs()
+{
+  check!(gl2::vertex_attrib_pointer_f32(0, 3, false, sys::size_of::<voxel::Vertex>() as i32, 0));
+  check!(gl2::vertex_attrib_pointer_f32(1, 3, false, sys::size_of::<voxel::Vertex>() as i32,
+                                         sys::size_of::<math::Vec3f>() as u32));
+}
+
+/* Every face of every cube in a debris body, in object space. */
+fn debris_cubes(map: &voxel::Map, debris: &voxel::Debris) -> ~[voxel::Vertex]
+{
+  let half = map.voxel_size / 2.0;
+  let mut verts = vec::with_capacity(debris.voxels.len() * 36);
+  for index in debris.voxels.iter()
+  {
+    let vox = &map.voxels[*index];
+    let center = vox.position * map.voxel_size;
+    let corner = |&(x, y, z): &(f32, f32, f32)| -> voxel::Vertex
+    { voxel::Vertex::new(center + math::Vec3f::new(x * half, y * half, z * half), vox.color) };
+
+    /* Counter-clockwise from outside, two triangles per face. */
+    for &(ref a, ref b, ref c, ref d) in Cube_Faces.iter()
+    {
+      let quad = [ corner(a), corner(b), corner(c), corner(d) ];
+      verts.push_all([ quad[0], quad[1], quad[2], quad[0], quad[2], quad[3] ]);
+    }
+  }
+  verts
+}
@@ -0,0 +1,225 @@
+/*
+    Copyright 2013 Jesse 'Jeaye' Wilkerson
+    See licensing in LICENSE file, or at:
+        http://www.opensource.org/licenses/BSD-3-Clause
+
+    File: state/loading/loading.rs
+    Author: Jesse 'Jeaye' Wilkerson
+    Description:
+      A client-only state that loads a map
+      in the background and renders the
+      progress. Once the map is loaded, the
+      game states replace this one.
+*/
+
+use std::{ comm, task, cell };
+use gl;
+use ui;
+use math;
+use util;
+use res;
+use BSP_Map = bsp::Map;
+use Voxel_Map = voxel::Map;
+use voxel;
+use super::{ State, Director, Console, Game, Game_Renderer };
+use super::game::{ Load_Event, Parsing, Voxelizing, Loaded, Failed };
+use util::Log;
+
+#[macro_escape]
+#[path = "../../util/log_macros.rs"]
+mod log_macros;
+
+/* Width of the progress bar, in characters. */
+static Bar_Width: uint = 40;
+
+static Font_File: &'static str = "data/fonts/test.ttf";
+static Font_Size: i32 = 30;
+
+pub struct Loading
+{
+  map_name: ~str,
+
+  /* Communication with the loading task. */
+  events: comm::Port<Load_Event>,
+  cancel: comm::Chan<()>,
+  finished: bool,
+
+  /* Loaded maps and their navigation grid, waiting to be uploaded. */
+  maps: Option<(BSP_Map, Voxel_Map, voxel::Nav_Grid)>,
+
+  status: ~str,
+  progress: f32, /* 0.0 to 1.0 */
+  font: @ui::Font,
+}
+
+impl Loading
+{
+  pub fn new(map_name: &str, voxel_size: f32) -> @mut Loading
+  {
+    let (events_port, events_chan) = comm::stream();
+    let events_chan = comm::SharedChan::new(events_chan);
+    let (cancel_port, cancel_chan) = comm::stream();
+
+    /* Parsing and voxelizing happen in the background. The task is
+     * unlinked so that a bad map doesn't take the game down with it. */
+    let name = map_name.to_owned();
+    do task::spawn_unlinked
+    {
+      util::Log::initialize(); /* Done for every task. */
+      Game::load(name, voxel_size, events_chan, cancel_port);
+    }
+
+    let loading = @mut Loading
+    {
+      map_name: map_name.to_owned(),
+
+      events: events_port,
+      cancel: cancel_chan,
+      finished: false,
+
+      maps: None,
+
+      status: fmt!("Loading %s", map_name),
+      progress: 0.0,
+      font: res::Manager::get().get_font(Font_File, Font_Size),
+    };
+
+    loading
+  }
+
+  /* Replaces any existing game with the newly loaded one. This
+   * is deferred, since it modifies the director's states. */
+  fn finish(&mut self, maps: (BSP_Map, Voxel_Map, voxel::Nav_Grid))
+  {
+    self.finished = true;
+
+    let maps = cell::Cell::new(maps);
+    let map_name = self.map_name.clone();
+    do Director::push_deferred
+    {
+      let (bmap, vmap, nav) = maps.take();
+      let game_state = Game::new(bmap, vmap, nav);
+      let game_renderer_state = Game_Renderer::new(game_state);
+
+      do Director::get_mut |director|
+      {
+        /* Remove any existing game states. */
+        do director.remove_if |state|
+        { state.get_key() == (game_state as @mut State).get_key() ||
+          state.get_key() == (game_renderer_state as @mut State).get_key() }
+
+        director.unshift(game_state as @mut State);
+        director.unshift(game_renderer_state as @mut State);
+        director.pull("loading");
+      }
+      gl::Camera::get_active().reset(); /* Jump back to the origin. */
+
+      Console::get().add_log(~"Loaded map: \\5" + map_name + "\\1");
+    }
+  }
+
+  fn fail(&mut self, err: ~str)
+  {
+    self.finished = true;
+
+    Console::get().add_error_log(err);
+    do Director::push_deferred
+    {
+      do Director::get_mut |director|
+      { director.pull("loading"); }
+    }
+  }
+}
+
+impl State for Loading
+{
+  fn load(&mut self)
+  { log_debug!("Loading loading state for %s", self.map_name); }
+
+  fn unload(&mut self)
+  {
+    log_debug!("Unloading loading state");
+    res::Manager::get().release_font(Font_File, Font_Size);
+
+    /* Being pulled before finishing means the load was cancelled. */
+    if !self.finished
+    {
+      log_info!("Cancelling load of %s", self.map_name);
+      self.cancel.try_send(());
+    }
+  }
+
+  fn get_key(&self) -> &str
+  { &"loading" }
+
+  fn update(&mut self, _delta: f32) -> bool /* dt is in terms of seconds. */
+  {
+    if self.finished
+    { return false; }
+
+    /* The maps were received last frame, so the upload
+     * status has been shown; do the upload now. */
+    if self.maps.is_some()
+    {
+      let maps = self.maps.take_unwrap();
+      self.finish(maps);
+      return false;
+    }
+
+    /* Peeking is also true once the loading task has gone away,
+     * in which case there's nothing to receive. */
+    while self.events.peek()
+    {
+      let event = match self.events.try_recv()
+      {
+        Some(event) => { event },
+        None => { self.fail(~"Loading stopped without a result"); break; }
+      };
+      match event
+      {
+        Parsing(done) =>
+        {
+          self.status = fmt!("Parsing %s", self.map_name);
+          self.progress = done * 0.1;
+        }
+        Voxelizing(done, total) =>
+        {
+          self.status = fmt!("Voxelizing %u of %u triangles", done, total);
+          self.progress = 0.1 + ((done as f32 / total as f32) * 0.8);
+        }
+        Loaded(bmap, vmap, nav) =>
+        {
+          self.status = ~"Uploading";
+          self.progress = 0.9;
+          self.maps = Some((bmap, vmap, nav));
+          break;
+        }
+        Failed(err) =>
+        {
+          self.fail(err);
+          break;
+        }
+      }
+    }
+
+    false
+  }
+
+  fn render(&mut self) -> bool
+  {
+    let filled = ((Bar_Width as f32) * self.progress) as uint;
+    let bar = fmt!("[%s%s] %d%%", "#".repeat(filled), "-".repeat(Bar_Width - filled),
+                                  (self.progress * 100.0) as int);
+
+    let ui_renderer = ui::Renderer::get();
+    let (width, height) = ui_renderer.window.get_size();
+    let center = math::Vec2f::new(width as f32 / 4.0, height as f32 / 2.0);
+
+    ui_renderer.begin();
+    ui_renderer.render_font(self.status, center, &*self.font);
+    ui_renderer.render_font(bar, math::Vec2f::new(center.x, center.y + 40.0), &*self.font);
+    ui_renderer.end();
+
+    false
+  }
+}
@@ -18,6 +18,7 @@ pub use self::game_renderer::Game_Renderer;
 pub use self::bsp_renderer::BSP_Renderer;
 pub use self::console::Console;
 pub use self::console_renderer::Console_Renderer;
+pub use self::loading::Loading;
 
 mod director;
 
@@ -31,4 +32,6 @@ mod bsp_renderer;
 mod console;
 #[path = "console/console_renderer.rs"]
 mod console_renderer;
+#[path = "loading/loading.rs"]
+mod loading;
 
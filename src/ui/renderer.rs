@@ -12,6 +12,7 @@
 use std::local_data;
 use glfw;
 use gl;
+use res;
 use math;
 use TTF_Renderer = super::ttf::Renderer;
 use TTF_Font = super::ttf::Font;
@@ -55,7 +56,7 @@ impl Renderer
       vao: 0,
       vbo: 0,
 
-      shader: gl::Shader_Builder::new_with_files("data/shaders/ui.vert", "data/shaders/ui.frag"),
+      shader: res::Manager::get().get_shader("data/shaders/ui.vert", "data/shaders/ui.frag"),
       world: math::Mat4x4::new(),
       tex_world: math::Mat4x4::new(),
 
@@ -162,6 +162,10 @@ impl Font
 
     font
   }
+
+  /* Bytes used by the atlas, which is RGB. */
+  pub fn memory_usage(&self) -> uint
+  { (self.atlas_dimensions.x * self.atlas_dimensions.y * 3) as uint }
 }
 
 impl Drop for Font
@@ -169,6 +173,7 @@ impl Drop for Font
   #[fixed_stack_segment]
   fn drop(&self)
   {
+    check!(gl2::delete_textures(&[self.texture_atlas]));
     unsafe
     { ft::FT_Done_FreeType(self.library); }
   }
@@ -11,6 +11,7 @@
 
 use std::{ u32, vec, sys };
 use gl;
+use res;
 use super::Font;
 use math;
 use gl2 = opengles::gl2;
@@ -34,7 +35,7 @@ impl Renderer
     {
         vao: 0,
         vbo: 0,
-        shader: gl::Shader_Builder::new_with_files("data/shaders/text.vert", "data/shaders/text.frag"),
+        shader: res::Manager::get().get_shader("data/shaders/text.vert", "data/shaders/text.frag"),
         proj_loc: 0,
     };
     renderer.proj_loc = renderer.shader.get_uniform_location("proj");